@@ -0,0 +1,223 @@
+//! Cold-open cost: a fresh descriptor per iteration over an image already in
+//! memory, asked for as little as an indexer needs (kind, header, build-id)
+//! and, for contrast, for every section header.
+//!
+//! Runs over a synthetic file with a large section table and a large blob,
+//! or over the files given on the command line:
+//!
+//!     cargo bench --bench cold_open [-- FILE...]
+
+use libelf_rs::*;
+use object::Endianness;
+use object::elf::{EM_X86_64, ET_EXEC, NT_GNU_BUILD_ID, SHT_PROGBITS};
+use object::write::elf::{FileHeader, ProgramHeader, SectionHeader, Writer};
+use std::hint::black_box;
+use std::ptr;
+use std::time::{Duration, Instant};
+
+const ITERATIONS: usize = 200;
+
+/// An ELF64 executable with a `PT_NOTE` build-id, `sections` small code
+/// sections and a `blob`-byte debug section.
+fn synthetic(sections: usize, blob: usize) -> Vec<u8> {
+    let names: Vec<Vec<u8>> = (0..sections)
+        .map(|i| format!(".text.f{i}").into_bytes())
+        .collect();
+    let mut note = Vec::new();
+    note.extend_from_slice(&4u32.to_le_bytes());
+    note.extend_from_slice(&20u32.to_le_bytes());
+    note.extend_from_slice(&NT_GNU_BUILD_ID.to_le_bytes());
+    note.extend_from_slice(b"GNU\0");
+    note.extend((0..20).map(|i| i as u8));
+
+    let mut out = Vec::new();
+    let mut w = Writer::new(Endianness::Little, true, &mut out);
+    w.reserve_file_header();
+    w.reserve_program_headers(1);
+    w.reserve_null_section_index();
+    let note_name = w.add_section_name(b".note.gnu.build-id");
+    w.reserve_section_index();
+    let blob_name = w.add_section_name(b".debug_info");
+    w.reserve_section_index();
+    let small_names: Vec<_> = names
+        .iter()
+        .map(|name| {
+            let id = w.add_section_name(name);
+            w.reserve_section_index();
+            id
+        })
+        .collect();
+    w.reserve_shstrtab_section_index();
+    let note_offset = w.reserve(note.len(), 4);
+    let blob_offset = w.reserve(blob, 1);
+    let small_offsets: Vec<_> = names.iter().map(|_| w.reserve(16, 16)).collect();
+    w.reserve_shstrtab();
+    w.reserve_section_headers();
+
+    w.write_file_header(&FileHeader {
+        os_abi: 0,
+        abi_version: 0,
+        e_type: ET_EXEC,
+        e_machine: EM_X86_64,
+        e_entry: 0,
+        e_flags: 0,
+    })
+    .unwrap();
+    w.write_align_program_headers();
+    w.write_program_header(&ProgramHeader {
+        p_type: PT_NOTE,
+        p_flags: PF_R,
+        p_offset: note_offset as u64,
+        p_vaddr: 0,
+        p_paddr: 0,
+        p_filesz: note.len() as u64,
+        p_memsz: note.len() as u64,
+        p_align: 4,
+    });
+    w.write_align(4);
+    w.write(&note);
+    w.write(&vec![0xcc; blob]);
+    for _ in &names {
+        w.write_align(16);
+        w.write(&[0x90; 16]);
+    }
+    w.write_shstrtab();
+
+    let header = |name, sh_type, sh_flags: u32, offset: usize, size: usize, align| SectionHeader {
+        name: Some(name),
+        sh_type,
+        sh_flags: sh_flags.into(),
+        sh_addr: 0,
+        sh_offset: offset as u64,
+        sh_size: size as u64,
+        sh_link: 0,
+        sh_info: 0,
+        sh_addralign: align,
+        sh_entsize: 0,
+    };
+    w.write_null_section_header();
+    w.write_section_header(&header(
+        note_name,
+        SHT_NOTE,
+        SHF_ALLOC,
+        note_offset,
+        note.len(),
+        4,
+    ));
+    w.write_section_header(&header(blob_name, SHT_PROGBITS, 0, blob_offset, blob, 1));
+    for (name, offset) in small_names.into_iter().zip(small_offsets) {
+        w.write_section_header(&header(
+            name,
+            SHT_PROGBITS,
+            SHF_ALLOC | SHF_EXECINSTR,
+            offset,
+            16,
+            16,
+        ));
+    }
+    w.write_shstrtab_section_header();
+    out
+}
+
+/// The build-id from the `PT_NOTE` segments, without the section table.
+fn build_id(elf: *mut Elf) -> Option<Vec<u8>> {
+    let mut phnum = 0;
+    if elf_getphdrnum(elf, &mut phnum) != 0 {
+        return None;
+    }
+    let mut phdr: GElf_Phdr = unsafe { std::mem::zeroed() };
+    for index in 0..phnum {
+        gelf_getphdr(elf, index as i32, &mut phdr);
+        if phdr.p_type.get(NativeEndian) != PT_NOTE {
+            continue;
+        }
+        let offset = phdr.p_offset.get(NativeEndian) as i64;
+        let size = phdr.p_filesz.get(NativeEndian) as usize;
+        let data = elf_getdata_rawchunk(elf, offset, size, ELF_T_NHDR);
+        if data.is_null() {
+            continue;
+        }
+        let mut nhdr: GElf_Nhdr = unsafe { std::mem::zeroed() };
+        let (mut name, mut desc) = (0, 0);
+        let mut next = 0;
+        loop {
+            let at = next;
+            next = gelf_getnote(data, at, &mut nhdr, &mut name, &mut desc);
+            if next == 0 {
+                break;
+            }
+            if nhdr.n_type.get(NativeEndian) == NT_GNU_BUILD_ID {
+                let buf = unsafe { (*data).d_buf as *const u8 };
+                let len = nhdr.n_descsz.get(NativeEndian) as usize;
+                return Some(unsafe { std::slice::from_raw_parts(buf.add(desc), len) }.to_vec());
+            }
+        }
+    }
+    None
+}
+
+fn measure(name: &str, mut f: impl FnMut()) {
+    let mut samples: Vec<Duration> = (0..ITERATIONS)
+        .map(|_| {
+            let start = Instant::now();
+            f();
+            start.elapsed()
+        })
+        .collect();
+    samples.sort();
+    println!(
+        "  {name:<28} median {:>12.2?}   min {:>12.2?}",
+        samples[samples.len() / 2],
+        samples[0]
+    );
+}
+
+fn run(label: &str, mut image: Vec<u8>) {
+    println!("{label} ({} bytes)", image.len());
+    let (ptr, len) = (image.as_mut_ptr(), image.len());
+    let open = || elf_memory(ptr as *mut i8, len);
+
+    measure("elf_kind + gelf_getehdr", || {
+        let elf = open();
+        black_box(elf_kind(elf));
+        let mut ehdr: GElf_Ehdr = unsafe { std::mem::zeroed() };
+        black_box(gelf_getehdr(elf, &mut ehdr));
+        elf_end(elf);
+    });
+    measure("build-id from PT_NOTE", || {
+        let elf = open();
+        black_box(build_id(elf));
+        elf_end(elf);
+    });
+    measure("every section header", || {
+        let elf = open();
+        let mut shdr: GElf_Shdr = unsafe { std::mem::zeroed() };
+        let mut scn = elf_nextscn(elf, ptr::null_mut());
+        while !scn.is_null() {
+            black_box(gelf_getshdr(scn, &mut shdr));
+            scn = elf_nextscn(elf, scn);
+        }
+        elf_end(elf);
+    });
+}
+
+fn main() {
+    elf_version(EV_CURRENT.into());
+    // Cargo passes `--bench` through to harness-less benches
+    let files: Vec<_> = std::env::args()
+        .skip(1)
+        .filter(|arg| !arg.starts_with("--"))
+        .collect();
+    if files.is_empty() {
+        run(
+            "synthetic: 20000 sections, 64 MiB",
+            synthetic(20_000, 64 << 20),
+        );
+    }
+    for path in files {
+        match std::fs::read(&path) {
+            Ok(image) => run(&path, image),
+            Err(e) => eprintln!("{path}: {e}"),
+        }
+    }
+}
@@ -0,0 +1,20 @@
+#![no_main]
+
+use libelf_rs::{Elf_Limits, elf_setlimits};
+use libfuzzer_sys::fuzz_target;
+use std::sync::Once;
+
+static LIMITS: Once = Once::new();
+
+fuzz_target!(|data: &[u8]| {
+    // What a service parsing uploaded binaries would configure
+    LIMITS.call_once(|| {
+        let limits = Elf_Limits {
+            max_sections: 1 << 16,
+            max_decompressed_size: 64 << 20,
+            max_notes: 1 << 12,
+        };
+        elf_setlimits(&limits);
+    });
+    libelf_rs::fuzz::exercise(data);
+});
@@ -20,6 +20,8 @@ typedef uint32_t Elf32_Off;
 typedef int32_t  Elf32_Sword;
 typedef uint32_t Elf32_Word;
 
+typedef Elf32_Word Elf32_Relr;
+
 typedef struct {
     unsigned char e_ident[16];
     Elf32_Half    e_type;
@@ -167,6 +169,62 @@ typedef struct {
     Elf64_Word n_type;
 } Elf64_Nhdr;
 
+typedef struct {
+    uint32_t a_type;
+    union {
+        uint32_t a_val;
+    } a_un;
+} Elf32_auxv_t;
+
+typedef struct {
+    uint64_t a_type;
+    union {
+        uint64_t a_val;
+    } a_un;
+} Elf64_auxv_t;
+
+typedef struct {
+    Elf32_Half si_boundto;
+    Elf32_Half si_flags;
+} Elf32_Syminfo;
+
+typedef struct {
+    Elf64_Half si_boundto;
+    Elf64_Half si_flags;
+} Elf64_Syminfo;
+
+typedef struct {
+    uint64_t   m_value;
+    Elf32_Word m_info;
+    Elf32_Word m_poffset;
+    Elf32_Half m_repeat;
+    Elf32_Half m_stride;
+} Elf32_Move;
+
+typedef struct {
+    Elf64_Xword m_value;
+    Elf64_Xword m_info;
+    Elf64_Xword m_poffset;
+    Elf64_Half  m_repeat;
+    Elf64_Half  m_stride;
+} Elf64_Move;
+
+typedef struct {
+    Elf32_Word l_name;
+    Elf32_Word l_time_stamp;
+    Elf32_Word l_checksum;
+    Elf32_Word l_version;
+    Elf32_Word l_flags;
+} Elf32_Lib;
+
+typedef struct {
+    Elf64_Word l_name;
+    Elf64_Word l_time_stamp;
+    Elf64_Word l_checksum;
+    Elf64_Word l_version;
+    Elf64_Word l_flags;
+} Elf64_Lib;
+
 typedef Elf64_Half Elf64_Versym;
 
 typedef struct {
@@ -232,6 +290,7 @@ typedef struct {
 #define SHT_REL           9
 #define SHT_SHLIB         10
 #define SHT_DYNSYM        11
+#define SHT_RELR          19
 #define SHT_LOOS          0x60000000
 #define SHT_HIOS          0x6fffffff
 #define SHT_LOPROC        0x70000000
@@ -305,10 +364,48 @@ typedef struct {
 #define PT_PHDR    6
 #define PT_TLS     7
 
+#define DT_NULL    0
+#define DT_HASH    4
+#define DT_STRTAB  5
+#define DT_SYMTAB  6
+#define DT_STRSZ   10
+#define DT_SYMENT  11
+#define DT_RELRSZ  35
+#define DT_RELR    36
+#define DT_RELRENT 37
+#define DT_GNU_HASH    0x6ffffef5
+#define DT_VERSYM      0x6ffffff0
+#define DT_VERDEF      0x6ffffffc
+#define DT_VERDEFNUM   0x6ffffffd
+#define DT_VERNEED     0x6ffffffe
+#define DT_VERNEEDNUM  0x6fffffff
+
 #define PF_X  (1 << 0)
 #define PF_W  (1 << 1)
 #define PF_R  (1 << 2)
 
+#define NT_PRSTATUS  1
+#define NT_PRPSINFO  3
+#define NT_AUXV      6
+#define NT_SIGINFO   0x53494749
+#define NT_FILE      0x46494c45
+
+#define AT_NULL         0
+#define AT_IGNORE       1
+#define AT_EXECFD       2
+#define AT_PHDR         3
+#define AT_PHENT        4
+#define AT_PHNUM        5
+#define AT_PAGESZ       6
+#define AT_BASE         7
+#define AT_FLAGS        8
+#define AT_ENTRY        9
+#define AT_UID          11
+#define AT_HWCAP        16
+#define AT_RANDOM       25
+#define AT_EXECFN       31
+#define AT_SYSINFO_EHDR 33
+
 #define VER_DEF_CURRENT   1
 #define VER_NEED_CURRENT  1
 
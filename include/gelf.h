@@ -5,6 +5,7 @@
 #include "elf.h"
 #include "libelf.h"
 
+typedef Elf64_Addr GElf_Addr;
 typedef Elf64_Shdr GElf_Shdr;
 typedef Elf64_Sym GElf_Sym;
 typedef Elf64_Rel GElf_Rel;
@@ -15,14 +16,22 @@ typedef Elf64_Versym GElf_Versym;
 typedef Elf64_Verdef GElf_Verdef;
 typedef Elf64_Verdaux GElf_Verdaux;
 typedef Elf64_Relr GElf_Relr;
+typedef Elf64_auxv_t GElf_auxv_t;
+typedef Elf64_Syminfo GElf_Syminfo;
+typedef Elf64_Move GElf_Move;
+typedef Elf64_Lib GElf_Lib;
 
 int gelf_getclass(Elf *elf);
 
 GElf_Ehdr *gelf_getehdr(Elf *elf, GElf_Ehdr *dst);
+void *gelf_newehdr(Elf *elf, int class);
 GElf_Shdr *gelf_getshdr(Elf_Scn *scn, GElf_Shdr *dst);
+int gelf_update_shdr(Elf_Scn *scn, GElf_Shdr *src);
 GElf_Phdr *gelf_getphdr(Elf *elf, int index, GElf_Phdr *dst);
 
 GElf_Sym *gelf_getsym(Elf_Data *data, int ndx, GElf_Sym *dst);
+GElf_Sym *gelf_lookup_dynsym(Elf *elf, const char *name, const char *version,
+                             GElf_Sym *dst, size_t *ndx);
 GElf_Versym *gelf_getversym(Elf_Data *data, int ndx, GElf_Versym *dst);
 GElf_Verdef *gelf_getverdef(Elf_Data *data, int offset, GElf_Verdef *dst);
 GElf_Verdaux *gelf_getverdaux(Elf_Data *data, int offset, GElf_Verdaux *dst);
@@ -30,6 +39,20 @@ GElf_Verdaux *gelf_getverdaux(Elf_Data *data, int offset, GElf_Verdaux *dst);
 size_t gelf_getnote(Elf_Data *data, size_t offset, GElf_Nhdr *nhdr,
                     size_t *name_offset, size_t *desc_offset);
 
+GElf_auxv_t *gelf_getauxv(Elf_Data *data, int ndx, GElf_auxv_t *dst);
+int gelf_update_auxv(Elf_Data *data, int ndx, GElf_auxv_t *src);
+
+GElf_Syminfo *gelf_getsyminfo(Elf_Data *data, int ndx, GElf_Syminfo *dst);
+int gelf_update_syminfo(Elf_Data *data, int ndx, GElf_Syminfo *src);
+GElf_Move *gelf_getmove(Elf_Data *data, int ndx, GElf_Move *dst);
+int gelf_update_move(Elf_Data *data, int ndx, GElf_Move *src);
+GElf_Lib *gelf_getlib(Elf_Data *data, int ndx, GElf_Lib *dst);
+int gelf_update_lib(Elf_Data *data, int ndx, GElf_Lib *src);
+
+GElf_Relr *gelf_getrelr(Elf_Data *data, int ndx, GElf_Relr *dst);
+int gelf_update_relr(Elf_Data *data, int ndx, GElf_Relr *src);
+size_t gelf_relr_addrs(Elf_Data *data, GElf_Addr *dst, size_t n);
+
 unsigned char GELF_ST_BIND(unsigned char info);
 unsigned char GELF_ST_TYPE(unsigned char info);
 unsigned char GELF_ST_INFO(unsigned char bind, unsigned char type);
@@ -66,7 +66,8 @@ typedef enum {
     ELF_T_AUXV = 24,
     ELF_T_CHDR = 25,
     ELF_T_NHDR8 = 26,
-    ELF_T_NUM = 27
+    ELF_T_RELR = 27,
+    ELF_T_NUM = 28
 } Elf_Type;
 
 typedef struct {
@@ -78,10 +79,73 @@ typedef struct {
     size_t d_align;
 } Elf_Data;
 
+/* Process-wide resource caps for untrusted input; not part of libelf.
+   Anything over a limit fails with ELF_E_NOMEM. Defaults are SIZE_MAX. */
+typedef struct {
+    size_t max_sections;
+    size_t max_decompressed_size;
+    size_t max_notes;
+} Elf_Limits;
+
 #define ELF_F_DIRTY 0x1
 #define ELF_F_LAYOUT 0x4
 #define ELF_F_PERMISSIVE 0x8
 
+typedef enum {
+    ELF_E_NOERROR = 0,
+    ELF_E_UNKNOWN_ERROR = 1,
+    ELF_E_UNKNOWN_VERSION = 2,
+    ELF_E_UNKNOWN_TYPE = 3,
+    ELF_E_INVALID_HANDLE = 4,
+    ELF_E_SOURCE_SIZE = 5,
+    ELF_E_DEST_SIZE = 6,
+    ELF_E_INVALID_ENCODING = 7,
+    ELF_E_NOMEM = 8,
+    ELF_E_INVALID_FILE = 9,
+    ELF_E_INVALID_ELF = 10,
+    ELF_E_INVALID_OP = 11,
+    ELF_E_NO_VERSION = 12,
+    ELF_E_INVALID_CMD = 13,
+    ELF_E_RANGE = 14,
+    ELF_E_ARCHIVE_FMAG = 15,
+    ELF_E_INVALID_ARCHIVE = 16,
+    ELF_E_NO_ARCHIVE = 17,
+    ELF_E_NO_INDEX = 18,
+    ELF_E_READ_ERROR = 19,
+    ELF_E_WRITE_ERROR = 20,
+    ELF_E_INVALID_CLASS = 21,
+    ELF_E_INVALID_INDEX = 22,
+    ELF_E_INVALID_OPERAND = 23,
+    ELF_E_INVALID_SECTION = 24,
+    ELF_E_INVALID_COMMAND = 25,
+    ELF_E_WRONG_ORDER_EHDR = 26,
+    ELF_E_FD_DISABLED = 27,
+    ELF_E_FD_MISMATCH = 28,
+    ELF_E_OFFSET_RANGE = 29,
+    ELF_E_NOT_NUL_SECTION = 30,
+    ELF_E_DATA_MISMATCH = 31,
+    ELF_E_INVALID_SECTION_HEADER = 32,
+    ELF_E_INVALID_DATA = 33,
+    ELF_E_DATA_ENCODING = 34,
+    ELF_E_SECTION_TOO_SMALL = 35,
+    ELF_E_INVALID_ALIGN = 36,
+    ELF_E_INVALID_SHENTSIZE = 37,
+    ELF_E_UPDATE_RO = 38,
+    ELF_E_NOFILE = 39,
+    ELF_E_GROUP_NOT_REL = 40,
+    ELF_E_INVALID_PHDR = 41,
+    ELF_E_NO_PHDR = 42,
+    ELF_E_INVALID_OFFSET = 43,
+    ELF_E_INVALID_SECTION_TYPE = 44,
+    ELF_E_INVALID_SECTION_FLAGS = 45,
+    ELF_E_NOT_COMPRESSED = 46,
+    ELF_E_ALREADY_COMPRESSED = 47,
+    ELF_E_UNKNOWN_COMPRESSION_TYPE = 48,
+    ELF_E_COMPRESS_ERROR = 49,
+    ELF_E_DECOMPRESS_ERROR = 50,
+    ELF_E_NUM = 51
+} Elf_Error;
+
 #define EV_NONE 0
 #define EV_CURRENT 1
 
@@ -90,12 +154,19 @@ unsigned int elf_version(unsigned int ver);
 Elf *elf_begin(int fd, Elf_Cmd cmd, Elf *ref_elf);
 Elf *elf_memory(char *image, size_t size);
 int elf_end(Elf *elf);
+int elf_cntl(Elf *elf, Elf_Cmd cmd);
+Elf *elf_clone(Elf *elf, Elf_Cmd cmd);
 
 Elf_Kind elf_kind(Elf *elf);
+char *elf_getident(Elf *elf, size_t *nbytes);
+int64_t elf_getbase(Elf *elf);
+char *elf_rawfile(Elf *elf, size_t *nbytes);
 
 Elf_Scn *elf_nextscn(Elf *elf, Elf_Scn *scn);
 Elf_Scn *elf_getscn(Elf *elf, size_t index);
 size_t elf_ndxscn(Elf_Scn *scn);
+Elf_Scn *elf32_offscn(Elf *elf, Elf32_Off offset);
+Elf_Scn *elf64_offscn(Elf *elf, Elf64_Off offset);
 
 int elf_getshdrstrndx(Elf *elf, size_t *dst);
 int elf_getphdrnum(Elf *elf, size_t *dst);
@@ -103,20 +174,36 @@ int elf_getshdrnum(Elf *elf, size_t *dst);
 
 Elf_Data *elf_getdata(Elf_Scn *scn, Elf_Data *data);
 Elf_Data *elf_rawdata(Elf_Scn *scn, Elf_Data *data);
+Elf_Data *elf_getdata_rawchunk(Elf *elf, int64_t offset, size_t size, Elf_Type type);
+Elf_Data *elf_getdata_dynamic(Elf *elf, int64_t tag);
 
 char *elf_strptr(Elf *elf, size_t section, size_t offset);
 
+unsigned int elf_flagelf(Elf *elf, Elf_Cmd cmd, unsigned int flags);
+unsigned int elf_flagehdr(Elf *elf, Elf_Cmd cmd, unsigned int flags);
+unsigned int elf_flagphdr(Elf *elf, Elf_Cmd cmd, unsigned int flags);
+unsigned int elf_flagscn(Elf_Scn *scn, Elf_Cmd cmd, unsigned int flags);
 unsigned int elf_flagdata(Elf_Data *data, Elf_Cmd cmd, unsigned int flags);
 unsigned int elf_flagshdr(Elf_Scn *scn, Elf_Cmd cmd, unsigned int flags);
 
 Elf_Scn *elf_newscn(Elf *elf);
 Elf_Data *elf_newdata(Elf_Scn *scn);
 int64_t elf_update(Elf *elf, Elf_Cmd cmd);
+void elf_fill(int fill);
 
+Elf32_Ehdr *elf32_getehdr(Elf *elf);
 Elf64_Ehdr *elf64_getehdr(Elf *elf);
+Elf32_Shdr *elf32_getshdr(Elf_Scn *scn);
 Elf64_Shdr *elf64_getshdr(Elf_Scn *scn);
+Elf32_Ehdr *elf32_newehdr(Elf *elf);
 Elf64_Ehdr *elf64_newehdr(Elf *elf);
 
+unsigned long int elf_hash(const char *string);
+unsigned long int elf_gnu_hash(const char *string);
+
+int elf_getlimits(Elf_Limits *dst);
+int elf_setlimits(const Elf_Limits *src);
+
 int elf_errno(void);
 const char *elf_errmsg(int error);
 
@@ -0,0 +1,78 @@
+//! Fields read and written in an explicit byte order, for records whose
+//! layout depends on the class and byte order of the file.
+
+use crate::error::*;
+use object::Endianness;
+
+pub fn read_u16(buf: &[u8], pos: usize, encoding: Endianness) -> Option<u16> {
+    let bytes: [u8; 2] = buf.get(pos..pos.checked_add(2)?)?.try_into().ok()?;
+    Some(match encoding {
+        Endianness::Little => u16::from_le_bytes(bytes),
+        Endianness::Big => u16::from_be_bytes(bytes),
+    })
+}
+
+pub fn read_u32(buf: &[u8], pos: usize, encoding: Endianness) -> Option<u32> {
+    let bytes: [u8; 4] = buf.get(pos..pos.checked_add(4)?)?.try_into().ok()?;
+    Some(match encoding {
+        Endianness::Little => u32::from_le_bytes(bytes),
+        Endianness::Big => u32::from_be_bytes(bytes),
+    })
+}
+
+pub fn read_u64(buf: &[u8], pos: usize, encoding: Endianness) -> Option<u64> {
+    let bytes: [u8; 8] = buf.get(pos..pos.checked_add(8)?)?.try_into().ok()?;
+    Some(match encoding {
+        Endianness::Little => u64::from_le_bytes(bytes),
+        Endianness::Big => u64::from_be_bytes(bytes),
+    })
+}
+
+pub fn write_u16(buf: &mut [u8], value: u16, encoding: Endianness) {
+    let bytes = match encoding {
+        Endianness::Little => value.to_le_bytes(),
+        Endianness::Big => value.to_be_bytes(),
+    };
+    buf[..2].copy_from_slice(&bytes);
+}
+
+pub fn write_u32(buf: &mut [u8], value: u32, encoding: Endianness) {
+    let bytes = match encoding {
+        Endianness::Little => value.to_le_bytes(),
+        Endianness::Big => value.to_be_bytes(),
+    };
+    buf[..4].copy_from_slice(&bytes);
+}
+
+pub fn write_u64(buf: &mut [u8], value: u64, encoding: Endianness) {
+    let bytes = match encoding {
+        Endianness::Little => value.to_le_bytes(),
+        Endianness::Big => value.to_be_bytes(),
+    };
+    buf[..8].copy_from_slice(&bytes);
+}
+
+/// Reads a class-sized word (`Elf32_Addr`/`Elf64_Addr` and friends).
+pub fn read_word(buf: &[u8], pos: usize, elf64: bool, encoding: Endianness) -> Option<u64> {
+    if elf64 {
+        read_u64(buf, pos, encoding)
+    } else {
+        read_u32(buf, pos, encoding).map(u64::from)
+    }
+}
+
+/// Writes a class-sized word, failing if `value` does not fit in ELF32.
+pub fn write_word(
+    buf: &mut [u8],
+    value: u64,
+    elf64: bool,
+    encoding: Endianness,
+) -> Result<(), ElfError> {
+    if elf64 {
+        write_u64(buf, value, encoding);
+    } else {
+        let value = u32::try_from(value).map_err(|_| ELF_E_INVALID_DATA)?;
+        write_u32(buf, value, encoding);
+    }
+    Ok(())
+}
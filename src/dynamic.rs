@@ -0,0 +1,264 @@
+//! Tables reached through `PT_DYNAMIC`, for files without section headers.
+//!
+//! The dynamic entries hold virtual addresses, which the `PT_LOAD` segments
+//! map back to file offsets. Most tables carry no size of their own: the
+//! symbol count comes from `DT_HASH`'s `nchain`, or from walking the last
+//! `DT_GNU_HASH` chain, and the other sizes follow from it.
+
+use crate::bytes::{read_u32, read_word};
+use crate::error::*;
+use crate::file::{Symbols, string_at};
+use crate::types::*;
+use object::Endianness;
+
+/// A table's place in the file, as `(offset, size)`.
+pub type FileRange = (usize, usize);
+
+/// The tables the dynamic section of a file points at. Tables the file
+/// doesn't have, or whose address no segment maps, are `None`; tables
+/// running past the end of their segment are cut short.
+pub struct Dynamic<'a> {
+    image: &'a [u8],
+    entries: &'a [u8],
+    elf64: bool,
+    encoding: Endianness,
+    syment: usize,
+    pub symtab: Option<FileRange>,
+    pub strtab: Option<FileRange>,
+    pub hash: Option<FileRange>,
+    pub gnu_hash: Option<FileRange>,
+    pub versym: Option<FileRange>,
+    pub verdef: Option<FileRange>,
+    pub verneed: Option<FileRange>,
+}
+
+/// Dynamic entries that locate a table, by tag.
+#[derive(Default)]
+struct Entries {
+    symtab: Option<u64>,
+    syment: Option<u64>,
+    strtab: Option<u64>,
+    strsz: Option<u64>,
+    hash: Option<u64>,
+    gnu_hash: Option<u64>,
+    versym: Option<u64>,
+    verdef: Option<u64>,
+    verneed: Option<u64>,
+}
+
+impl<'a> Dynamic<'a> {
+    /// Fails with `ELF_E_NO_PHDR` if the file has no `PT_DYNAMIC`, and
+    /// with `ELF_E_INVALID_PHDR` if it lies outside the file.
+    pub fn new(
+        image: &'a [u8],
+        phdrs: &[GElf_Phdr],
+        elf64: bool,
+        encoding: Endianness,
+    ) -> Result<Self, ElfError> {
+        let dynamic = phdrs
+            .iter()
+            .find(|ph| ph.p_type.get(NativeEndian) == PT_DYNAMIC)
+            .ok_or(ELF_E_NO_PHDR)?;
+        let offset = dynamic.p_offset.get(NativeEndian);
+        let buf = usize::try_from(offset)
+            .ok()
+            .zip(usize::try_from(dynamic.p_filesz.get(NativeEndian)).ok())
+            .and_then(|(offset, size)| image.get(offset..offset.checked_add(size)?))
+            .ok_or(ELF_E_INVALID_PHDR)?;
+
+        let entries = read_entries(buf, elf64, encoding);
+        let loads: Vec<_> = phdrs
+            .iter()
+            .filter(|ph| ph.p_type.get(NativeEndian) == PT_LOAD)
+            .collect();
+        // The rest of the segment that maps `addr`, within the image
+        let locate = |addr: Option<u64>| -> Option<FileRange> {
+            let addr = addr?;
+            loads.iter().find_map(|ph| {
+                let delta = addr.checked_sub(ph.p_vaddr.get(NativeEndian))?;
+                let left = ph.p_filesz.get(NativeEndian).checked_sub(delta)?;
+                let start =
+                    usize::try_from(ph.p_offset.get(NativeEndian).checked_add(delta)?).ok()?;
+                let available = image.len().checked_sub(start)?;
+                Some((
+                    start,
+                    usize::try_from(left).unwrap_or(usize::MAX).min(available),
+                ))
+            })
+        };
+
+        let word = if elf64 { 8 } else { 4 };
+        let syment = match entries.syment {
+            Some(size) if size > 0 => size as usize,
+            _ if elf64 => 24,
+            _ => 16,
+        };
+        let symtab = locate(entries.symtab);
+        let strtab = locate(entries.strtab).map(|(offset, available)| {
+            let size = entries.strsz.map_or(available, |size| size as usize);
+            (offset, size.min(available))
+        });
+
+        let hash = locate(entries.hash).and_then(|(offset, available)| {
+            let table = &image[offset..offset + available];
+            let nbucket = read_u32(table, 0, encoding)? as usize;
+            let nchain = read_u32(table, 4, encoding)? as usize;
+            let size = nbucket
+                .checked_add(nchain)?
+                .checked_add(2)?
+                .checked_mul(4)?;
+            Some(((offset, size.min(available)), nchain))
+        });
+        let gnu_hash = locate(entries.gnu_hash).and_then(|(offset, available)| {
+            let table = &image[offset..offset + available];
+            let (size, count) = gnu_hash_extent(table, word, encoding)?;
+            Some(((offset, size.min(available)), count))
+        });
+
+        // Without a hash table, assume the usual layout with the string
+        // table right after the symbols
+        let count = match (hash, gnu_hash, symtab, strtab) {
+            (Some((_, nchain)), _, _, _) => nchain,
+            (None, Some((_, count)), _, _) => count,
+            (None, None, Some((symbols, _)), Some((strings, _))) if strings > symbols => {
+                (strings - symbols) / syment
+            }
+            _ => 0,
+        };
+        let sized = |range: Option<FileRange>, entsize: usize| {
+            range.map(|(offset, available)| (offset, count.saturating_mul(entsize).min(available)))
+        };
+
+        Ok(Self {
+            image,
+            entries: buf,
+            elf64,
+            encoding,
+            syment,
+            symtab: sized(symtab, syment),
+            strtab,
+            hash: hash.map(|(range, _)| range),
+            gnu_hash: gnu_hash.map(|(range, _)| range),
+            versym: sized(locate(entries.versym), 2),
+            verdef: locate(entries.verdef),
+            verneed: locate(entries.verneed),
+        })
+    }
+
+    /// The file range of the table the dynamic entry `tag` points at, and
+    /// the data type to translate it as.
+    pub fn table(&self, tag: u32) -> Option<(FileRange, ElfType)> {
+        match tag {
+            DT_SYMTAB => self.symtab.map(|r| (r, ELF_T_SYM)),
+            DT_STRTAB => self.strtab.map(|r| (r, ELF_T_BYTE)),
+            DT_HASH => self.hash.map(|r| (r, ELF_T_WORD)),
+            DT_GNU_HASH => self.gnu_hash.map(|r| (r, ELF_T_GNUHASH)),
+            DT_VERSYM => self.versym.map(|r| (r, ELF_T_HALF)),
+            DT_VERDEF => self.verdef.map(|r| (r, ELF_T_VDEF)),
+            DT_VERNEED => self.verneed.map(|r| (r, ELF_T_VNEED)),
+            _ => None,
+        }
+    }
+
+    fn bytes(&self, range: Option<FileRange>) -> &'a [u8] {
+        range
+            .and_then(|(offset, size)| self.image.get(offset..offset + size))
+            .unwrap_or(&[])
+    }
+
+    /// The dynamic string table.
+    pub fn strings(&self) -> &'a [u8] {
+        self.bytes(self.strtab)
+    }
+
+    /// The NUL-terminated string at `offset` in the dynamic string table.
+    pub fn string(&self, offset: usize) -> Option<&'a [u8]> {
+        string_at(self.strings(), offset)
+    }
+
+    /// Iterates the dynamic entries, up to and excluding `DT_NULL`.
+    pub fn entries(&self) -> impl Iterator<Item = GElf_Dyn> + 'a {
+        entries(self.entries, self.elf64, self.encoding).map(|(tag, value)| {
+            let mut entry: GElf_Dyn = unsafe { std::mem::zeroed() };
+            entry.d_tag.set(NativeEndian, tag);
+            entry.d_val.set(NativeEndian, value);
+            entry
+        })
+    }
+
+    /// Iterates the dynamic symbols, including the null symbol at index 0.
+    pub fn symbols(&self) -> Symbols<'a> {
+        Symbols::new(
+            self.bytes(self.symtab),
+            self.syment,
+            self.strings(),
+            self.bytes(self.versym),
+            self.elf64,
+            self.encoding,
+        )
+    }
+}
+
+/// The `(d_tag, d_val)` pairs of a dynamic section, up to `DT_NULL`.
+fn entries(buf: &[u8], elf64: bool, encoding: Endianness) -> impl Iterator<Item = (u64, u64)> {
+    let word = if elf64 { 8 } else { 4 };
+    (0..buf.len() / (2 * word))
+        .map_while(move |i| {
+            let pos = i * 2 * word;
+            Some((
+                read_word(buf, pos, elf64, encoding)?,
+                read_word(buf, pos + word, elf64, encoding)?,
+            ))
+        })
+        .take_while(|&(tag, _)| tag != u64::from(DT_NULL))
+}
+
+fn read_entries(buf: &[u8], elf64: bool, encoding: Endianness) -> Entries {
+    let mut entries = Entries::default();
+    for (tag, value) in self::entries(buf, elf64, encoding) {
+        let slot = match u32::try_from(tag) {
+            Ok(DT_SYMTAB) => &mut entries.symtab,
+            Ok(DT_SYMENT) => &mut entries.syment,
+            Ok(DT_STRTAB) => &mut entries.strtab,
+            Ok(DT_STRSZ) => &mut entries.strsz,
+            Ok(DT_HASH) => &mut entries.hash,
+            Ok(DT_GNU_HASH) => &mut entries.gnu_hash,
+            Ok(DT_VERSYM) => &mut entries.versym,
+            Ok(DT_VERDEF) => &mut entries.verdef,
+            Ok(DT_VERNEED) => &mut entries.verneed,
+            _ => continue,
+        };
+        slot.get_or_insert(value);
+    }
+    entries
+}
+
+/// Size of a `DT_GNU_HASH` table and the number of symbols it implies: one
+/// past the end of the chain that starts at the highest bucket.
+fn gnu_hash_extent(table: &[u8], word: usize, encoding: Endianness) -> Option<(usize, usize)> {
+    let nbuckets = read_u32(table, 0, encoding)? as usize;
+    let symoffset = read_u32(table, 4, encoding)? as usize;
+    let bloom_size = read_u32(table, 8, encoding)? as usize;
+    let buckets = bloom_size.checked_mul(word)?.checked_add(16)?;
+    let chains = nbuckets.checked_mul(4)?.checked_add(buckets)?;
+    if chains > table.len() {
+        return None;
+    }
+
+    let last = (0..nbuckets)
+        .filter_map(|i| read_u32(table, buckets + i * 4, encoding))
+        .max()
+        .unwrap_or(0) as usize;
+    if last < symoffset {
+        return Some((chains, symoffset));
+    }
+    let mut index = last;
+    loop {
+        let pos = (index - symoffset).checked_mul(4)?.checked_add(chains)?;
+        let hash = read_u32(table, pos, encoding)?;
+        index += 1;
+        if hash & 1 != 0 {
+            return Some((pos + 4, index));
+        }
+    }
+}
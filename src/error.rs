@@ -1,59 +1,180 @@
-use std::cell::RefCell;
-use std::ffi::CString;
+use std::cell::Cell;
+use std::ffi::CStr;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
 
-thread_local! {
-    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
-    static ERROR_NUM: RefCell<i32> = const { RefCell::new(0) };
+/// Error codes, numbered as in elfutils' `ELF_E_*` so values can be compared
+/// against (and mixed with) code written for elfutils.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ElfError {
+    ELF_E_NOERROR = 0,
+    ELF_E_UNKNOWN_ERROR = 1,
+    ELF_E_UNKNOWN_VERSION = 2,
+    ELF_E_UNKNOWN_TYPE = 3,
+    ELF_E_INVALID_HANDLE = 4,
+    ELF_E_SOURCE_SIZE = 5,
+    ELF_E_DEST_SIZE = 6,
+    ELF_E_INVALID_ENCODING = 7,
+    ELF_E_NOMEM = 8,
+    ELF_E_INVALID_FILE = 9,
+    ELF_E_INVALID_ELF = 10,
+    ELF_E_INVALID_OP = 11,
+    ELF_E_NO_VERSION = 12,
+    ELF_E_INVALID_CMD = 13,
+    ELF_E_RANGE = 14,
+    ELF_E_ARCHIVE_FMAG = 15,
+    ELF_E_INVALID_ARCHIVE = 16,
+    ELF_E_NO_ARCHIVE = 17,
+    ELF_E_NO_INDEX = 18,
+    ELF_E_READ_ERROR = 19,
+    ELF_E_WRITE_ERROR = 20,
+    ELF_E_INVALID_CLASS = 21,
+    ELF_E_INVALID_INDEX = 22,
+    ELF_E_INVALID_OPERAND = 23,
+    ELF_E_INVALID_SECTION = 24,
+    ELF_E_INVALID_COMMAND = 25,
+    ELF_E_WRONG_ORDER_EHDR = 26,
+    ELF_E_FD_DISABLED = 27,
+    ELF_E_FD_MISMATCH = 28,
+    ELF_E_OFFSET_RANGE = 29,
+    ELF_E_NOT_NUL_SECTION = 30,
+    ELF_E_DATA_MISMATCH = 31,
+    ELF_E_INVALID_SECTION_HEADER = 32,
+    ELF_E_INVALID_DATA = 33,
+    ELF_E_DATA_ENCODING = 34,
+    ELF_E_SECTION_TOO_SMALL = 35,
+    ELF_E_INVALID_ALIGN = 36,
+    ELF_E_INVALID_SHENTSIZE = 37,
+    ELF_E_UPDATE_RO = 38,
+    ELF_E_NOFILE = 39,
+    ELF_E_GROUP_NOT_REL = 40,
+    ELF_E_INVALID_PHDR = 41,
+    ELF_E_NO_PHDR = 42,
+    ELF_E_INVALID_OFFSET = 43,
+    ELF_E_INVALID_SECTION_TYPE = 44,
+    ELF_E_INVALID_SECTION_FLAGS = 45,
+    ELF_E_NOT_COMPRESSED = 46,
+    ELF_E_ALREADY_COMPRESSED = 47,
+    ELF_E_UNKNOWN_COMPRESSION_TYPE = 48,
+    ELF_E_COMPRESS_ERROR = 49,
+    ELF_E_DECOMPRESS_ERROR = 50,
+    ELF_E_NUM = 51,
 }
 
-pub fn set_error(msg: &str) {
-    LAST_ERROR.with(|e| {
-        *e.borrow_mut() = CString::new(msg).ok();
-    });
-    ERROR_NUM.with(|n| {
-        *n.borrow_mut() += 1;
-    });
+pub use ElfError::*;
+
+static MESSAGES: [&CStr; ELF_E_NUM as usize] = [
+    c"no error",
+    c"unknown error",
+    c"unknown version",
+    c"unknown type",
+    c"invalid `Elf' handle",
+    c"invalid size of source operand",
+    c"invalid size of destination operand",
+    c"invalid encoding",
+    c"out of memory",
+    c"invalid file descriptor",
+    c"invalid ELF file data",
+    c"invalid operation",
+    c"ELF version not set",
+    c"invalid command",
+    c"offset out of range",
+    c"invalid fmag field in archive header",
+    c"invalid archive file",
+    c"descriptor is not for an archive",
+    c"no index available",
+    c"cannot read data from file",
+    c"cannot write data to file",
+    c"invalid binary class",
+    c"invalid section index",
+    c"invalid operand",
+    c"invalid section",
+    c"invalid command",
+    c"executable header not created first",
+    c"file descriptor disabled",
+    c"archive/member file descriptor mismatch",
+    c"offset out of range",
+    c"cannot manipulate null section",
+    c"data/scn mismatch",
+    c"invalid section header",
+    c"invalid data",
+    c"unknown data encoding",
+    c"section `sh_size' too small for data",
+    c"invalid section alignment",
+    c"invalid section entry size",
+    c"update() for write on read-only file",
+    c"no such file",
+    c"only relocatable files can contain section groups",
+    c"program header only allowed in executables, shared objects, and core files",
+    c"file has no program header",
+    c"invalid offset",
+    c"invalid section type",
+    c"invalid section flags",
+    c"section does not contain compressed data",
+    c"section contains compressed data",
+    c"unknown compression type",
+    c"cannot compress data",
+    c"cannot decompress data",
+];
+
+impl ElfError {
+    pub fn message(self) -> &'static CStr {
+        MESSAGES
+            .get(self as usize)
+            .copied()
+            .unwrap_or(MESSAGES[ELF_E_UNKNOWN_ERROR as usize])
+    }
 }
 
-pub fn get_error_ptr() -> *const i8 {
-    LAST_ERROR.with(|e| {
-        e.borrow()
-            .as_ref()
-            .map(|s| s.as_ptr())
-            .unwrap_or(std::ptr::null())
-    })
+impl fmt::Display for ElfError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message().to_string_lossy())
+    }
 }
 
-pub fn get_errno() -> i32 {
-    ERROR_NUM.with(|n| *n.borrow())
+impl std::error::Error for ElfError {}
+
+thread_local! {
+    static LAST_ERROR: Cell<ElfError> = const { Cell::new(ELF_E_NOERROR) };
 }
 
-#[unsafe(no_mangle)]
-pub extern "C" fn elf_errno() -> i32 {
-    let errno = get_errno();
-    ERROR_NUM.with(|n| *n.borrow_mut() = 0);
-    errno
+pub fn set_error(err: ElfError) {
+    LAST_ERROR.with(|e| e.set(err));
 }
 
-#[unsafe(no_mangle)]
-pub extern "C" fn elf_errmsg(error: i32) -> *const i8 {
-    static UNKNOWN: &[u8] = b"unknown error\0";
-    static NO_ERROR: &[u8] = b"no error\0";
+pub fn get_error() -> ElfError {
+    LAST_ERROR.with(|e| e.get())
+}
 
-    if error == 0 {
-        let ptr = get_error_ptr();
-        if ptr.is_null() {
-            return NO_ERROR.as_ptr() as *const i8;
+/// Runs the body of an exported function. A panic must not unwind into the
+/// C caller, so it is caught here, recorded as `ELF_E_UNKNOWN_ERROR` and
+/// reported as `fail`, the function's normal failure value.
+pub fn guard<T>(fail: T, body: impl FnOnce() -> T) -> T {
+    match panic::catch_unwind(AssertUnwindSafe(body)) {
+        Ok(value) => value,
+        Err(_) => {
+            set_error(ELF_E_UNKNOWN_ERROR);
+            fail
         }
-        return ptr;
     }
+}
 
-    if error == -1 {
-        let ptr = get_error_ptr();
-        if !ptr.is_null() {
-            return ptr;
-        }
-    }
+#[unsafe(no_mangle)]
+pub extern "C" fn elf_errno() -> i32 {
+    guard(ELF_E_UNKNOWN_ERROR as i32, || {
+        LAST_ERROR.with(|e| e.replace(ELF_E_NOERROR)) as i32
+    })
+}
 
-    UNKNOWN.as_ptr() as *const i8
+#[unsafe(no_mangle)]
+pub extern "C" fn elf_errmsg(error: i32) -> *const i8 {
+    guard(ELF_E_UNKNOWN_ERROR.message().as_ptr(), || {
+        let msg = match error {
+            0 | -1 => get_error().message(),
+            n if n > 0 && n < ELF_E_NUM as i32 => MESSAGES[n as usize],
+            _ => ELF_E_UNKNOWN_ERROR.message(),
+        };
+        msg.as_ptr()
+    })
 }
@@ -0,0 +1,587 @@
+//! A parsed ELF file, for Rust callers. The C API reads through the same
+//! type: every `Elf` descriptor over an image parses it into an `ElfFile`.
+//!
+//! Everything handed out borrows from the `ElfFile`. Headers and records
+//! come as the `GElf_*` types, in host byte order; section and segment
+//! contents are as stored in the file.
+
+use crate::bytes::{read_u16, read_u32, read_word};
+use crate::dynamic::Dynamic;
+use crate::error::*;
+use crate::handle::ParsedElf;
+use crate::note::{NoteIter, SegmentNotes, note_align};
+use crate::types::*;
+use object::Endianness;
+use object::elf::EM_MIPS;
+use std::fs::File;
+use std::marker::PhantomData;
+use std::path::Path;
+
+/// A parsed image that borrows its bytes for `'data`, or owns the mapping
+/// they live in. What it hands out borrows from it in turn, so can't
+/// outlive it, nor the bytes under it:
+///
+/// ```compile_fail,E0597
+/// use libelf_rs::ElfFile;
+///
+/// let image = std::fs::read("/proc/self/exe").unwrap();
+/// let section = {
+///     let file = ElfFile::from_bytes(&image).unwrap();
+///     file.section(1).unwrap()
+/// };
+/// section.name();
+/// ```
+///
+/// ```compile_fail,E0505
+/// use libelf_rs::ElfFile;
+///
+/// let image = std::fs::read("/proc/self/exe").unwrap();
+/// let file = ElfFile::from_bytes(&image).unwrap();
+/// drop(image);
+/// file.header();
+/// ```
+pub struct ElfFile<'data> {
+    // Borrows from `map` or from the caller's slice: declared first so it
+    // is dropped first
+    parsed: ParsedElf<'static>,
+    _map: Option<memmap2::Mmap>,
+    _data: PhantomData<&'data [u8]>,
+}
+
+impl ElfFile<'static> {
+    /// Maps the file at `path` read-only. Fails with `ELF_E_INVALID_FILE`
+    /// if it can't be opened and `ELF_E_READ_ERROR` if it can't be mapped.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, ElfError> {
+        let file = File::open(path).map_err(|_| ELF_E_INVALID_FILE)?;
+        let map = unsafe { memmap2::Mmap::map(&file) }.map_err(|_| ELF_E_READ_ERROR)?;
+        let data = unsafe { std::slice::from_raw_parts(map.as_ptr(), map.len()) };
+        unsafe { Self::from_image(data, false, Some(map)) }
+    }
+}
+
+impl<'data> ElfFile<'data> {
+    /// Parses the ELF header of `data`. The header tables are decoded when
+    /// first asked for, and rejected if `object` finds them malformed.
+    pub fn from_bytes(data: &'data [u8]) -> Result<Self, ElfError> {
+        Self::parse(data, false)
+    }
+
+    /// Like `from_bytes`, with the tables decoded as `ELF_F_PERMISSIVE`
+    /// does if `permissive` is set.
+    pub fn parse(data: &'data [u8], permissive: bool) -> Result<Self, ElfError> {
+        unsafe { Self::from_image(data, permissive, None) }
+    }
+
+    /// Parses an image kept alive by `map`, or by the caller for as long
+    /// as the result is used, as for an `Elf` descriptor.
+    ///
+    /// # Safety
+    ///
+    /// `data` is taken as `'static` so that the parse can be stored next
+    /// to the mapping it may borrow from. That holds only if:
+    ///
+    /// - with `map`, `data` lies inside it. The mapping doesn't move with
+    ///   the `Mmap`, and `parsed` is declared before `_map`, so it is
+    ///   dropped while the mapping is still there;
+    /// - without, `data` stays valid for `'data`, which the caller must
+    ///   pick no longer than the borrow it has. `from_bytes` and `parse`
+    ///   tie it to the slice they are given; an `Elf` descriptor keeps its
+    ///   image until `elf_end` and lends the result out only behind a
+    ///   borrow of itself.
+    ///
+    /// Nothing typed with the `'static` of `parsed` is handed out: every
+    /// accessor ties what it returns to `&self`.
+    pub(crate) unsafe fn from_image(
+        data: &[u8],
+        permissive: bool,
+        map: Option<memmap2::Mmap>,
+    ) -> Result<Self, ElfError> {
+        let data: &'static [u8] = unsafe { std::mem::transmute(data) };
+        Ok(Self {
+            parsed: ParsedElf::parse(data, permissive)?,
+            _map: map,
+            _data: PhantomData,
+        })
+    }
+
+    /// Switches the mode the header tables will be decoded in, as
+    /// `ELF_F_PERMISSIVE` does. Fails with `ELF_E_INVALID_OP` once either
+    /// table has been decoded in the other mode.
+    pub fn set_permissive(&self, permissive: bool) -> Result<(), ElfError> {
+        self.parsed.set_permissive(permissive)
+    }
+
+    /// The whole image.
+    pub fn data(&self) -> &[u8] {
+        self.parsed.data()
+    }
+
+    pub fn is_elf32(&self) -> bool {
+        self.parsed.is_elf32()
+    }
+
+    pub fn is_elf64(&self) -> bool {
+        self.parsed.is_elf64()
+    }
+
+    pub fn endianness(&self) -> Endianness {
+        self.parsed.endianness()
+    }
+
+    pub fn header(&self) -> GElf_Ehdr {
+        self.parsed.get_ehdr()
+    }
+
+    /// The index of the section header string table, from `e_shstrndx`
+    /// or, for `SHN_XINDEX`, section 0's `sh_link`.
+    pub fn shstrndx(&self) -> Result<usize, ElfError> {
+        self.parsed.shstrndx()
+    }
+
+    /// Sections in the file, counting the null section.
+    pub fn section_count(&self) -> Result<usize, ElfError> {
+        self.parsed.section_count()
+    }
+
+    pub fn program_header_count(&self) -> Result<usize, ElfError> {
+        self.parsed.program_header_count()
+    }
+
+    pub fn section_header(&self, index: usize) -> Option<GElf_Shdr> {
+        self.parsed.get_shdr(index)
+    }
+
+    pub fn program_header(&self, index: usize) -> Option<GElf_Phdr> {
+        self.parsed.get_phdr(index)
+    }
+
+    /// The file contents of section `index`; empty for `SHT_NOBITS`.
+    pub fn section_data(&self, index: usize) -> Option<&[u8]> {
+        self.parsed.section_data(index)
+    }
+
+    /// Section `index`, failing with `ELF_E_INVALID_INDEX` past the end of
+    /// the table.
+    pub fn section(&self, index: usize) -> Result<Section<'_>, ElfError> {
+        self.section_count()?;
+        let header = self.section_header(index).ok_or(ELF_E_INVALID_INDEX)?;
+        Ok(Section {
+            file: self,
+            index,
+            header,
+        })
+    }
+
+    /// The first section called `name`.
+    pub fn section_by_name(&self, name: &[u8]) -> Result<Option<Section<'_>>, ElfError> {
+        Ok(self.sections()?.find(|s| s.name() == Some(name)))
+    }
+
+    /// Iterates the sections, including the null section at index 0.
+    pub fn sections(&self) -> Result<Sections<'_>, ElfError> {
+        Ok(Sections {
+            file: self,
+            indices: 0..self.section_count()?,
+        })
+    }
+
+    /// Iterates the program headers.
+    pub fn segments(&self) -> Result<Segments<'_>, ElfError> {
+        Ok(Segments {
+            file: self,
+            indices: 0..self.program_header_count()?,
+        })
+    }
+
+    /// The symbols of the first `SHT_SYMTAB`; none if there isn't one.
+    pub fn symbols(&self) -> Result<Symbols<'_>, ElfError> {
+        self.symbol_table(SHT_SYMTAB)
+    }
+
+    /// The symbols of the first `SHT_DYNSYM`, with their versions; none if
+    /// there isn't one. For files without section headers, see `dynamic`.
+    pub fn dynamic_symbols(&self) -> Result<Symbols<'_>, ElfError> {
+        self.symbol_table(SHT_DYNSYM)
+    }
+
+    fn symbol_table(&self, sh_type: u32) -> Result<Symbols<'_>, ElfError> {
+        match self.sections()?.find(|s| s.sh_type() == sh_type) {
+            Some(section) => section.symbols(),
+            None => Ok(Symbols::new(
+                &[],
+                1,
+                &[],
+                &[],
+                self.is_elf64(),
+                self.endianness(),
+            )),
+        }
+    }
+
+    /// Iterates the notes of every `PT_NOTE` segment, which is where core
+    /// files keep `NT_PRSTATUS`, `NT_AUXV`, `NT_FILE` and `NT_SIGINFO`.
+    pub fn notes(&self) -> Result<SegmentNotes<'_>, ElfError> {
+        let segments = self
+            .segments()?
+            .filter(|segment| segment.header.p_type.get(NativeEndian) == PT_NOTE)
+            .map(|segment| {
+                let ph = segment.header;
+                (
+                    ph.p_offset.get(NativeEndian) as usize,
+                    ph.p_filesz.get(NativeEndian) as usize,
+                    ph.p_align.get(NativeEndian) as usize,
+                )
+            })
+            .collect();
+        Ok(SegmentNotes::new(
+            self.data(),
+            segments,
+            self.is_elf64(),
+            self.endianness(),
+        ))
+    }
+
+    /// The dynamic entries and the tables `PT_DYNAMIC` points at. For files
+    /// without section headers this is the only way to their symbols and
+    /// versions.
+    pub fn dynamic(&self) -> Result<Dynamic<'_>, ElfError> {
+        let phdrs: Vec<_> = self.segments()?.map(|segment| segment.header).collect();
+        Dynamic::new(self.data(), &phdrs, self.is_elf64(), self.endianness())
+    }
+
+    /// Finds a dynamic symbol through `.gnu.hash` or, failing that, `.hash`.
+    /// Without `version`, only the default version of a symbol matches, as
+    /// with `dlsym`.
+    pub fn lookup_dynsym(
+        &self,
+        name: &[u8],
+        version: Option<&[u8]>,
+    ) -> Result<Option<(usize, GElf_Sym)>, ElfError> {
+        self.parsed.lookup_dynsym(name, version)
+    }
+}
+
+/// A section and its header.
+#[derive(Clone, Copy)]
+pub struct Section<'a> {
+    file: &'a ElfFile<'a>,
+    pub index: usize,
+    pub header: GElf_Shdr,
+}
+
+impl<'a> Section<'a> {
+    pub fn sh_type(&self) -> u32 {
+        self.header.sh_type.get(NativeEndian)
+    }
+
+    /// The name from the section header string table, if it has one.
+    pub fn name(&self) -> Option<&'a [u8]> {
+        self.file.parsed.section_name(self.index)
+    }
+
+    /// The contents as stored in the file; empty for `SHT_NOBITS`. Fails
+    /// with `ELF_E_INVALID_SECTION` if they lie outside the file.
+    pub fn data(&self) -> Result<&'a [u8], ElfError> {
+        self.file
+            .section_data(self.index)
+            .ok_or(ELF_E_INVALID_SECTION)
+    }
+
+    /// The section `sh_link` refers to.
+    pub fn link(&self) -> Result<Section<'a>, ElfError> {
+        self.file
+            .section(self.header.sh_link.get(NativeEndian) as usize)
+    }
+
+    fn entsize(&self, record: usize) -> usize {
+        match self.header.sh_entsize.get(NativeEndian) as usize {
+            size if size >= record => size,
+            _ => record,
+        }
+    }
+
+    /// The symbols of an `SHT_SYMTAB` or `SHT_DYNSYM`, with their versions
+    /// if an `SHT_GNU_versym` refers to it. Other sections fail with
+    /// `ELF_E_INVALID_SECTION_TYPE`.
+    pub fn symbols(&self) -> Result<Symbols<'a>, ElfError> {
+        if !matches!(self.sh_type(), SHT_SYMTAB | SHT_DYNSYM) {
+            return Err(ELF_E_INVALID_SECTION_TYPE);
+        }
+        let file = self.file;
+        let elf64 = file.is_elf64();
+        let versyms = file.sections()?.find(|s| {
+            s.sh_type() == SHT_GNU_VERSYM
+                && s.header.sh_link.get(NativeEndian) as usize == self.index
+        });
+        Ok(Symbols::new(
+            self.data()?,
+            self.entsize(if elf64 { 24 } else { 16 }),
+            self.link()?.data()?,
+            versyms.map_or(Ok(&[][..]), |s| s.data())?,
+            elf64,
+            file.endianness(),
+        ))
+    }
+
+    /// The entries of an `SHT_REL` or `SHT_RELA`. Other sections fail with
+    /// `ELF_E_INVALID_SECTION_TYPE`.
+    pub fn relocations(&self) -> Result<Relocations<'a>, ElfError> {
+        let rela = match self.sh_type() {
+            SHT_REL => false,
+            SHT_RELA => true,
+            _ => return Err(ELF_E_INVALID_SECTION_TYPE),
+        };
+        let ehdr = self.file.header();
+        let elf64 = self.file.is_elf64();
+        let encoding = self.file.endianness();
+        let word = if elf64 { 8 } else { 4 };
+        let record = if rela { 3 * word } else { 2 * word };
+        Ok(Relocations {
+            records: self.data()?.chunks_exact(self.entsize(record)),
+            rela,
+            elf64,
+            mips64el: elf64
+                && encoding == Endianness::Little
+                && ehdr.e_machine.get(NativeEndian) == EM_MIPS,
+            encoding,
+        })
+    }
+
+    /// The notes of an `SHT_NOTE`. Other sections fail with
+    /// `ELF_E_INVALID_SECTION_TYPE`.
+    pub fn notes(&self) -> Result<NoteIter<'a>, ElfError> {
+        if self.sh_type() != SHT_NOTE {
+            return Err(ELF_E_INVALID_SECTION_TYPE);
+        }
+        let align = note_align(
+            ELF_T_BYTE,
+            self.header.sh_addralign.get(NativeEndian) as usize,
+        );
+        Ok(NoteIter::new(
+            self.data()?,
+            align,
+            self.file.is_elf64(),
+            self.file.endianness(),
+        ))
+    }
+}
+
+pub struct Sections<'a> {
+    file: &'a ElfFile<'a>,
+    indices: std::ops::Range<usize>,
+}
+
+impl<'a> Iterator for Sections<'a> {
+    type Item = Section<'a>;
+
+    fn next(&mut self) -> Option<Section<'a>> {
+        let index = self.indices.next()?;
+        let header = self.file.section_header(index)?;
+        Some(Section {
+            file: self.file,
+            index,
+            header,
+        })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.indices.size_hint()
+    }
+}
+
+/// A program header and the file it describes.
+#[derive(Clone, Copy)]
+pub struct Segment<'a> {
+    file: &'a ElfFile<'a>,
+    pub index: usize,
+    pub header: GElf_Phdr,
+}
+
+impl<'a> Segment<'a> {
+    pub fn p_type(&self) -> u32 {
+        self.header.p_type.get(NativeEndian)
+    }
+
+    /// The `p_filesz` bytes at `p_offset`. Fails with `ELF_E_INVALID_PHDR`
+    /// if they lie outside the file.
+    pub fn data(&self) -> Result<&'a [u8], ElfError> {
+        let offset = usize::try_from(self.header.p_offset.get(NativeEndian));
+        let size = usize::try_from(self.header.p_filesz.get(NativeEndian));
+        offset
+            .ok()
+            .zip(size.ok())
+            .and_then(|(offset, size)| self.file.data().get(offset..offset.checked_add(size)?))
+            .ok_or(ELF_E_INVALID_PHDR)
+    }
+
+    /// The notes of a `PT_NOTE`. Other segments fail with
+    /// `ELF_E_INVALID_OPERAND`.
+    pub fn notes(&self) -> Result<NoteIter<'a>, ElfError> {
+        if self.p_type() != PT_NOTE {
+            return Err(ELF_E_INVALID_OPERAND);
+        }
+        let align = note_align(ELF_T_BYTE, self.header.p_align.get(NativeEndian) as usize);
+        Ok(NoteIter::new(
+            self.data()?,
+            align,
+            self.file.is_elf64(),
+            self.file.endianness(),
+        ))
+    }
+}
+
+pub struct Segments<'a> {
+    file: &'a ElfFile<'a>,
+    indices: std::ops::Range<usize>,
+}
+
+impl<'a> Iterator for Segments<'a> {
+    type Item = Segment<'a>;
+
+    fn next(&mut self) -> Option<Segment<'a>> {
+        let index = self.indices.next()?;
+        let header = self.file.program_header(index)?;
+        Some(Segment {
+            file: self.file,
+            index,
+            header,
+        })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.indices.size_hint()
+    }
+}
+
+/// One entry of a symbol table.
+#[derive(Debug, Clone, Copy)]
+pub struct Symbol<'a> {
+    pub index: usize,
+    pub name: &'a [u8],
+    pub sym: GElf_Sym,
+    /// The `SHT_GNU_versym` or `DT_VERSYM` entry, if the file has one.
+    pub version: Option<u16>,
+}
+
+/// Walks a symbol table, including the null symbol at index 0.
+pub struct Symbols<'a> {
+    records: std::iter::Enumerate<std::slice::ChunksExact<'a, u8>>,
+    strings: &'a [u8],
+    versyms: &'a [u8],
+    elf64: bool,
+    encoding: Endianness,
+}
+
+impl<'a> Symbols<'a> {
+    /// `symbols` holds records of `entsize` bytes, named from `strings`
+    /// and versioned from `versyms`.
+    pub fn new(
+        symbols: &'a [u8],
+        entsize: usize,
+        strings: &'a [u8],
+        versyms: &'a [u8],
+        elf64: bool,
+        encoding: Endianness,
+    ) -> Self {
+        Self {
+            records: symbols.chunks_exact(entsize.max(1)).enumerate(),
+            strings,
+            versyms,
+            elf64,
+            encoding,
+        }
+    }
+
+    /// Decodes one symbol stored in the class and byte order given: an
+    /// ELF32 symbol has `st_value` and `st_size` first, an ELF64 one last.
+    /// `gelf_getsym` decodes through here too.
+    pub(crate) fn decode(record: &[u8], elf64: bool, e: Endianness) -> Option<GElf_Sym> {
+        let mut sym: GElf_Sym = unsafe { std::mem::zeroed() };
+        sym.st_name.set(NativeEndian, read_u32(record, 0, e)?);
+        let (value, size, info) = if elf64 { (8, 16, 4) } else { (4, 8, 12) };
+        sym.st_value
+            .set(NativeEndian, read_word(record, value, elf64, e)?);
+        sym.st_size
+            .set(NativeEndian, read_word(record, size, elf64, e)?);
+        sym.st_info = *record.get(info)?;
+        sym.st_other = *record.get(info + 1)?;
+        sym.st_shndx
+            .set(NativeEndian, read_u16(record, info + 2, e)?);
+        Some(sym)
+    }
+}
+
+impl<'a> Iterator for Symbols<'a> {
+    type Item = Symbol<'a>;
+
+    fn next(&mut self) -> Option<Symbol<'a>> {
+        let (index, record) = self.records.next()?;
+        let sym = Self::decode(record, self.elf64, self.encoding)?;
+        Some(Symbol {
+            index,
+            name: string_at(self.strings, sym.st_name.get(NativeEndian) as usize)
+                .unwrap_or_default(),
+            sym,
+            version: read_u16(self.versyms, index * 2, self.encoding),
+        })
+    }
+}
+
+/// One entry of an `SHT_REL` or `SHT_RELA`, with `r_info` split up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Relocation {
+    pub offset: u64,
+    pub symbol: u32,
+    pub r_type: u32,
+    /// The explicit addend; `None` for `SHT_REL`.
+    pub addend: Option<i64>,
+}
+
+pub struct Relocations<'a> {
+    records: std::slice::ChunksExact<'a, u8>,
+    rela: bool,
+    elf64: bool,
+    /// 64-bit little-endian MIPS, whose `r_info` is not a plain word
+    mips64el: bool,
+    encoding: Endianness,
+}
+
+impl Iterator for Relocations<'_> {
+    type Item = Relocation;
+
+    fn next(&mut self) -> Option<Relocation> {
+        let record = self.records.next()?;
+        let word = if self.elf64 { 8 } else { 4 };
+        let read = |pos| read_word(record, pos, self.elf64, self.encoding);
+        let mut info = read(word)?;
+        if self.mips64el {
+            info = (info << 32)
+                | ((info >> 8) & 0xff000000)
+                | ((info >> 24) & 0x00ff0000)
+                | ((info >> 40) & 0x0000ff00)
+                | ((info >> 56) & 0x000000ff);
+        }
+        let (symbol, r_type) = match self.elf64 {
+            true => ((info >> 32) as u32, info as u32),
+            false => ((info >> 8) as u32, info as u8 as u32),
+        };
+        let addend = match (self.rela, self.elf64) {
+            (false, _) => None,
+            (true, true) => Some(read(2 * word)? as i64),
+            (true, false) => Some(i64::from(read(2 * word)? as i32)),
+        };
+        Some(Relocation {
+            offset: read(0)?,
+            symbol,
+            r_type,
+            addend,
+        })
+    }
+}
+
+/// The NUL-terminated string at `offset` in a string table.
+pub fn string_at(table: &[u8], offset: usize) -> Option<&[u8]> {
+    let tail = table.get(offset..)?;
+    let end = tail.iter().position(|&b| b == 0)?;
+    Some(&tail[..end])
+}
@@ -0,0 +1,249 @@
+//! Drives the C API over one arbitrary input, the way a consumer walking an
+//! untrusted file would. `fuzz/` wraps this in a libFuzzer target through the
+//! `fuzz` feature; the tests run it over mutated files.
+
+use crate::handle::Elf;
+use crate::*;
+use std::ptr;
+
+/// Records looked at per data buffer, so huge tables don't dominate a run.
+const MAX_RECORDS: i32 = 256;
+
+/// Parses `input` and reads everything reachable from it. Errors are
+/// expected and ignored; anything else (a crash, a hang, a sanitizer
+/// report) is a bug.
+pub fn exercise(input: &[u8]) {
+    elf_version(EV_CURRENT.into());
+
+    let mut image = input.to_vec();
+    let elf = elf_memory(image.as_mut_ptr() as *mut i8, image.len());
+    if elf.is_null() {
+        return;
+    }
+    exercise_elf(elf);
+
+    // A second activation shares the parsed state
+    let dup = elf_begin(-1, ELF_C_READ, elf);
+    if !dup.is_null() {
+        exercise_elf(dup);
+        elf_end(dup);
+    }
+
+    elf_flagelf(elf, ELF_C_SET, ELF_F_DIRTY);
+    elf_update(elf, ELF_C_NULL);
+    elf_end(elf);
+
+    // Permissive parsing keeps more of a damaged file reachable
+    let elf = elf_memory(image.as_mut_ptr() as *mut i8, image.len());
+    if !elf.is_null() {
+        elf_flagelf(elf, ELF_C_SET, ELF_F_PERMISSIVE);
+        exercise_elf(elf);
+        elf_end(elf);
+    }
+
+    for permissive in [false, true] {
+        if let Ok(file) = ElfFile::parse(input, permissive) {
+            exercise_file(&file);
+        }
+    }
+}
+
+/// Walks what the Rust API hands out, without going through a descriptor.
+fn exercise_file(file: &ElfFile) {
+    let records = MAX_RECORDS as usize;
+    file.header();
+    if let Ok(sections) = file.sections() {
+        for section in sections.take(records) {
+            section.name();
+            let _ = section.data();
+            if let Ok(symbols) = section.symbols() {
+                symbols.take(records).for_each(drop);
+            }
+            if let Ok(relocations) = section.relocations() {
+                relocations.take(records).for_each(drop);
+            }
+            if let Ok(notes) = section.notes() {
+                notes.take(records).for_each(drop);
+            }
+        }
+    }
+    if let Ok(segments) = file.segments() {
+        for segment in segments.take(records) {
+            let _ = segment.data();
+            if let Ok(notes) = segment.notes() {
+                notes.take(records).for_each(drop);
+            }
+        }
+    }
+    if let Ok(dynamic) = file.dynamic() {
+        dynamic.entries().take(records).for_each(drop);
+    }
+}
+
+fn exercise_elf(elf: *mut Elf) {
+    let mut size = 0;
+    elf_kind(elf);
+    elf_getident(elf, &mut size);
+    elf_rawfile(elf, &mut size);
+    elf_getbase(elf);
+    gelf_getclass(elf);
+
+    let mut ehdr: GElf_Ehdr = unsafe { std::mem::zeroed() };
+    gelf_getehdr(elf, &mut ehdr);
+    let raw_ehdr = elf64_getehdr(elf);
+    if !raw_ehdr.is_null() {
+        unsafe { ptr::read_volatile(raw_ehdr) };
+    }
+
+    let mut shstrndx = 0;
+    elf_getshdrstrndx(elf, &mut shstrndx);
+    let mut shnum = 0;
+    elf_getshdrnum(elf, &mut shnum);
+    let mut phnum = 0;
+    elf_getphdrnum(elf, &mut phnum);
+
+    for index in 0..phnum.min(MAX_RECORDS as usize) {
+        exercise_segment(elf, index as i32);
+    }
+
+    let mut scn = elf_nextscn(elf, ptr::null_mut());
+    while !scn.is_null() {
+        exercise_section(elf, scn, shstrndx);
+        scn = elf_nextscn(elf, scn);
+    }
+    elf_getscn(elf, shnum);
+    elf64_offscn(elf, ehdr.e_shoff.get(NativeEndian));
+
+    let mut sym: GElf_Sym = unsafe { std::mem::zeroed() };
+    let mut ndx = 0;
+    gelf_lookup_dynsym(elf, c"main".as_ptr(), ptr::null(), &mut sym, &mut ndx);
+
+    for tag in [
+        DT_SYMTAB,
+        DT_STRTAB,
+        DT_HASH,
+        DT_GNU_HASH,
+        DT_VERSYM,
+        DT_VERDEF,
+        DT_VERNEED,
+    ] {
+        elf_getdata_dynamic(elf, tag.into());
+    }
+    if let Ok(dynamic) = unsafe { &*elf }.dynamic() {
+        for symbol in dynamic.symbols().take(MAX_RECORDS as usize) {
+            dynamic.string(symbol.sym.st_name.get(NativeEndian) as usize);
+        }
+    }
+
+    if let Ok(notes) = unsafe { &*elf }.file().and_then(|file| file.notes()) {
+        for note in notes {
+            note.siginfo();
+            note.prstatus();
+            note.auxv();
+            note.mapped_files();
+        }
+    }
+}
+
+fn exercise_segment(elf: *mut Elf, index: i32) {
+    let mut phdr: GElf_Phdr = unsafe { std::mem::zeroed() };
+    if gelf_getphdr(elf, index, &mut phdr).is_null() {
+        return;
+    }
+    let ty = match phdr.p_type.get(NativeEndian) {
+        PT_NOTE => ELF_T_NHDR,
+        PT_DYNAMIC => ELF_T_DYN,
+        _ => ELF_T_BYTE,
+    };
+    let offset = phdr.p_offset.get(NativeEndian) as i64;
+    let size = phdr.p_filesz.get(NativeEndian) as usize;
+    let data = elf_getdata_rawchunk(elf, offset, size, ty);
+    if !data.is_null() && ty == ELF_T_NHDR {
+        exercise_notes(data);
+    }
+}
+
+fn exercise_section(elf: *mut Elf, scn: *mut Elf_Scn, shstrndx: usize) {
+    elf_ndxscn(scn);
+    let raw_shdr = elf64_getshdr(scn);
+    if !raw_shdr.is_null() {
+        unsafe { ptr::read_volatile(raw_shdr) };
+    }
+
+    let mut shdr: GElf_Shdr = unsafe { std::mem::zeroed() };
+    if gelf_getshdr(scn, &mut shdr).is_null() {
+        return;
+    }
+    elf_strptr(elf, shstrndx, shdr.sh_name.get(NativeEndian) as usize);
+
+    let mut data = elf_getdata(scn, ptr::null_mut());
+    while !data.is_null() {
+        exercise_data(elf, data, &shdr);
+        data = elf_getdata(scn, data);
+    }
+    elf_rawdata(scn, ptr::null_mut());
+}
+
+fn exercise_data(elf: *mut Elf, data: *mut Elf_Data, shdr: &GElf_Shdr) {
+    let link = shdr.sh_link.get(NativeEndian) as usize;
+    match shdr.sh_type.get(NativeEndian) {
+        SHT_SYMTAB | SHT_DYNSYM => {
+            let mut sym: GElf_Sym = unsafe { std::mem::zeroed() };
+            for ndx in 0..MAX_RECORDS {
+                if gelf_getsym(data, ndx, &mut sym).is_null() {
+                    break;
+                }
+                elf_strptr(elf, link, sym.st_name.get(NativeEndian) as usize);
+                GELF_ST_TYPE(sym.st_info);
+            }
+        }
+        SHT_GNU_VERSYM => {
+            let mut versym: GElf_Versym = unsafe { std::mem::zeroed() };
+            for ndx in 0..MAX_RECORDS {
+                if gelf_getversym(data, ndx, &mut versym).is_null() {
+                    break;
+                }
+            }
+        }
+        SHT_GNU_VERDEF => {
+            let mut verdef: GElf_Verdef = unsafe { std::mem::zeroed() };
+            let mut verdaux: GElf_Verdaux = unsafe { std::mem::zeroed() };
+            if !gelf_getverdef(data, 0, &mut verdef).is_null() {
+                let aux = verdef.vd_aux.get(NativeEndian) as i32;
+                gelf_getverdaux(data, aux, &mut verdaux);
+            }
+        }
+        SHT_NOTE => exercise_notes(data),
+        SHT_RELR => {
+            let mut relr: GElf_Relr = unsafe { std::mem::zeroed() };
+            gelf_getrelr(data, 0, &mut relr);
+            let mut addrs = [0u64; 16];
+            gelf_relr_addrs(data, addrs.as_mut_ptr(), addrs.len());
+        }
+        _ => {
+            // Whatever the section holds, the record accessors must refuse
+            // what doesn't fit
+            let mut auxv = GElf_auxv_t::default();
+            gelf_getauxv(data, 0, &mut auxv);
+            let mut syminfo: GElf_Syminfo = unsafe { std::mem::zeroed() };
+            gelf_getsyminfo(data, 0, &mut syminfo);
+            let mut mv: GElf_Move = unsafe { std::mem::zeroed() };
+            gelf_getmove(data, 0, &mut mv);
+            let mut lib: GElf_Lib = unsafe { std::mem::zeroed() };
+            gelf_getlib(data, 0, &mut lib);
+        }
+    }
+}
+
+fn exercise_notes(data: *mut Elf_Data) {
+    let mut nhdr: GElf_Nhdr = unsafe { std::mem::zeroed() };
+    let (mut name, mut desc) = (0, 0);
+    let mut offset = 0;
+    for _ in 0..MAX_RECORDS {
+        let next = gelf_getnote(data, offset, &mut nhdr, &mut name, &mut desc);
+        if next == 0 {
+            break;
+        }
+        offset = next;
+    }
+}
@@ -1,25 +1,166 @@
-use crate::error::set_error;
+use crate::dynamic::Dynamic;
+use crate::error::*;
+use crate::file::ElfFile;
+use crate::limits;
 use crate::types::*;
-use object::read::elf::{ElfFile32, ElfFile64, FileHeader, ProgramHeader, SectionHeader};
-use object::write::Object as WriteObject;
-use object::{Endianness, FileKind, SectionIndex};
+use object::elf::{FileHeader32, FileHeader64, SHN_XINDEX, SectionHeader32, SectionHeader64};
+use object::endian::{U32, U64};
+use object::read::elf::{
+    FileHeader, ProgramHeader, SectionHeader, SectionTable, Sym, VersionIndex, VersionTable,
+};
+use object::{Endianness, FileKind};
+use object::{Pod, ReadRef};
+use std::ffi::c_void;
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Mutex, MutexGuard, OnceLock, PoisonError};
+
+/// The parts of a file the accessors work on. Only the ELF header is read
+/// up front: the program and section header tables are decoded the first
+/// time they are asked for, so a caller that only wants the header or the
+/// notes never touches the rest of the file.
+///
+/// In strict mode a table `object` rejects fails every access to it. In
+/// permissive mode a table that is truncated keeps the entries that fit,
+/// and one that can't be read at all is empty. The mode can change until
+/// either table has been decoded.
+pub struct ElfImage<'a, Elf: FileHeader<Endian = Endianness>> {
+    data: &'a [u8],
+    endian: Endianness,
+    header: &'a Elf,
+    permissive: AtomicBool,
+    segments: OnceLock<Result<&'a [Elf::ProgramHeader], ElfError>>,
+    sections: OnceLock<Result<SectionTable<'a, Elf>, ElfError>>,
+}
+
+pub type ElfImage32<'a> = ElfImage<'a, FileHeader32<Endianness>>;
+pub type ElfImage64<'a> = ElfImage<'a, FileHeader64<Endianness>>;
+
+impl<'a, Elf: FileHeader<Endian = Endianness>> ElfImage<'a, Elf> {
+    pub fn parse(data: &'a [u8], permissive: bool) -> Result<Self, ElfError> {
+        let header = Elf::parse(data).map_err(|_| ELF_E_INVALID_ELF)?;
+        let endian = header.endian().map_err(|_| ELF_E_INVALID_ELF)?;
+        Ok(Self {
+            data,
+            endian,
+            header,
+            permissive: AtomicBool::new(permissive),
+            segments: OnceLock::new(),
+            sections: OnceLock::new(),
+        })
+    }
+
+    pub fn endian(&self) -> Endianness {
+        self.endian
+    }
+
+    fn permissive(&self) -> bool {
+        self.permissive.load(Ordering::Relaxed)
+    }
+
+    /// Switches the mode the tables will be decoded in. Fails with
+    /// `ELF_E_INVALID_OP` once either has been decoded in the other mode.
+    pub fn set_permissive(&self, permissive: bool) -> Result<(), ElfError> {
+        if self.permissive() == permissive {
+            return Ok(());
+        }
+        if self.segments.get().is_some() || self.sections.get().is_some() {
+            return Err(ELF_E_INVALID_OP);
+        }
+        self.permissive.store(permissive, Ordering::Relaxed);
+        Ok(())
+    }
+
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+
+    pub fn elf_header(&self) -> &'a Elf {
+        self.header
+    }
+
+    /// The program headers, decoded on first use.
+    pub fn elf_program_headers(&self) -> Result<&'a [Elf::ProgramHeader], ElfError> {
+        *self.segments.get_or_init(|| {
+            let (header, endian, data) = (self.header, self.endian, self.data);
+            match header.program_headers(endian, data) {
+                Ok(segments) => Ok(segments),
+                Err(_) if self.permissive() => {
+                    let count = header
+                        .phnum(endian, data)
+                        .unwrap_or(usize::from(header.e_phnum(endian)));
+                    Ok(intact_entries(
+                        data,
+                        header.e_phoff(endian).into(),
+                        count,
+                        header.e_phentsize(endian),
+                    ))
+                }
+                Err(_) => Err(ELF_E_INVALID_PHDR),
+            }
+        })
+    }
+
+    /// The section headers and their names, decoded on first use. Files
+    /// with more sections than `max_sections` fail with `ELF_E_NOMEM`.
+    pub fn elf_section_table(&self) -> Result<&SectionTable<'a, Elf>, ElfError> {
+        let table = self.sections.get_or_init(|| {
+            let (header, endian, data) = (self.header, self.endian, self.data);
+            let table = if self.permissive() {
+                let headers = header.section_headers(endian, data).unwrap_or_else(|_| {
+                    let count = header.shnum(endian, data).unwrap_or(0);
+                    intact_entries(
+                        data,
+                        header.e_shoff(endian).into(),
+                        count,
+                        header.e_shentsize(endian),
+                    )
+                });
+                let strings = header
+                    .section_strings(endian, data, headers)
+                    .unwrap_or_default();
+                SectionTable::new(headers, strings)
+            } else {
+                header
+                    .sections(endian, data)
+                    .map_err(|_| ELF_E_INVALID_SECTION_HEADER)?
+            };
+            limits::check_section_count(table.len())?;
+            Ok(table)
+        });
+        table.as_ref().map_err(|&e| e)
+    }
+}
+
+/// The leading entries of a table of `count` entries at `offset` that lie
+/// within `data`; none if the entry size is wrong.
+fn intact_entries<T: Pod>(data: &[u8], offset: u64, count: usize, entsize: u16) -> &[T] {
+    if usize::from(entsize) != size_of::<T>() || offset == 0 {
+        return &[];
+    }
+    let available = usize::try_from(offset)
+        .ok()
+        .and_then(|offset| data.len().checked_sub(offset))
+        .unwrap_or(0)
+        / size_of::<T>();
+    data.read_slice_at(offset, count.min(available))
+        .unwrap_or(&[])
+}
 
 pub enum ParsedElf<'a> {
-    Elf32(ElfFile32<'a, Endianness>),
-    Elf64(ElfFile64<'a, Endianness>),
+    Elf32(ElfImage32<'a>),
+    Elf64(ElfImage64<'a>),
 }
 
 impl<'a> ParsedElf<'a> {
-    pub fn parse(data: &'a [u8]) -> Result<Self, &'static str> {
+    pub fn parse(data: &'a [u8], permissive: bool) -> Result<Self, ElfError> {
         match FileKind::parse(data) {
-            Ok(FileKind::Elf32) => ElfFile32::parse(data)
-                .map(ParsedElf::Elf32)
-                .map_err(|_| "failed to parse ELF32"),
-            Ok(FileKind::Elf64) => ElfFile64::parse(data)
-                .map(ParsedElf::Elf64)
-                .map_err(|_| "failed to parse ELF64"),
-            Ok(_) => Err("not an ELF file"),
-            Err(_) => Err("failed to determine file kind"),
+            Ok(FileKind::Elf32) => ElfImage::parse(data, permissive).map(ParsedElf::Elf32),
+            Ok(FileKind::Elf64) => ElfImage::parse(data, permissive).map(ParsedElf::Elf64),
+            Ok(_) => Err(ELF_E_INVALID_FILE),
+            // ELF magic with a class or encoding `object` can't read
+            Err(_) if data.starts_with(&object::elf::ELFMAG) => Err(ELF_E_INVALID_ELF),
+            Err(_) => Err(ELF_E_INVALID_FILE),
         }
     }
 
@@ -38,114 +179,84 @@ impl<'a> ParsedElf<'a> {
         }
     }
 
-    pub fn section_count(&self) -> usize {
+    pub fn set_permissive(&self, permissive: bool) -> Result<(), ElfError> {
+        match self {
+            ParsedElf::Elf32(e) => e.set_permissive(permissive),
+            ParsedElf::Elf64(e) => e.set_permissive(permissive),
+        }
+    }
+
+    pub fn data(&self) -> &'a [u8] {
         match self {
-            ParsedElf::Elf32(e) => e.elf_section_table().len(),
-            ParsedElf::Elf64(e) => e.elf_section_table().len(),
+            ParsedElf::Elf32(e) => e.data(),
+            ParsedElf::Elf64(e) => e.data(),
         }
     }
 
-    pub fn program_header_count(&self) -> usize {
+    /// Decodes the section table if nothing has yet.
+    pub fn section_count(&self) -> Result<usize, ElfError> {
         match self {
-            ParsedElf::Elf32(e) => e.elf_program_headers().len(),
-            ParsedElf::Elf64(e) => e.elf_program_headers().len(),
+            ParsedElf::Elf32(e) => e.elf_section_table().map(|t| t.len()),
+            ParsedElf::Elf64(e) => e.elf_section_table().map(|t| t.len()),
         }
     }
 
-    pub fn shstrndx(&self) -> usize {
+    /// Decodes the program headers if nothing has yet.
+    pub fn program_header_count(&self) -> Result<usize, ElfError> {
         match self {
-            ParsedElf::Elf32(e) => e.elf_header().e_shstrndx(e.endian()) as usize,
-            ParsedElf::Elf64(e) => e.elf_header().e_shstrndx(e.endian()) as usize,
+            ParsedElf::Elf32(e) => e.elf_program_headers().map(|p| p.len()),
+            ParsedElf::Elf64(e) => e.elf_program_headers().map(|p| p.len()),
         }
     }
 
+    /// The section header string table index, read from section 0 for
+    /// `SHN_XINDEX`. Fails with `ELF_E_INVALID_SECTION_HEADER` if it is
+    /// past the end of the section table.
+    pub fn shstrndx(&self) -> Result<usize, ElfError> {
+        let index = match self {
+            ParsedElf::Elf32(e) => e.elf_header().e_shstrndx(e.endian()),
+            ParsedElf::Elf64(e) => e.elf_header().e_shstrndx(e.endian()),
+        };
+        let index = match index {
+            SHN_XINDEX => self
+                .get_shdr(0)
+                .ok_or(ELF_E_INVALID_SECTION_HEADER)?
+                .sh_link
+                .get(NativeEndian) as usize,
+            index => usize::from(index),
+        };
+        if index != 0 && index >= self.section_count()? {
+            return Err(ELF_E_INVALID_SECTION_HEADER);
+        }
+        Ok(index)
+    }
+
     pub fn get_ehdr(&self) -> GElf_Ehdr {
-        let mut result: GElf_Ehdr = unsafe { std::mem::zeroed() };
         match self {
-            ParsedElf::Elf32(e) => {
-                let h = e.elf_header();
-                let endian = e.endian();
-                result.e_ident = *h.e_ident();
-                result.e_type.set(NativeEndian, h.e_type(endian));
-                result.e_machine.set(NativeEndian, h.e_machine(endian));
-                result.e_version.set(NativeEndian, h.e_version(endian));
-                result.e_entry.set(NativeEndian, h.e_entry(endian).into());
-                result.e_phoff.set(NativeEndian, h.e_phoff(endian).into());
-                result.e_shoff.set(NativeEndian, h.e_shoff(endian).into());
-                result.e_flags.set(NativeEndian, h.e_flags(endian));
-                result.e_ehsize.set(NativeEndian, h.e_ehsize(endian));
-                result.e_phentsize.set(NativeEndian, h.e_phentsize(endian));
-                result.e_phnum.set(NativeEndian, h.e_phnum(endian));
-                result.e_shentsize.set(NativeEndian, h.e_shentsize(endian));
-                result.e_shnum.set(NativeEndian, h.e_shnum(endian));
-                result.e_shstrndx.set(NativeEndian, h.e_shstrndx(endian));
-            }
-            ParsedElf::Elf64(e) => {
-                let h = e.elf_header();
-                let endian = e.endian();
-                result.e_ident = *h.e_ident();
-                result.e_type.set(NativeEndian, h.e_type(endian));
-                result.e_machine.set(NativeEndian, h.e_machine(endian));
-                result.e_version.set(NativeEndian, h.e_version(endian));
-                result.e_entry.set(NativeEndian, h.e_entry(endian));
-                result.e_phoff.set(NativeEndian, h.e_phoff(endian));
-                result.e_shoff.set(NativeEndian, h.e_shoff(endian));
-                result.e_flags.set(NativeEndian, h.e_flags(endian));
-                result.e_ehsize.set(NativeEndian, h.e_ehsize(endian));
-                result.e_phentsize.set(NativeEndian, h.e_phentsize(endian));
-                result.e_phnum.set(NativeEndian, h.e_phnum(endian));
-                result.e_shentsize.set(NativeEndian, h.e_shentsize(endian));
-                result.e_shnum.set(NativeEndian, h.e_shnum(endian));
-                result.e_shstrndx.set(NativeEndian, h.e_shstrndx(endian));
-            }
+            ParsedElf::Elf32(e) => to_gelf_ehdr(e.elf_header(), e.endian()),
+            ParsedElf::Elf64(e) => to_gelf_ehdr(e.elf_header(), e.endian()),
         }
-        result
     }
 
     pub fn get_shdr(&self, index: usize) -> Option<GElf_Shdr> {
-        let mut result: GElf_Shdr = unsafe { std::mem::zeroed() };
         match self {
             ParsedElf::Elf32(e) => {
-                let table = e.elf_section_table();
+                let table = e.elf_section_table().ok()?;
                 let endian = e.endian();
-                table.section(SectionIndex(index)).ok().map(|s| {
-                    result.sh_name.set(NativeEndian, s.sh_name(endian));
-                    result.sh_type.set(NativeEndian, s.sh_type(endian));
-                    result.sh_flags.set(NativeEndian, s.sh_flags(endian).into());
-                    result.sh_addr.set(NativeEndian, s.sh_addr(endian).into());
-                    result
-                        .sh_offset
-                        .set(NativeEndian, s.sh_offset(endian).into());
-                    result.sh_size.set(NativeEndian, s.sh_size(endian).into());
-                    result.sh_link.set(NativeEndian, s.sh_link(endian));
-                    result.sh_info.set(NativeEndian, s.sh_info(endian));
-                    result
-                        .sh_addralign
-                        .set(NativeEndian, s.sh_addralign(endian).into());
-                    result
-                        .sh_entsize
-                        .set(NativeEndian, s.sh_entsize(endian).into());
-                    result
-                })
+                table
+                    .iter()
+                    .as_slice()
+                    .get(index)
+                    .map(|s| to_gelf_shdr(s, endian))
             }
             ParsedElf::Elf64(e) => {
-                let table = e.elf_section_table();
+                let table = e.elf_section_table().ok()?;
                 let endian = e.endian();
-                table.section(SectionIndex(index)).ok().map(|s| {
-                    result.sh_name.set(NativeEndian, s.sh_name(endian));
-                    result.sh_type.set(NativeEndian, s.sh_type(endian));
-                    result.sh_flags.set(NativeEndian, s.sh_flags(endian));
-                    result.sh_addr.set(NativeEndian, s.sh_addr(endian));
-                    result.sh_offset.set(NativeEndian, s.sh_offset(endian));
-                    result.sh_size.set(NativeEndian, s.sh_size(endian));
-                    result.sh_link.set(NativeEndian, s.sh_link(endian));
-                    result.sh_info.set(NativeEndian, s.sh_info(endian));
-                    result
-                        .sh_addralign
-                        .set(NativeEndian, s.sh_addralign(endian));
-                    result.sh_entsize.set(NativeEndian, s.sh_entsize(endian));
-                    result
-                })
+                table
+                    .iter()
+                    .as_slice()
+                    .get(index)
+                    .map(|s| to_gelf_shdr(s, endian))
             }
         }
     }
@@ -154,7 +265,7 @@ impl<'a> ParsedElf<'a> {
         let mut result: GElf_Phdr = unsafe { std::mem::zeroed() };
         match self {
             ParsedElf::Elf32(e) => {
-                let phdrs = e.elf_program_headers();
+                let phdrs = e.elf_program_headers().ok()?;
                 let endian = e.endian();
                 phdrs.get(index).map(|p| {
                     result.p_type.set(NativeEndian, p.p_type(endian));
@@ -169,7 +280,7 @@ impl<'a> ParsedElf<'a> {
                 })
             }
             ParsedElf::Elf64(e) => {
-                let phdrs = e.elf_program_headers();
+                let phdrs = e.elf_program_headers().ok()?;
                 let endian = e.endian();
                 phdrs.get(index).map(|p| {
                     result.p_type.set(NativeEndian, p.p_type(endian));
@@ -189,72 +300,573 @@ impl<'a> ParsedElf<'a> {
     pub fn section_data(&self, index: usize) -> Option<&'a [u8]> {
         match self {
             ParsedElf::Elf32(e) => {
-                let table = e.elf_section_table();
-                let data = e.data();
-                table
-                    .section(SectionIndex(index))
-                    .ok()
-                    .and_then(|s| s.data(e.endian(), data).ok())
+                let table = e.elf_section_table().ok()?;
+                let section = table.iter().as_slice().get(index)?;
+                section.data(e.endian(), e.data()).ok()
             }
             ParsedElf::Elf64(e) => {
-                let table = e.elf_section_table();
-                let data = e.data();
-                table
-                    .section(SectionIndex(index))
-                    .ok()
-                    .and_then(|s| s.data(e.endian(), data).ok())
+                let table = e.elf_section_table().ok()?;
+                let section = table.iter().as_slice().get(index)?;
+                section.data(e.endian(), e.data()).ok()
             }
         }
     }
+
+    /// The name of section `index`, from the section header string table.
+    pub fn section_name(&self, index: usize) -> Option<&'a [u8]> {
+        match self {
+            ParsedElf::Elf32(e) => {
+                let table = e.elf_section_table().ok()?;
+                let section = table.iter().as_slice().get(index)?;
+                table.section_name(e.endian(), section).ok()
+            }
+            ParsedElf::Elf64(e) => {
+                let table = e.elf_section_table().ok()?;
+                let section = table.iter().as_slice().get(index)?;
+                table.section_name(e.endian(), section).ok()
+            }
+        }
+    }
+
+    /// Finds a dynamic symbol through `.gnu.hash` or, failing that, `.hash`.
+    /// Without `version`, only the default version of a symbol matches, as
+    /// with `dlsym`.
+    pub fn lookup_dynsym(
+        &self,
+        name: &[u8],
+        version: Option<&[u8]>,
+    ) -> Result<Option<(usize, GElf_Sym)>, ElfError> {
+        match self {
+            ParsedElf::Elf32(e) => lookup_dynsym(e, name, version),
+            ParsedElf::Elf64(e) => lookup_dynsym(e, name, version),
+        }
+    }
+}
+
+pub fn to_gelf_ehdr<H: FileHeader<Endian = Endianness>>(h: &H, endian: Endianness) -> GElf_Ehdr {
+    let mut result: GElf_Ehdr = unsafe { std::mem::zeroed() };
+    result.e_ident = *h.e_ident();
+    result.e_type.set(NativeEndian, h.e_type(endian));
+    result.e_machine.set(NativeEndian, h.e_machine(endian));
+    result.e_version.set(NativeEndian, h.e_version(endian));
+    result.e_entry.set(NativeEndian, h.e_entry(endian).into());
+    result.e_phoff.set(NativeEndian, h.e_phoff(endian).into());
+    result.e_shoff.set(NativeEndian, h.e_shoff(endian).into());
+    result.e_flags.set(NativeEndian, h.e_flags(endian));
+    result.e_ehsize.set(NativeEndian, h.e_ehsize(endian));
+    result.e_phentsize.set(NativeEndian, h.e_phentsize(endian));
+    result.e_phnum.set(NativeEndian, h.e_phnum(endian));
+    result.e_shentsize.set(NativeEndian, h.e_shentsize(endian));
+    result.e_shnum.set(NativeEndian, h.e_shnum(endian));
+    result.e_shstrndx.set(NativeEndian, h.e_shstrndx(endian));
+    result
+}
+
+pub fn to_gelf_shdr<S: SectionHeader<Endian = Endianness>>(s: &S, endian: Endianness) -> GElf_Shdr {
+    let mut result: GElf_Shdr = unsafe { std::mem::zeroed() };
+    result.sh_name.set(NativeEndian, s.sh_name(endian));
+    result.sh_type.set(NativeEndian, s.sh_type(endian));
+    result.sh_flags.set(NativeEndian, s.sh_flags(endian).into());
+    result.sh_addr.set(NativeEndian, s.sh_addr(endian).into());
+    result
+        .sh_offset
+        .set(NativeEndian, s.sh_offset(endian).into());
+    result.sh_size.set(NativeEndian, s.sh_size(endian).into());
+    result.sh_link.set(NativeEndian, s.sh_link(endian));
+    result.sh_info.set(NativeEndian, s.sh_info(endian));
+    result
+        .sh_addralign
+        .set(NativeEndian, s.sh_addralign(endian).into());
+    result
+        .sh_entsize
+        .set(NativeEndian, s.sh_entsize(endian).into());
+    result
+}
+
+fn to_gelf_sym<S: Sym<Endian = Endianness>>(sym: &S, endian: Endianness) -> GElf_Sym {
+    let mut result: GElf_Sym = unsafe { std::mem::zeroed() };
+    result.st_name.set(NativeEndian, sym.st_name(endian));
+    result.st_info = sym.st_info();
+    result.st_other = sym.st_other();
+    result.st_shndx.set(NativeEndian, sym.st_shndx(endian));
+    result
+        .st_value
+        .set(NativeEndian, sym.st_value(endian).into());
+    result.st_size.set(NativeEndian, sym.st_size(endian).into());
+    result
+}
+
+/// Index of the version called `name` in `.gnu.version_d` or `.gnu.version_r`.
+fn version_index<Elf: FileHeader<Endian = Endianness>>(
+    file: &ElfImage<'_, Elf>,
+    name: &[u8],
+) -> Result<Option<u16>, ElfError> {
+    let endian = file.endian();
+    let data = file.data();
+    let sections = file.elf_section_table()?;
+    let err = |_| ELF_E_INVALID_SECTION;
+
+    if let Some((mut verdefs, link)) = sections.gnu_verdef(endian, data).map_err(err)? {
+        let strings = sections.strings(endian, data, link).map_err(err)?;
+        while let Some((verdef, mut verdauxs)) = verdefs.next().map_err(err)? {
+            if let Some(verdaux) = verdauxs.next().map_err(err)?
+                && verdaux.name(endian, strings).map_err(err)? == name
+            {
+                return Ok(Some(verdef.vd_ndx.get(endian)));
+            }
+        }
+    }
+
+    if let Some((mut verneeds, link)) = sections.gnu_verneed(endian, data).map_err(err)? {
+        let strings = sections.strings(endian, data, link).map_err(err)?;
+        while let Some((_, mut vernauxs)) = verneeds.next().map_err(err)? {
+            while let Some(vernaux) = vernauxs.next().map_err(err)? {
+                if vernaux.name(endian, strings).map_err(err)? == name {
+                    return Ok(Some(vernaux.vna_other.get(endian)));
+                }
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+fn lookup_dynsym<Elf: FileHeader<Endian = Endianness>>(
+    file: &ElfImage<'_, Elf>,
+    name: &[u8],
+    version: Option<&[u8]>,
+) -> Result<Option<(usize, GElf_Sym)>, ElfError> {
+    let endian = file.endian();
+    let data = file.data();
+    let sections = file.elf_section_table()?;
+    let err = |_| ELF_E_INVALID_SECTION;
+
+    let symbols = sections
+        .symbols(endian, data, object::elf::SHT_DYNSYM)
+        .map_err(err)?;
+    let versions = sections
+        .versions(endian, data)
+        .map_err(err)?
+        .unwrap_or_else(VersionTable::default);
+
+    let wanted = match version {
+        Some(v) => match version_index(file, v)? {
+            Some(idx) => versions.version(VersionIndex(idx)).ok().flatten(),
+            None => return Ok(None),
+        },
+        None => None,
+    };
+
+    let found = if let Some((table, _)) = sections.gnu_hash(endian, data).map_err(err)? {
+        let hash = object::elf::gnu_hash(name);
+        table.find(endian, name, hash, wanted, &symbols, &versions)
+    } else if let Some((table, _)) = sections.hash(endian, data).map_err(err)? {
+        let hash = object::elf::hash(name);
+        table.find(endian, name, hash, wanted, &symbols, &versions)
+    } else {
+        return Err(ELF_E_NO_INDEX);
+    };
+
+    Ok(found.map(|(index, sym)| (index.0, to_gelf_sym(sym, endian))))
+}
+
+/// The header `elf32_newehdr` or `elf64_newehdr` created, in memory
+/// order. Its class is the class of the output; its `EI_DATA` the byte
+/// order.
+pub enum WriteEhdr {
+    Elf32(Box<FileHeader32<Endianness>>),
+    Elf64(Box<FileHeader64<Endianness>>),
+}
+
+impl WriteEhdr {
+    pub fn elf64(&self) -> bool {
+        matches!(self, WriteEhdr::Elf64(_))
+    }
+
+    pub fn class(&self) -> u8 {
+        if self.elf64() { ELFCLASS64 } else { ELFCLASS32 }
+    }
+
+    pub fn as_ptr(&mut self) -> *mut c_void {
+        match self {
+            WriteEhdr::Elf32(h) => h.as_mut() as *mut _ as *mut c_void,
+            WriteEhdr::Elf64(h) => h.as_mut() as *mut _ as *mut c_void,
+        }
+    }
+
+    pub fn e_ident(&mut self) -> &mut object::elf::Ident {
+        match self {
+            WriteEhdr::Elf32(h) => &mut h.e_ident,
+            WriteEhdr::Elf64(h) => &mut h.e_ident,
+        }
+    }
+
+    /// The byte order `EI_DATA` asks for, if it is a valid one.
+    pub fn encoding(&self) -> Option<Endianness> {
+        let data = match self {
+            WriteEhdr::Elf32(h) => h.e_ident.data,
+            WriteEhdr::Elf64(h) => h.e_ident.data,
+        };
+        match data {
+            ELFDATA2LSB => Some(Endianness::Little),
+            ELFDATA2MSB => Some(Endianness::Big),
+            _ => None,
+        }
+    }
+
+    pub fn get(&self) -> GElf_Ehdr {
+        match self {
+            WriteEhdr::Elf32(h) => to_gelf_ehdr(h.as_ref(), Endianness::default()),
+            WriteEhdr::Elf64(h) => to_gelf_ehdr(h.as_ref(), Endianness::default()),
+        }
+    }
+}
+
+/// The header of a section added with `elf_newscn`, in memory order and
+/// in the class of the output. `elf_update` lays out its offset, size and
+/// alignment and writes the other fields as the application set them.
+pub enum WriteShdr {
+    Elf32(Box<SectionHeader32<Endianness>>),
+    Elf64(Box<SectionHeader64<Endianness>>),
+}
+
+impl WriteShdr {
+    /// A zeroed header of class `elf64`.
+    pub fn new(elf64: bool) -> Self {
+        if elf64 {
+            WriteShdr::Elf64(Box::new(unsafe { std::mem::zeroed() }))
+        } else {
+            WriteShdr::Elf32(Box::new(unsafe { std::mem::zeroed() }))
+        }
+    }
+
+    pub fn as_ptr(&mut self) -> *mut c_void {
+        match self {
+            WriteShdr::Elf32(h) => h.as_mut() as *mut _ as *mut c_void,
+            WriteShdr::Elf64(h) => h.as_mut() as *mut _ as *mut c_void,
+        }
+    }
+
+    pub fn get(&self) -> GElf_Shdr {
+        match self {
+            WriteShdr::Elf32(h) => to_gelf_shdr(h.as_ref(), Endianness::default()),
+            WriteShdr::Elf64(h) => to_gelf_shdr(h.as_ref(), Endianness::default()),
+        }
+    }
+
+    /// Replaces the header with `src`, failing with `ELF_E_INVALID_DATA`
+    /// and leaving it as it was if a field doesn't fit an ELF32 header.
+    pub fn set(&mut self, src: &GElf_Shdr) -> Result<(), ElfError> {
+        let e = Endianness::default();
+        match self {
+            WriteShdr::Elf32(h) => {
+                let word = |v: u64| u32::try_from(v).map_err(|_| ELF_E_INVALID_DATA);
+                **h = SectionHeader32 {
+                    sh_name: U32::new(e, src.sh_name.get(NativeEndian)),
+                    sh_type: U32::new(e, src.sh_type.get(NativeEndian)),
+                    sh_flags: U32::new(e, word(src.sh_flags.get(NativeEndian))?),
+                    sh_addr: U32::new(e, word(src.sh_addr.get(NativeEndian))?),
+                    sh_offset: U32::new(e, word(src.sh_offset.get(NativeEndian))?),
+                    sh_size: U32::new(e, word(src.sh_size.get(NativeEndian))?),
+                    sh_link: U32::new(e, src.sh_link.get(NativeEndian)),
+                    sh_info: U32::new(e, src.sh_info.get(NativeEndian)),
+                    sh_addralign: U32::new(e, word(src.sh_addralign.get(NativeEndian))?),
+                    sh_entsize: U32::new(e, word(src.sh_entsize.get(NativeEndian))?),
+                };
+            }
+            WriteShdr::Elf64(h) => {
+                **h = SectionHeader64 {
+                    sh_name: U32::new(e, src.sh_name.get(NativeEndian)),
+                    sh_type: U32::new(e, src.sh_type.get(NativeEndian)),
+                    sh_flags: U64::new(e, src.sh_flags.get(NativeEndian)),
+                    sh_addr: U64::new(e, src.sh_addr.get(NativeEndian)),
+                    sh_offset: U64::new(e, src.sh_offset.get(NativeEndian)),
+                    sh_size: U64::new(e, src.sh_size.get(NativeEndian)),
+                    sh_link: U32::new(e, src.sh_link.get(NativeEndian)),
+                    sh_info: U32::new(e, src.sh_info.get(NativeEndian)),
+                    sh_addralign: U64::new(e, src.sh_addralign.get(NativeEndian)),
+                    sh_entsize: U64::new(e, src.sh_entsize.get(NativeEndian)),
+                };
+            }
+        }
+        Ok(())
+    }
+
+    /// Records where `elf_update` put the section, as libelf does.
+    pub fn place(&mut self, offset: usize, size: usize, align: usize) {
+        let e = Endianness::default();
+        match self {
+            WriteShdr::Elf32(h) => {
+                h.sh_offset.set(e, offset as u32);
+                h.sh_size.set(e, size as u32);
+                h.sh_addralign.set(e, align as u32);
+            }
+            WriteShdr::Elf64(h) => {
+                h.sh_offset.set(e, offset as u64);
+                h.sh_size.set(e, size as u64);
+                h.sh_addralign.set(e, align as u64);
+            }
+        }
+    }
+}
+
+/// The size and alignment of a section added with `elf_newscn`, as of the
+/// last `elf_update` that found it dirty. The contents stay in the data
+/// chain until they are written.
+#[derive(Default)]
+pub struct WriteSection {
+    pub size: usize,
+    pub align: usize,
 }
 
 pub struct WriteState {
-    pub obj: WriteObject<'static>,
-    pub sections: Vec<object::write::SectionId>,
-    pub section_data: Vec<Vec<u8>>,
+    pub sections: Vec<WriteSection>,
     pub shstrtab_idx: Option<usize>,
-    pub ehdr64: Option<Box<object::elf::FileHeader64<Endianness>>>,
+    pub ehdr: Option<WriteEhdr>,
+}
+
+pub struct RawChunk {
+    pub offset: i64,
+    pub size: usize,
+    pub ty: ElfType,
+    pub data: *mut Elf_Data,
 }
 
+/// A descriptor may be shared between threads for reading: the lazily
+/// built state below is behind a `OnceLock` or a mutex, and read paths only
+/// take `&Elf`. Writing still needs exclusive access.
 pub struct Elf {
-    pub fd: i32,
-    pub cmd: ElfCmd,
-    pub data: *const u8,
-    pub data_len: usize,
-    pub owned_data: Option<Vec<u8>>,
-    pub mmap: Option<memmap2::MmapRaw>,
-    pub parsed: Option<Box<ParsedElfOwned>>,
-    pub section_handles: Vec<*mut Elf_Scn>,
-    pub data_handles: Vec<*mut Elf_Data>,
-    pub section_data_cache: Vec<Vec<u8>>,
-    pub writer: Option<WriteState>,
+    pub(crate) fd: i32,
+    pub(crate) cmd: ElfCmd,
+    pub(crate) ref_count: AtomicU32,
+    pub(crate) flags: AtomicU32,
+    pub(crate) ehdr_flags: AtomicU32,
+    pub(crate) phdr_flags: AtomicU32,
+    pub(crate) data: *const u8,
+    pub(crate) data_len: usize,
+    pub(crate) owned_data: Option<Vec<u8>>,
+    pub(crate) mmap: Option<memmap2::MmapRaw>,
+    pub(crate) parsed: OnceLock<Box<ElfFile<'static>>>,
+    kind: OnceLock<ElfKind>,
+    pub(crate) section_handles: Mutex<Vec<*mut Elf_Scn>>,
+    pub(crate) data_handles: Mutex<Vec<*mut Elf_Data_Scn>>,
+    pub(crate) section_data_cache: Mutex<Vec<Vec<u8>>>,
+    pub(crate) rawchunks: Mutex<Vec<RawChunk>>,
+    /// Held while a section's data chain is first filled in.
+    load_lock: Mutex<()>,
+    pub(crate) writer: Option<WriteState>,
+    /// The descriptor this one was cloned from, kept alive until this one
+    /// is ended. Null for anything `elf_clone` didn't make.
+    pub(crate) source: *mut Elf,
 }
 
-pub struct ParsedElfOwned {
-    inner: ParsedElf<'static>,
+/// Locks `mutex`, ignoring poisoning: every critical section leaves the
+/// state consistent before anything that could panic.
+pub(crate) fn locked<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
 }
 
-impl ParsedElfOwned {
-    pub unsafe fn new(data: &[u8]) -> Result<Self, &'static str> {
-        let static_data: &'static [u8] = unsafe { std::mem::transmute(data) };
-        ParsedElf::parse(static_data).map(|inner| Self { inner })
+impl Elf {
+    pub(crate) fn new(fd: i32, cmd: ElfCmd) -> Self {
+        Self {
+            fd,
+            cmd,
+            ref_count: AtomicU32::new(1),
+            flags: AtomicU32::new(0),
+            ehdr_flags: AtomicU32::new(0),
+            phdr_flags: AtomicU32::new(0),
+            data: std::ptr::null(),
+            data_len: 0,
+            owned_data: None,
+            mmap: None,
+            parsed: OnceLock::new(),
+            kind: OnceLock::new(),
+            section_handles: Mutex::default(),
+            data_handles: Mutex::default(),
+            section_data_cache: Mutex::default(),
+            rawchunks: Mutex::default(),
+            load_lock: Mutex::default(),
+            writer: None,
+            source: std::ptr::null_mut(),
+        }
     }
 
-    pub fn get(&self) -> &ParsedElf<'static> {
-        &self.inner
+    /// A descriptor for the same file as `source`, as `elf_clone` makes:
+    /// it shares the command, the kind and the image, so it reads as the
+    /// same class, but no section has been handed out from it yet, and it
+    /// starts out dirty. The image is borrowed, so the clone takes a
+    /// reference to `source` that `elf_end` gives back.
+    pub(crate) fn clone_of(source: *mut Elf) -> Self {
+        let source_ref = unsafe { &*source };
+        let mut elf = Elf::new(source_ref.fd, source_ref.cmd);
+        elf.data = source_ref.data;
+        elf.data_len = source_ref.data_len;
+        elf.kind = OnceLock::from(source_ref.kind());
+        elf.flags = AtomicU32::new(ELF_F_DIRTY);
+        source_ref.ref_count.fetch_add(1, Ordering::AcqRel);
+        elf.source = source;
+        elf
     }
-}
 
-impl Elf {
-    pub fn ensure_parsed(&mut self) -> bool {
-        if self.parsed.is_some() {
+    /// Whether `flag` is set with `elf_flagelf`.
+    pub(crate) fn flag(&self, flag: u32) -> bool {
+        self.flags.load(Ordering::Relaxed) & flag != 0
+    }
+
+    pub(crate) fn set_owned_data(&mut self, buf: Vec<u8>) {
+        self.data = buf.as_ptr();
+        self.data_len = buf.len();
+        self.owned_data = Some(buf);
+    }
+
+    /// Takes ownership of a new data descriptor for `scn` (null for data not
+    /// tied to a section) and returns the public view of it.
+    pub(crate) fn alloc_data(&self, d: Elf_Data, scn: *mut Elf_Scn) -> *mut Elf_Data {
+        let data_ptr = Box::into_raw(Box::new(Elf_Data_Scn {
+            d,
+            flags: AtomicU32::new(0),
+            elf: self as *const Elf as *mut Elf,
+            scn,
+            next: std::ptr::null_mut(),
+        }));
+        locked(&self.data_handles).push(data_ptr);
+        data_ptr as *mut Elf_Data
+    }
+
+    /// The descriptor for section `index`, created on first use so that
+    /// every lookup of a section shares one data chain.
+    pub(crate) fn scn_handle(&self, index: usize) -> *mut Elf_Scn {
+        let mut handles = locked(&self.section_handles);
+        let existing = handles
+            .iter()
+            .copied()
+            .find(|&scn| unsafe { (*scn).index } == index);
+        if let Some(scn) = existing {
+            return scn;
+        }
+        let elf = self as *const Elf as *mut Elf;
+        let scn = Box::into_raw(Box::new(Elf_Scn::new(elf, index)));
+        handles.push(scn);
+        scn
+    }
+
+    /// The descriptor for section `index` if one has been handed out.
+    pub(crate) fn find_scn(&self, index: usize) -> Option<*mut Elf_Scn> {
+        locked(&self.section_handles)
+            .iter()
+            .copied()
+            .find(|&scn| unsafe { (*scn).index } == index)
+    }
+
+    /// Puts the file contents of `scn` at the head of its data chain, once.
+    /// Sections created with `elf_newscn` have nothing to load.
+    pub(crate) fn load_scn_data(&self, scn: *mut Elf_Scn) -> Result<(), ElfError> {
+        if self.data.is_null() {
+            return Ok(());
+        }
+        let _guard = locked(&self.load_lock);
+        let scn_ref = unsafe { &mut *scn };
+        if scn_ref.data_read {
+            return Ok(());
+        }
+        if !self.ensure_parsed() {
+            return Err(get_error());
+        }
+
+        let index = scn_ref.index;
+        let shdr = self
+            .with_parsed(|p| p.section_header(index))
+            .flatten()
+            .ok_or(ELF_E_INVALID_SECTION)?;
+        let mut d = Elf_Data {
+            d_align: (shdr.sh_addralign.get(NativeEndian) as usize).max(1),
+            ..Elf_Data::default()
+        };
+
+        // NOBITS sections occupy no file space: no buffer, but the size
+        // they take in memory
+        if shdr.sh_type.get(NativeEndian) == SHT_NOBITS {
+            d.d_size = shdr.sh_size.get(NativeEndian) as usize;
+        } else {
+            let (ptr, len) = self
+                .with_parsed(|p| p.section_data(index).map(|d| (d.as_ptr(), d.len())))
+                .flatten()
+                .ok_or(ELF_E_INVALID_SECTION)?;
+            let bytes = unsafe { std::slice::from_raw_parts(ptr, len) };
+            // Hand out the image itself where it may be written through, so
+            // edits land in the caller's buffer or the mapping
+            let image = self.image().as_ptr_range();
+            if self.image_writable() && image.contains(&bytes.as_ptr()) {
+                d.d_buf = bytes.as_ptr() as *mut c_void;
+                d.d_size = bytes.len();
+            } else {
+                limits::check_data_size(bytes.len())?;
+                let mut cache = locked(&self.section_data_cache);
+                cache.push(bytes.to_vec());
+                let cached = cache.last_mut().unwrap();
+                d.d_buf = cached.as_mut_ptr() as *mut c_void;
+                d.d_size = cached.len();
+            }
+        }
+        let data = self.alloc_data(d, scn) as *mut Elf_Data_Scn;
+        unsafe { (*data).next = scn_ref.data_list_head };
+        scn_ref.data_list_head = data;
+        scn_ref.data_read = true;
+        Ok(())
+    }
+
+    /// The tables `PT_DYNAMIC` points at; see [`ElfFile::dynamic`].
+    pub(crate) fn dynamic(&self) -> Result<Dynamic<'_>, ElfError> {
+        self.file()?.dynamic()
+    }
+
+    /// What the image holds, from its leading bytes, worked out once.
+    pub(crate) fn kind(&self) -> ElfKind {
+        *self
+            .kind
+            .get_or_init(|| match FileKind::parse(self.image()) {
+                Ok(FileKind::Elf32) | Ok(FileKind::Elf64) => ELF_K_ELF,
+                Ok(FileKind::Archive) => ELF_K_AR,
+                _ => ELF_K_NONE,
+            })
+    }
+
+    /// Whether the image may be written through: everything but a
+    /// read-only mapping of the file.
+    pub(crate) fn image_writable(&self) -> bool {
+        let read_only_map = self.mmap.is_some() && self.cmd == ELF_C_READ_MMAP;
+        !self.data.is_null() && !read_only_map
+    }
+
+    pub(crate) fn image_mut(&mut self) -> Option<&mut [u8]> {
+        if !self.image_writable() {
+            return None;
+        }
+        Some(unsafe { std::slice::from_raw_parts_mut(self.data as *mut u8, self.data_len) })
+    }
+
+    /// The whole image, empty for descriptors opened for writing.
+    pub(crate) fn image(&self) -> &[u8] {
+        if self.data.is_null() {
+            return &[];
+        }
+        unsafe { std::slice::from_raw_parts(self.data, self.data_len) }
+    }
+
+    /// Reads the ELF header on first use. The tables are decoded when first
+    /// asked for, permissively if `ELF_F_PERMISSIVE` is set by then; see
+    /// `elf_flagelf`. Threads racing here may each read it, but only one result
+    /// is kept.
+    pub(crate) fn ensure_parsed(&self) -> bool {
+        if self.parsed.get().is_some() {
             return true;
         }
 
-        let data = unsafe { std::slice::from_raw_parts(self.data, self.data_len) };
-        match unsafe { ParsedElfOwned::new(data) } {
+        let permissive = self.flag(ELF_F_PERMISSIVE);
+        // The image outlives the parsed file: both go with the descriptor
+        match unsafe { ElfFile::from_image(self.image(), permissive, None) } {
             Ok(parsed) => {
-                self.parsed = Some(Box::new(parsed));
+                let _ = self.parsed.set(Box::new(parsed));
                 true
             }
             Err(e) => {
@@ -264,19 +876,89 @@ impl Elf {
         }
     }
 
-    pub fn with_parsed<T>(&self, f: impl FnOnce(&ParsedElf) -> T) -> Option<T> {
-        self.parsed.as_ref().map(|p| f(p.get()))
+    pub(crate) fn with_parsed<T>(&self, f: impl FnOnce(&ElfFile) -> T) -> Option<T> {
+        self.parsed.get().map(|p| f(p))
+    }
+
+    /// The parsed image, parsing it if nothing has yet.
+    pub(crate) fn file(&self) -> Result<&ElfFile<'static>, ElfError> {
+        if !self.ensure_parsed() {
+            return Err(get_error());
+        }
+        self.parsed.get().map(|p| &**p).ok_or(ELF_E_INVALID_HANDLE)
+    }
+}
+
+/// An `Elf` descriptor over an in-memory image, for Rust callers. A
+/// borrowed image must outlive the descriptor, and is where edits made
+/// through it and `elf_update` end up.
+pub struct ElfMemory<'a> {
+    elf: *mut Elf,
+    _image: PhantomData<&'a mut [u8]>,
+}
+
+impl<'a> ElfMemory<'a> {
+    /// Works on `image` in place, like `elf_memory`.
+    pub fn borrowed(image: &'a mut [u8]) -> Result<Self, ElfError> {
+        let elf = crate::read::elf_memory(image.as_mut_ptr() as *mut i8, image.len());
+        if elf.is_null() {
+            return Err(get_error());
+        }
+        Ok(Self {
+            elf,
+            _image: PhantomData,
+        })
+    }
+
+    /// Takes ownership of `image`; it is freed with the descriptor.
+    pub fn owned(image: Vec<u8>) -> Result<ElfMemory<'static>, ElfError> {
+        if !crate::read::version_set() {
+            return Err(get_error());
+        }
+        if image.is_empty() {
+            return Err(ELF_E_INVALID_OPERAND);
+        }
+        let mut elf = Box::new(Elf::new(-1, ELF_C_RDWR_MMAP));
+        elf.set_owned_data(image);
+        Ok(ElfMemory {
+            elf: Box::into_raw(elf),
+            _image: PhantomData,
+        })
+    }
+
+    /// The descriptor, for use with the C API. It stays valid as long as
+    /// `self` does.
+    pub fn as_ptr(&self) -> *mut Elf {
+        self.elf
+    }
+
+    pub fn image(&self) -> &[u8] {
+        unsafe { (*self.elf).image() }
+    }
+}
+
+impl Drop for ElfMemory<'_> {
+    fn drop(&mut self) {
+        crate::read::elf_end(self.elf);
     }
 }
 
 impl Drop for Elf {
     fn drop(&mut self) {
-        for handle in self.section_handles.drain(..) {
+        let section_handles = self.section_handles.get_mut();
+        for handle in section_handles
+            .unwrap_or_else(PoisonError::into_inner)
+            .drain(..)
+        {
             unsafe {
                 drop(Box::from_raw(handle));
             }
         }
-        for handle in self.data_handles.drain(..) {
+        let data_handles = self.data_handles.get_mut();
+        for handle in data_handles
+            .unwrap_or_else(PoisonError::into_inner)
+            .drain(..)
+        {
             unsafe {
                 drop(Box::from_raw(handle));
             }
@@ -286,19 +968,102 @@ impl Drop for Elf {
 
 #[repr(C)]
 pub struct Elf_Scn {
-    pub elf: *mut Elf,
-    pub index: usize,
-    pub data_list_head: *mut Elf_Data,
-    pub flags: u32,
+    pub(crate) elf: *mut Elf,
+    pub(crate) index: usize,
+    pub(crate) data_list_head: *mut Elf_Data_Scn,
+    /// Whether the file contents are in the data chain yet.
+    pub(crate) data_read: bool,
+    pub(crate) flags: AtomicU32,
+    pub(crate) shdr_flags: AtomicU32,
+    /// The header of a section added with `elf_newscn`, once asked for.
+    pub(crate) shdr: Option<WriteShdr>,
 }
 
 impl Elf_Scn {
-    pub fn new(elf: *mut Elf, index: usize) -> Self {
+    pub(crate) fn new(elf: *mut Elf, index: usize) -> Self {
         Self {
             elf,
             index,
             data_list_head: std::ptr::null_mut(),
-            flags: 0,
+            data_read: false,
+            flags: AtomicU32::new(0),
+            shdr_flags: AtomicU32::new(0),
+            shdr: None,
+        }
+    }
+
+    /// Pairs each data descriptor with its offset in the section: `d_off`
+    /// when the application controls the layout, otherwise the offset
+    /// `elf_update` will place it at.
+    pub(crate) fn placed_data(&self, layout: bool) -> impl Iterator<Item = (usize, &Elf_Data_Scn)> {
+        let mut end = 0usize;
+        self.data_chain().map(move |data| {
+            let start = if layout {
+                data.d.d_off.max(0) as usize
+            } else {
+                end.checked_next_multiple_of(data.d.d_align.max(1))
+                    .unwrap_or(usize::MAX)
+            };
+            end = start.saturating_add(data.d.d_size);
+            (start, data)
+        })
+    }
+
+    /// Iterates the section's data descriptors, in order. Readers may share
+    /// a section, so this only ever hands out shared references.
+    pub(crate) fn data_chain(&self) -> impl Iterator<Item = &Elf_Data_Scn> {
+        let mut cur = self.data_list_head;
+        std::iter::from_fn(move || {
+            if cur.is_null() {
+                return None;
+            }
+            let item = unsafe { &*cur };
+            cur = item.next;
+            Some(item)
+        })
+    }
+
+    /// Like `data_chain`, for the write side, which has the section to
+    /// itself.
+    pub(crate) fn data_chain_mut(&mut self) -> impl Iterator<Item = &mut Elf_Data_Scn> {
+        let mut cur = self.data_list_head;
+        std::iter::from_fn(move || {
+            if cur.is_null() {
+                return None;
+            }
+            let item = unsafe { &mut *cur };
+            cur = item.next;
+            Some(item)
+        })
+    }
+}
+
+/// Every `Elf_Data` handed out is the head of one of these, so a pointer to
+/// either can be cast to the other, as with libelf's own `Elf_Data_Scn`.
+#[repr(C)]
+pub struct Elf_Data_Scn {
+    pub(crate) d: Elf_Data,
+    pub(crate) flags: AtomicU32,
+    pub(crate) elf: *mut Elf,
+    pub(crate) scn: *mut Elf_Scn,
+    pub(crate) next: *mut Elf_Data_Scn,
+}
+
+impl Elf_Data_Scn {
+    /// Class and byte order of the buffer. `ELF_T_BYTE` data is handed out
+    /// as read from the file; any other type has been translated to the
+    /// host's byte order.
+    pub(crate) fn layout(&self) -> (bool, Endianness) {
+        let elf = unsafe { self.elf.as_ref() };
+        let file = elf.and_then(|e| e.with_parsed(|p| (p.is_elf64(), p.endianness())));
+        match file {
+            Some((elf64, encoding)) if self.d.d_type == ELF_T_BYTE => (elf64, encoding),
+            Some((elf64, _)) => (elf64, Endianness::default()),
+            // Data being written is in memory order until `elf_update`
+            None => {
+                let ehdr = elf.and_then(|e| e.writer.as_ref()?.ehdr.as_ref());
+                (ehdr.is_none_or(|h| h.elf64()), Endianness::default())
+            }
         }
     }
 }
@@ -1,18 +1,30 @@
 #![allow(non_camel_case_types)]
 #![allow(non_upper_case_globals)]
 
+mod bytes;
+pub mod dynamic;
 mod error;
+pub mod file;
+#[cfg(any(test, feature = "fuzz"))]
+#[doc(hidden)]
+pub mod fuzz;
 mod handle;
+pub mod limits;
+pub mod note;
 pub mod read;
+pub mod relr;
 pub mod types;
 pub mod write;
+mod xlate;
 
 // Re-export types
-pub use handle::{Elf, Elf_Scn};
+pub use handle::{Elf, Elf_Scn, ElfMemory};
 pub use types::*;
 
 // Re-export C API functions so cbindgen can find them
-pub use error::{elf_errmsg, elf_errno};
+pub use error::{ElfError, elf_errmsg, elf_errno};
+pub use file::ElfFile;
+pub use limits::{Elf_Limits, elf_getlimits, elf_setlimits};
 pub use read::*;
 pub use write::*;
 
@@ -22,6 +34,1686 @@ mod tests {
 
     #[test]
     fn test_elf_version() {
-        assert_eq!(elf_version(0), 1);
+        assert_eq!(elf_version(EV_NONE.into()), 1);
+        assert_eq!(elf_version(EV_CURRENT.into()), 1);
+        assert_eq!(elf_version(2), 0);
+        assert_eq!(elf_errno(), ElfError::ELF_E_UNKNOWN_VERSION as i32);
+        assert_eq!(elf_version(EV_NONE.into()), 1);
+    }
+
+    #[test]
+    fn test_version_required() {
+        use std::os::fd::AsRawFd;
+
+        // The working version is process-wide and other tests set it, so
+        // the check runs again in a process of its own
+        if std::env::var_os("LIBELF_RS_NO_VERSION").is_none() {
+            let status = std::process::Command::new(std::env::current_exe().unwrap())
+                .args(["--exact", "tests::test_version_required", "--quiet"])
+                .env("LIBELF_RS_NO_VERSION", "1")
+                .status()
+                .unwrap();
+            assert!(status.success());
+            return;
+        }
+
+        let file = std::fs::File::open("/proc/self/exe").unwrap();
+        assert!(elf_begin(file.as_raw_fd(), ELF_C_READ, std::ptr::null_mut()).is_null());
+        assert_eq!(elf_errno(), ElfError::ELF_E_NO_VERSION as i32);
+        let mut image = std::fs::read("/proc/self/exe").unwrap();
+        assert!(elf_memory(image.as_mut_ptr() as *mut i8, image.len()).is_null());
+        assert_eq!(elf_errno(), ElfError::ELF_E_NO_VERSION as i32);
+
+        elf_version(EV_CURRENT.into());
+        let elf = elf_memory(image.as_mut_ptr() as *mut i8, image.len());
+        assert!(!elf.is_null());
+        elf_end(elf);
+    }
+
+    #[test]
+    fn test_elf_errmsg_codes() {
+        elf_version(EV_CURRENT.into());
+        use std::ffi::CStr;
+
+        assert!(elf_begin(-1, ELF_C_READ, std::ptr::null_mut()).is_null());
+        assert_eq!(elf_errno(), ElfError::ELF_E_INVALID_FILE as i32);
+        assert_eq!(elf_errno(), ElfError::ELF_E_NOERROR as i32);
+
+        let msg = |n| unsafe { CStr::from_ptr(elf_errmsg(n)) }.to_str().unwrap();
+        assert_eq!(msg(0), "no error");
+        assert_eq!(
+            msg(ElfError::ELF_E_NO_VERSION as i32),
+            "ELF version not set"
+        );
+        assert_eq!(msg(ElfError::ELF_E_NUM as i32), "unknown error");
+    }
+
+    #[test]
+    fn test_elf_begin_read_from_pipe() {
+        elf_version(EV_CURRENT.into());
+        use std::io::Write;
+        use std::os::fd::AsRawFd;
+
+        let header = std::fs::read("/proc/self/exe").unwrap()[..64].to_vec();
+        let (reader, mut writer) = std::io::pipe().unwrap();
+        let feeder = std::thread::spawn(move || writer.write_all(&header).unwrap());
+
+        let elf = elf_begin(reader.as_raw_fd(), ELF_C_READ_MMAP, std::ptr::null_mut());
+        feeder.join().unwrap();
+        assert!(!elf.is_null());
+        assert_eq!(elf_kind(elf), ELF_K_ELF);
+
+        assert_eq!(elf_cntl(elf, ELF_C_FDDONE), 0);
+        drop(reader);
+        assert_eq!(elf_kind(elf), ELF_K_ELF);
+        assert_eq!(elf_cntl(elf, ELF_C_FDREAD), -1);
+        assert_eq!(elf_end(elf), 0);
+    }
+
+    #[test]
+    fn test_elf_begin_read_from_proc_file() {
+        elf_version(EV_CURRENT.into());
+        use std::os::fd::AsRawFd;
+
+        let expected = std::fs::read("/proc/self/cmdline").unwrap();
+        let file = std::fs::File::open("/proc/self/cmdline").unwrap();
+        assert_eq!(file.metadata().unwrap().len(), 0);
+
+        let elf = elf_begin(file.as_raw_fd(), ELF_C_READ, std::ptr::null_mut());
+        assert!(!elf.is_null());
+        assert_eq!(elf_kind(elf), ELF_K_NONE);
+        let mut n = 0usize;
+        let raw = elf_rawfile(elf, &mut n);
+        assert_eq!(n, expected.len());
+        assert_eq!(
+            unsafe { std::slice::from_raw_parts(raw as *const u8, n) },
+            expected
+        );
+        assert_eq!(elf_end(elf), 0);
+    }
+
+    #[test]
+    fn test_elf_getdata_rawchunk() {
+        elf_version(EV_CURRENT.into());
+        let mut image = std::fs::read("/proc/self/exe").unwrap();
+        let elf = elf_memory(image.as_mut_ptr() as *mut i8, image.len());
+        let mut ehdr: GElf_Ehdr = unsafe { std::mem::zeroed() };
+        assert!(!gelf_getehdr(elf, &mut ehdr).is_null());
+
+        let data = elf_getdata_rawchunk(elf, 0, 64, ELF_T_EHDR);
+        assert!(!data.is_null());
+        assert_eq!(elf_getdata_rawchunk(elf, 0, 64, ELF_T_EHDR), data);
+        let raw = unsafe { &*((*data).d_buf as *const GElf_Ehdr) };
+        assert_eq!(
+            raw.e_phnum.get(NativeEndian),
+            ehdr.e_phnum.get(NativeEndian)
+        );
+
+        let len = image.len() as i64;
+        assert!(elf_getdata_rawchunk(elf, len - 8, 16, ELF_T_BYTE).is_null());
+        assert_eq!(elf_errno(), ElfError::ELF_E_INVALID_OP as i32);
+        assert!(elf_getdata_rawchunk(elf, -1, 1, ELF_T_BYTE).is_null());
+        elf_end(elf);
+    }
+
+    #[test]
+    fn test_elf_update_honours_flags() {
+        elf_version(EV_CURRENT.into());
+        use std::os::fd::AsRawFd;
+
+        let devnull = std::fs::File::create("/dev/null").unwrap();
+        let elf = elf_begin(devnull.as_raw_fd(), ELF_C_WRITE, std::ptr::null_mut());
+        assert!(!elf64_newehdr(elf).is_null());
+        let scn = elf_newscn(elf);
+
+        let first = [1u8; 3];
+        let second = [2u8; 8];
+        let d1 = elf_newdata(scn);
+        let d2 = elf_newdata(scn);
+        unsafe {
+            (*d1).d_buf = first.as_ptr() as *mut _;
+            (*d1).d_size = first.len();
+            (*d2).d_buf = second.as_ptr() as *mut _;
+            (*d2).d_size = second.len();
+            (*d2).d_align = 8;
+        }
+        assert_eq!(elf_flagdata(d1, ELF_C_SET, 0), ELF_F_DIRTY);
+        assert!(elf_update(elf, ELF_C_NULL) > 0);
+        assert_eq!(unsafe { (*d2).d_off }, 8);
+
+        assert_eq!(elf_flagelf(elf, ELF_C_SET, ELF_F_LAYOUT), ELF_F_LAYOUT);
+        unsafe { (*d2).d_off = 2 };
+        assert_eq!(elf_update(elf, ELF_C_NULL), -1);
+        assert_eq!(elf_errno(), ElfError::ELF_E_INVALID_DATA as i32);
+
+        assert_eq!(elf_flagscn(scn, ELF_C_CLR, ELF_F_DIRTY), 0);
+        assert_eq!(elf_flagdata(d1, ELF_C_CLR, ELF_F_DIRTY), 0);
+        assert_eq!(elf_flagdata(d2, ELF_C_CLR, ELF_F_DIRTY), 0);
+        assert!(elf_update(elf, ELF_C_NULL) > 0);
+
+        assert_eq!(elf_flagshdr(scn, ELF_C_READ, ELF_F_DIRTY), 0);
+        assert_eq!(elf_errno(), ElfError::ELF_E_INVALID_COMMAND as i32);
+        elf_end(elf);
+    }
+
+    #[test]
+    fn test_elf_hash_functions() {
+        assert_eq!(elf_hash(c"printf".as_ptr()), 0x077905a6);
+        assert_eq!(elf_hash(c"memcpy".as_ptr()), 0x073c3a79);
+        assert_eq!(elf_gnu_hash(c"".as_ptr()), 5381);
+        assert_eq!(elf_gnu_hash(c"printf".as_ptr()), 0x156b2bb8);
+    }
+
+    /// A shared object defining `realpath` twice, in `.dynsym` (1) with
+    /// `.dynstr` (2), `.hash` (3), `.gnu.version` (4) and `.gnu.version_d`
+    /// (5): `realpath@V_1` hidden at 0x1000 and the default `realpath@@V_2`
+    /// at 0x2000.
+    fn versioned_dso(endian: object::Endianness, is_64: bool) -> Vec<u8> {
+        use object::elf::{EM_MIPS, EM_X86_64, ET_DYN, VER_FLG_BASE, VERSYM_HIDDEN};
+        use object::write::elf::{FileHeader, Sym, Verdef, Writer};
+
+        let mut image = Vec::new();
+        let mut w = Writer::new(endian, is_64, &mut image);
+        w.reserve_file_header();
+        w.reserve_null_section_index();
+        w.reserve_dynsym_section_index();
+        w.reserve_dynstr_section_index();
+        w.reserve_hash_section_index();
+        w.reserve_gnu_versym_section_index();
+        w.reserve_gnu_verdef_section_index();
+        w.reserve_shstrtab_section_index();
+
+        let soname = w.add_dynamic_string(b"libtest.so");
+        let v1 = w.add_dynamic_string(b"V_1");
+        let v2 = w.add_dynamic_string(b"V_2");
+        let realpath = w.add_dynamic_string(b"realpath");
+        w.reserve_null_dynamic_symbol_index();
+        w.reserve_dynamic_symbol_index();
+        w.reserve_dynamic_symbol_index();
+        w.reserve_dynsym();
+        w.reserve_dynstr();
+        w.reserve_hash(1, 3);
+        w.reserve_gnu_versym();
+        w.reserve_gnu_verdef(3, 3);
+        w.reserve_shstrtab();
+        w.reserve_section_headers();
+
+        w.write_file_header(&FileHeader {
+            os_abi: 0,
+            abi_version: 0,
+            e_type: ET_DYN,
+            e_machine: if is_64 { EM_X86_64 } else { EM_MIPS },
+            e_entry: 0,
+            e_flags: 0,
+        })
+        .unwrap();
+        w.write_null_dynamic_symbol();
+        for value in [0x1000, 0x2000] {
+            w.write_dynamic_symbol(&Sym {
+                name: Some(realpath),
+                section: None,
+                st_info: (STB_GLOBAL << 4) | STT_FUNC,
+                st_other: 0,
+                st_shndx: SHN_ABS,
+                st_value: value,
+                st_size: 16,
+            });
+        }
+        w.write_dynstr();
+        let hash = elf_hash(c"realpath".as_ptr()) as u32;
+        w.write_hash(1, 3, |i| (i != 0).then_some(hash));
+        w.write_null_gnu_versym();
+        w.write_gnu_versym(2 | VERSYM_HIDDEN);
+        w.write_gnu_versym(3);
+        w.write_align_gnu_verdef();
+        for (index, name) in [(1, soname), (2, v1), (3, v2)] {
+            w.write_gnu_verdef(&Verdef {
+                version: VER_DEF_CURRENT,
+                flags: if index == 1 { VER_FLG_BASE } else { 0 },
+                index,
+                aux_count: 1,
+                name,
+            });
+        }
+        w.write_shstrtab();
+        w.write_null_section_header();
+        w.write_dynsym_section_header(0, 1);
+        w.write_dynstr_section_header(0);
+        w.write_hash_section_header(0);
+        w.write_gnu_versym_section_header(0);
+        w.write_gnu_verdef_section_header(0);
+        w.write_shstrtab_section_header();
+
+        image
+    }
+
+    #[test]
+    fn test_gelf_lookup_dynsym() {
+        elf_version(EV_CURRENT.into());
+        let mut image = versioned_dso(object::Endianness::Little, true);
+        let elf = elf_memory(image.as_mut_ptr() as *mut i8, image.len());
+        let mut sym: GElf_Sym = unsafe { std::mem::zeroed() };
+        let mut ndx = 0usize;
+
+        let default = gelf_lookup_dynsym(
+            elf,
+            c"realpath".as_ptr(),
+            std::ptr::null(),
+            &mut sym,
+            &mut ndx,
+        );
+        assert!(!default.is_null());
+        assert_eq!(ndx, 2);
+        assert_eq!(sym.st_value.get(NativeEndian), 0x2000);
+
+        let old = gelf_lookup_dynsym(
+            elf,
+            c"realpath".as_ptr(),
+            c"V_1".as_ptr(),
+            &mut sym,
+            &mut ndx,
+        );
+        assert!(!old.is_null());
+        assert_eq!(ndx, 1);
+        assert_eq!(sym.st_value.get(NativeEndian), 0x1000);
+
+        let unknown = gelf_lookup_dynsym(
+            elf,
+            c"realpath".as_ptr(),
+            c"V_3".as_ptr(),
+            &mut sym,
+            &mut ndx,
+        );
+        assert!(unknown.is_null());
+
+        let missing = gelf_lookup_dynsym(
+            elf,
+            c"no_such_symbol".as_ptr(),
+            std::ptr::null(),
+            &mut sym,
+            &mut ndx,
+        );
+        assert!(missing.is_null());
+        elf_end(elf);
+    }
+
+    #[test]
+    fn test_gelf_symbols_and_versions_by_layout() {
+        elf_version(EV_CURRENT.into());
+        use object::Endianness;
+        use object::elf::{VER_FLG_BASE, VERSYM_HIDDEN};
+
+        for (endian, is_64) in [(Endianness::Big, false), (Endianness::Little, true)] {
+            let mut image = versioned_dso(endian, is_64);
+            let elf = elf_memory(image.as_mut_ptr() as *mut i8, image.len());
+            let name = |offset: u32| {
+                let ptr = elf_strptr(elf, 2, offset as usize);
+                unsafe { std::ffi::CStr::from_ptr(ptr) }.to_bytes().to_vec()
+            };
+
+            // Translated to the host's byte order, and as in the file
+            for get in [elf_getdata, elf_rawdata] {
+                let data = |index| get(elf_getscn(elf, index), std::ptr::null_mut());
+
+                let mut sym: GElf_Sym = unsafe { std::mem::zeroed() };
+                assert!(!gelf_getsym(data(1), 2, &mut sym).is_null());
+                assert_eq!(name(sym.st_name.get(NativeEndian)), b"realpath");
+                assert_eq!(sym.st_info, (STB_GLOBAL << 4) | STT_FUNC);
+                assert_eq!(sym.st_shndx.get(NativeEndian), SHN_ABS);
+                assert_eq!(sym.st_value.get(NativeEndian), 0x2000);
+                assert_eq!(sym.st_size.get(NativeEndian), 16);
+                assert!(gelf_getsym(data(1), 3, &mut sym).is_null());
+                assert_eq!(elf_errno(), ElfError::ELF_E_INVALID_INDEX as i32);
+
+                let mut versym: GElf_Versym = unsafe { std::mem::zeroed() };
+                assert!(!gelf_getversym(data(4), 1, &mut versym).is_null());
+                assert_eq!(versym.0.get(NativeEndian), 2 | VERSYM_HIDDEN);
+                assert!(!gelf_getversym(data(4), 2, &mut versym).is_null());
+                assert_eq!(versym.0.get(NativeEndian), 3);
+
+                let mut verdef: GElf_Verdef = unsafe { std::mem::zeroed() };
+                let mut verdaux: GElf_Verdaux = unsafe { std::mem::zeroed() };
+                let mut offset = 0;
+                let mut names = Vec::new();
+                for index in 1..=3 {
+                    assert!(!gelf_getverdef(data(5), offset, &mut verdef).is_null());
+                    assert_eq!(verdef.vd_version.get(NativeEndian), VER_DEF_CURRENT);
+                    assert_eq!(verdef.vd_ndx.get(NativeEndian), index);
+                    assert_eq!(verdef.vd_cnt.get(NativeEndian), 1);
+                    let flags = if index == 1 { VER_FLG_BASE } else { 0 };
+                    assert_eq!(verdef.vd_flags.get(NativeEndian), flags);
+                    let aux = offset + verdef.vd_aux.get(NativeEndian) as i32;
+                    assert!(!gelf_getverdaux(data(5), aux, &mut verdaux).is_null());
+                    let vda_name = name(verdaux.vda_name.get(NativeEndian));
+                    assert_eq!(
+                        verdef.vd_hash.get(NativeEndian),
+                        object::elf::hash(&vda_name)
+                    );
+                    names.push(vda_name);
+                    offset += verdef.vd_next.get(NativeEndian) as i32;
+                }
+                assert_eq!(names, [&b"libtest.so"[..], b"V_1", b"V_2"]);
+                assert!(gelf_getverdef(data(5), 3 * 28, &mut verdef).is_null());
+                assert_eq!(elf_errno(), ElfError::ELF_E_INVALID_INDEX as i32);
+            }
+            elf_end(elf);
+        }
+    }
+
+    #[test]
+    fn test_elf_raw_image_accessors() {
+        elf_version(EV_CURRENT.into());
+        let mut image = std::fs::read("/proc/self/exe").unwrap();
+        let elf = elf_memory(image.as_mut_ptr() as *mut i8, image.len());
+
+        let mut n = 0usize;
+        let ident = elf_getident(elf, &mut n);
+        assert_eq!(n, EI_NIDENT);
+        assert_eq!(unsafe { *ident.add(1) }, b'E' as i8);
+
+        let raw = elf_rawfile(elf, &mut n);
+        assert_eq!(n, image.len());
+        assert_eq!(raw as *const u8, unsafe { (*elf).data });
+        assert_eq!(elf_getbase(elf), 0);
+
+        let scn = elf_nextscn(elf, std::ptr::null_mut());
+        let mut shdr: GElf_Shdr = unsafe { std::mem::zeroed() };
+        gelf_getshdr(scn, &mut shdr);
+        let start = shdr.sh_offset.get(NativeEndian);
+        assert_eq!(elf_ndxscn(elf64_offscn(elf, start)), elf_ndxscn(scn));
+        assert!(elf32_offscn(elf, start as u32).is_null());
+        assert_eq!(elf_errno(), ElfError::ELF_E_INVALID_CLASS as i32);
+
+        let text = (1..)
+            .map(|i| elf_getscn(elf, i))
+            .find(|&s| {
+                gelf_getshdr(s, &mut shdr);
+                shdr.sh_size.get(NativeEndian) > 16
+                    && shdr.sh_type.get(NativeEndian) == SHT_PROGBITS
+            })
+            .unwrap();
+        let inside = shdr.sh_offset.get(NativeEndian) + 16;
+        assert_eq!(elf_ndxscn(elf64_offscn(elf, inside)), elf_ndxscn(text));
+        elf_end(elf);
+    }
+
+    #[test]
+    fn test_elf_fill_pads_gaps() {
+        elf_version(EV_CURRENT.into());
+        use std::os::fd::IntoRawFd;
+
+        let path = std::env::temp_dir().join(format!("libelf-rs-fill-{}", std::process::id()));
+        let fd = std::fs::File::create(&path).unwrap().into_raw_fd();
+        let elf = elf_begin(fd, ELF_C_WRITE, std::ptr::null_mut());
+        elf64_newehdr(elf);
+        let scn = elf_newscn(elf);
+        let small = [1u8; 3];
+        let aligned = [2u8; 8];
+        for (buf, align) in [(&small[..], 1), (&aligned[..], 8)] {
+            let data = elf_newdata(scn);
+            unsafe {
+                (*data).d_buf = buf.as_ptr() as *mut _;
+                (*data).d_size = buf.len();
+                (*data).d_align = align;
+            }
+        }
+
+        elf_fill(0xff);
+        let written = elf_update(elf, ELF_C_WRITE);
+        elf_fill(0);
+        elf_end(elf);
+        let mut out = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(written as usize, out.len());
+
+        let elf = elf_memory(out.as_mut_ptr() as *mut i8, out.len());
+        let mut ranges = Vec::new();
+        let mut shdr: GElf_Shdr = unsafe { std::mem::zeroed() };
+        let mut scn = elf_nextscn(elf, std::ptr::null_mut());
+        while !scn.is_null() {
+            gelf_getshdr(scn, &mut shdr);
+            let start = shdr.sh_offset.get(NativeEndian) as usize;
+            ranges.push((start, start + shdr.sh_size.get(NativeEndian) as usize));
+            scn = elf_nextscn(elf, scn);
+        }
+        let mut ehdr: GElf_Ehdr = unsafe { std::mem::zeroed() };
+        gelf_getehdr(elf, &mut ehdr);
+        let shoff = ehdr.e_shoff.get(NativeEndian) as usize;
+        ranges.push((0, 64));
+        ranges.push((shoff, out.len()));
+        ranges.sort();
+
+        let (ours, _) = ranges[1];
+        assert_eq!(
+            &out[ours..ours + 16],
+            &[
+                1, 1, 1, 0xff, 0xff, 0xff, 0xff, 0xff, 2, 2, 2, 2, 2, 2, 2, 2
+            ]
+        );
+        assert!(ranges.windows(2).any(|w| w[0].1 < w[1].0));
+        for w in ranges.windows(2) {
+            assert!(out[w[0].1..w[1].0].iter().all(|&b| b == 0xff));
+        }
+        elf_end(elf);
+    }
+
+    #[test]
+    fn test_writer_class_and_encoding() {
+        elf_version(EV_CURRENT.into());
+        use object::Endianness;
+        use object::elf::{EM_ARM, EM_PPC, EM_S390};
+        use std::os::fd::IntoRawFd;
+
+        let words: [u32; 2] = [0x0102_0304, 0x0506_0708];
+        for (class, data, machine) in [
+            (ELFCLASS32, ELFDATA2LSB, EM_ARM),
+            (ELFCLASS32, ELFDATA2MSB, EM_PPC),
+            (ELFCLASS64, ELFDATA2MSB, EM_S390),
+        ] {
+            let path = std::env::temp_dir().join(format!(
+                "libelf-rs-class-{}-{class}-{data}",
+                std::process::id()
+            ));
+            let fd = std::fs::File::create(&path).unwrap().into_raw_fd();
+            let elf = elf_begin(fd, ELF_C_WRITE, std::ptr::null_mut());
+            assert!(!gelf_newehdr(elf, class.into()).is_null());
+            let other = if class == ELFCLASS32 {
+                ELFCLASS64
+            } else {
+                ELFCLASS32
+            };
+            assert!(gelf_newehdr(elf, other.into()).is_null());
+            assert_eq!(elf_errno(), ElfError::ELF_E_INVALID_CLASS as i32);
+            assert_eq!(gelf_getclass(elf), class as i32);
+            if class == ELFCLASS32 {
+                let ehdr = unsafe { &mut *elf32_getehdr(elf) };
+                ehdr.e_ident.data = data;
+                ehdr.e_type.set(Endianness::default(), ET_REL);
+                ehdr.e_machine.set(Endianness::default(), machine);
+            } else {
+                let ehdr = unsafe { &mut *elf64_getehdr(elf) };
+                ehdr.e_ident.data = data;
+                ehdr.e_type.set(Endianness::default(), ET_REL);
+                ehdr.e_machine.set(Endianness::default(), machine);
+            }
+
+            let d = elf_newdata(elf_newscn(elf));
+            unsafe {
+                (*d).d_buf = words.as_ptr() as *mut _;
+                (*d).d_size = size_of_val(&words);
+                (*d).d_type = ELF_T_WORD;
+                (*d).d_align = 4;
+            }
+            let written = elf_update(elf, ELF_C_WRITE);
+            assert!(written > 0);
+            // The caller's buffer stays in memory order
+            assert_eq!(words, [0x0102_0304, 0x0506_0708]);
+            let mut ehdr: GElf_Ehdr = unsafe { std::mem::zeroed() };
+            assert!(!gelf_getehdr(elf, &mut ehdr).is_null());
+            assert_ne!(ehdr.e_shoff.get(NativeEndian), 0);
+            elf_end(elf);
+            let mut out = std::fs::read(&path).unwrap();
+            std::fs::remove_file(&path).unwrap();
+            assert_eq!(written as usize, out.len());
+            assert_eq!(&out[..4], b"\x7fELF");
+            assert_eq!((out[4], out[5]), (class, data));
+
+            let elf = elf_memory(out.as_mut_ptr() as *mut i8, out.len());
+            assert_eq!(gelf_getclass(elf), class as i32);
+            let mut read: GElf_Ehdr = unsafe { std::mem::zeroed() };
+            assert!(!gelf_getehdr(elf, &mut read).is_null());
+            assert_eq!(read.e_machine.get(NativeEndian), machine);
+            assert_eq!(read.e_shoff, ehdr.e_shoff);
+            let mut shdr: GElf_Shdr = unsafe { std::mem::zeroed() };
+            assert!(!gelf_getshdr(elf_getscn(elf, 1), &mut shdr).is_null());
+            let offset = shdr.sh_offset.get(NativeEndian) as usize;
+            let expected: Vec<u8> = if data == ELFDATA2MSB {
+                words.iter().flat_map(|w| w.to_be_bytes()).collect()
+            } else {
+                words.iter().flat_map(|w| w.to_le_bytes()).collect()
+            };
+            assert_eq!(&out[offset..offset + 8], &expected[..]);
+            elf_end(elf);
+        }
+    }
+
+    #[test]
+    fn test_new_section_headers() {
+        elf_version(EV_CURRENT.into());
+        use object::Endianness;
+        use object::elf::{EM_PPC, SHT_REL, SHT_STRTAB, SHT_SYMTAB};
+        use std::os::fd::IntoRawFd;
+
+        let path = std::env::temp_dir().join(format!("libelf-rs-shdr-{}", std::process::id()));
+        let fd = std::fs::File::create(&path).unwrap().into_raw_fd();
+        let elf = elf_begin(fd, ELF_C_WRITE, std::ptr::null_mut());
+        let scn = elf_newscn(elf);
+        assert!(elf32_getshdr(scn).is_null());
+        assert_eq!(elf_errno(), ElfError::ELF_E_WRONG_ORDER_EHDR as i32);
+        let ehdr = unsafe { &mut *elf32_newehdr(elf) };
+        ehdr.e_ident.data = ELFDATA2MSB;
+        ehdr.e_type.set(Endianness::default(), ET_REL);
+        ehdr.e_machine.set(Endianness::default(), EM_PPC);
+        assert!(elf64_getshdr(scn).is_null());
+        assert_eq!(elf_errno(), ElfError::ELF_E_INVALID_CLASS as i32);
+
+        let names = b"\0.text\0.rel.text\0.symtab\0.strtab\0.bss\0.shstrtab\0";
+        let name = |n: &[u8]| {
+            names
+                .windows(n.len() + 1)
+                .position(|w| &w[1..] == n && w[0] == 0)
+                .unwrap() as u32
+                + 1
+        };
+        let words: [u32; 2] = [0x0102_0304, 0x0506_0708];
+        let symbols = [0u8; 32];
+        let (text, rel, symtab, strtab, bss, shstrtab) = (
+            scn,
+            elf_newscn(elf),
+            elf_newscn(elf),
+            elf_newscn(elf),
+            elf_newscn(elf),
+            elf_newscn(elf),
+        );
+        assert_eq!(elf_ndxscn(text), 1);
+        assert_eq!(elf_ndxscn(shstrtab), 6);
+        for (scn, buf, size, ty, align) in [
+            (text, words.as_ptr() as *mut _, 8, ELF_T_WORD, 4),
+            (rel, words.as_ptr() as *mut _, 8, ELF_T_WORD, 4),
+            (symtab, symbols.as_ptr() as *mut _, 32, ELF_T_BYTE, 4),
+            (strtab, c"".as_ptr() as *mut _, 1, ELF_T_BYTE, 1),
+            (bss, std::ptr::null_mut(), 0x100, ELF_T_BYTE, 8),
+            (
+                shstrtab,
+                names.as_ptr() as *mut _,
+                names.len(),
+                ELF_T_BYTE,
+                1,
+            ),
+        ] {
+            let d = elf_newdata(scn);
+            unsafe {
+                (*d).d_buf = buf;
+                (*d).d_size = size;
+                (*d).d_type = ty;
+                (*d).d_align = align;
+            }
+        }
+
+        // One through the class-specific header, the rest through gelf
+        let e = Endianness::default();
+        let shdr = unsafe { &mut *elf32_getshdr(text) };
+        shdr.sh_name.set(e, name(b".text"));
+        shdr.sh_type.set(e, SHT_PROGBITS);
+        shdr.sh_flags.set(e, SHF_ALLOC | SHF_EXECINSTR);
+        let expected = [
+            (rel, b".rel.text".as_slice(), SHT_REL, 0u64, 3, 1, 8),
+            (symtab, b".symtab", SHT_SYMTAB, 0, 4, 1, 16),
+            (strtab, b".strtab", SHT_STRTAB, 0, 0, 0, 0),
+            (
+                bss,
+                b".bss",
+                SHT_NOBITS,
+                (SHF_WRITE | SHF_ALLOC).into(),
+                0,
+                0,
+                0,
+            ),
+            (shstrtab, b".shstrtab", SHT_STRTAB, 0, 0, 0, 0),
+        ];
+        for &(scn, n, sh_type, flags, link, info, entsize) in &expected {
+            let mut shdr: GElf_Shdr = unsafe { std::mem::zeroed() };
+            shdr.sh_name.set(NativeEndian, name(n));
+            shdr.sh_type.set(NativeEndian, sh_type);
+            shdr.sh_flags.set(NativeEndian, flags);
+            shdr.sh_link.set(NativeEndian, link);
+            shdr.sh_info.set(NativeEndian, info);
+            shdr.sh_entsize.set(NativeEndian, entsize);
+            assert_eq!(gelf_update_shdr(scn, &mut shdr), 1);
+        }
+        let mut wide: GElf_Shdr = unsafe { std::mem::zeroed() };
+        wide.sh_flags.set(NativeEndian, 1 << 32);
+        assert_eq!(gelf_update_shdr(strtab, &mut wide), 0);
+        assert_eq!(elf_errno(), ElfError::ELF_E_INVALID_DATA as i32);
+        assert_eq!(elf_setshstrndx(elf, elf_ndxscn(shstrtab)), 0);
+
+        let written = elf_update(elf, ELF_C_WRITE);
+        assert!(written > 0);
+        let mut placed: GElf_Shdr = unsafe { std::mem::zeroed() };
+        assert!(!gelf_getshdr(text, &mut placed).is_null());
+        assert_eq!(placed.sh_size.get(NativeEndian), 8);
+        elf_end(elf);
+        let mut out = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(written as usize, out.len());
+
+        let elf = elf_memory(out.as_mut_ptr() as *mut i8, out.len());
+        let mut ehdr: GElf_Ehdr = unsafe { std::mem::zeroed() };
+        gelf_getehdr(elf, &mut ehdr);
+        assert_eq!(ehdr.e_shnum.get(NativeEndian), 7);
+        assert_eq!(ehdr.e_shstrndx.get(NativeEndian), 6);
+
+        let raw = unsafe { &*elf32_getshdr(elf_getscn(elf, 1)) };
+        assert_eq!(raw.sh_type.get(Endianness::Big), SHT_PROGBITS);
+        let mut shdr: GElf_Shdr = unsafe { std::mem::zeroed() };
+        gelf_getshdr(elf_getscn(elf, 1), &mut shdr);
+        assert_eq!(shdr.sh_offset, placed.sh_offset);
+        assert_eq!(
+            shdr.sh_flags.get(NativeEndian),
+            (SHF_ALLOC | SHF_EXECINSTR).into()
+        );
+        let offset = shdr.sh_offset.get(NativeEndian) as usize;
+        assert_eq!(&out[offset..offset + 4], &[1, 2, 3, 4]);
+        let text_name = unsafe { std::ffi::CStr::from_ptr(elf_strptr(elf, 6, 1)) };
+        assert_eq!(text_name.to_bytes(), b".text");
+
+        for (index, &(_, n, sh_type, flags, link, info, entsize)) in expected.iter().enumerate() {
+            gelf_getshdr(elf_getscn(elf, index + 2), &mut shdr);
+            let at = shdr.sh_name.get(NativeEndian) as usize;
+            let read = unsafe { std::ffi::CStr::from_ptr(elf_strptr(elf, 6, at)) };
+            assert_eq!(read.to_bytes(), n);
+            assert_eq!(shdr.sh_type.get(NativeEndian), sh_type);
+            assert_eq!(shdr.sh_flags.get(NativeEndian), flags);
+            assert_eq!(shdr.sh_link.get(NativeEndian), link);
+            assert_eq!(shdr.sh_info.get(NativeEndian), info);
+            assert_eq!(shdr.sh_entsize.get(NativeEndian), entsize);
+        }
+
+        // `.bss` keeps its size but takes no room in the file
+        let mut bss: GElf_Shdr = unsafe { std::mem::zeroed() };
+        gelf_getshdr(elf_getscn(elf, 5), &mut bss);
+        gelf_getshdr(elf_getscn(elf, 6), &mut shdr);
+        assert_eq!(bss.sh_size.get(NativeEndian), 0x100);
+        assert_eq!(bss.sh_offset, shdr.sh_offset);
+        elf_end(elf);
+    }
+
+    #[test]
+    fn test_streamed_output() {
+        elf_version(EV_CURRENT.into());
+        use std::os::fd::AsRawFd;
+
+        // Larger than the staging buffer, so sections span several writes
+        let words: Vec<u32> = (0..100_000).collect();
+        let bytes: Vec<u8> = (0..200_000u32).map(|i| i as u8).collect();
+        for cmd in [ELF_C_WRITE, ELF_C_WRITE_MMAP] {
+            let path = std::env::temp_dir().join(format!(
+                "libelf-rs-stream-{}-{}",
+                std::process::id(),
+                cmd as u32
+            ));
+            // Whatever the file held before is cut off
+            std::fs::write(&path, vec![0xaa; 2 << 20]).unwrap();
+            let file = std::fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(&path)
+                .unwrap();
+            let elf = elf_begin(file.as_raw_fd(), cmd, std::ptr::null_mut());
+            let ehdr = elf64_newehdr(elf);
+            unsafe { (*ehdr).e_ident.data = ELFDATA2MSB };
+
+            let typed = elf_newdata(elf_newscn(elf));
+            unsafe {
+                (*typed).d_buf = words.as_ptr() as *mut _;
+                (*typed).d_size = words.len() * 4;
+                (*typed).d_type = ELF_T_WORD;
+                (*typed).d_align = 4;
+            }
+            let scn = elf_newscn(elf);
+            for (half, align) in [(&bytes[..100_000], 16), (&bytes[100_000..], 1)] {
+                let data = elf_newdata(scn);
+                unsafe {
+                    (*data).d_buf = half.as_ptr() as *mut _;
+                    (*data).d_size = half.len();
+                    (*data).d_align = align;
+                }
+            }
+
+            let written = elf_update(elf, ELF_C_WRITE);
+            assert!(written > 600_000);
+            assert_eq!(elf_update(elf, ELF_C_NULL), written);
+            elf_end(elf);
+            // The descriptor is still the caller's
+            assert!(file.metadata().is_ok());
+            drop(file);
+            let mut out = std::fs::read(&path).unwrap();
+            std::fs::remove_file(&path).unwrap();
+            assert_eq!(out.len(), written as usize);
+
+            let elf = elf_memory(out.as_mut_ptr() as *mut i8, out.len());
+            let mut shdr: GElf_Shdr = unsafe { std::mem::zeroed() };
+            assert!(!gelf_getshdr(elf_getscn(elf, 1), &mut shdr).is_null());
+            let offset = shdr.sh_offset.get(NativeEndian) as usize;
+            let expected: Vec<u8> = words.iter().flat_map(|w| w.to_be_bytes()).collect();
+            assert_eq!(&out[offset..offset + expected.len()], &expected[..]);
+            assert!(!gelf_getshdr(elf_getscn(elf, 2), &mut shdr).is_null());
+            let offset = shdr.sh_offset.get(NativeEndian) as usize;
+            assert_eq!(shdr.sh_size.get(NativeEndian), bytes.len() as u64);
+            assert_eq!(&out[offset..offset + bytes.len()], &bytes[..]);
+            elf_end(elf);
+        }
+    }
+
+    #[test]
+    fn test_elf_begin_ref_counting() {
+        elf_version(EV_CURRENT.into());
+        use std::os::fd::AsRawFd;
+
+        let file = std::fs::File::open("/proc/self/exe").unwrap();
+        let fd = file.as_raw_fd();
+        let elf = elf_begin(fd, ELF_C_READ_MMAP, std::ptr::null_mut());
+        assert!(!elf.is_null());
+
+        assert_eq!(elf_begin(fd, ELF_C_READ, elf), elf);
+        assert_eq!(elf_begin(-1, ELF_C_READ, elf), elf);
+        assert!(elf_begin(fd + 1, ELF_C_READ, elf).is_null());
+        assert_eq!(elf_errno(), ElfError::ELF_E_FD_MISMATCH as i32);
+
+        assert_eq!(elf_end(elf), 2);
+        assert_eq!(elf_end(elf), 1);
+        assert_eq!(elf_kind(elf), ELF_K_ELF);
+        assert_eq!(elf_end(elf), 0);
+    }
+
+    #[test]
+    fn test_elf_clone() {
+        elf_version(EV_CURRENT.into());
+        let mut image = std::fs::read("/proc/self/exe").unwrap();
+        let elf = elf_memory(image.as_mut_ptr() as *mut i8, image.len());
+        assert!(!elf.is_null());
+
+        assert!(elf_clone(elf, ELF_C_READ).is_null());
+        assert_eq!(elf_errno(), ElfError::ELF_E_INVALID_CMD as i32);
+        assert!(elf_clone(std::ptr::null_mut(), ELF_C_EMPTY).is_null());
+
+        let clone = elf_clone(elf, ELF_C_EMPTY);
+        assert!(!clone.is_null());
+        assert!(unsafe { &*clone }.flag(ELF_F_DIRTY));
+        assert!(!unsafe { &*elf }.flag(ELF_F_DIRTY));
+        assert_eq!(elf_kind(clone), elf_kind(elf));
+        assert_eq!(gelf_getclass(clone), gelf_getclass(elf));
+        let (mut size, mut clone_size) = (0, 0);
+        assert_eq!(
+            elf_getident(clone, &mut clone_size),
+            elf_getident(elf, &mut size)
+        );
+        assert_eq!(clone_size, size);
+
+        // Ending the source first leaves the clone's image in place
+        assert_eq!(elf_end(elf), 1);
+        assert_eq!(elf_kind(clone), ELF_K_ELF);
+        assert_eq!(gelf_getclass(clone), ELFCLASS64 as i32);
+        assert_eq!(elf_end(clone), 0);
+    }
+
+    #[test]
+    fn test_gelf_auxv_and_core_notes() {
+        elf_version(EV_CURRENT.into());
+        use std::os::fd::AsRawFd;
+
+        let devnull = std::fs::File::create("/dev/null").unwrap();
+        let elf = elf_begin(devnull.as_raw_fd(), ELF_C_WRITE, std::ptr::null_mut());
+        assert!(!elf64_newehdr(elf).is_null());
+        let data = elf_newdata(elf_newscn(elf));
+        let mut vector = [0u64; 6];
+        unsafe {
+            (*data).d_buf = vector.as_mut_ptr() as *mut _;
+            (*data).d_size = std::mem::size_of_val(&vector);
+            (*data).d_type = ELF_T_AUXV;
+        }
+
+        let mut entry = GElf_auxv_t::default();
+        entry.a_type.set(NativeEndian, AT_PAGESZ);
+        entry.a_val.set(NativeEndian, 4096);
+        assert_eq!(gelf_update_auxv(data, 0, &mut entry), 1);
+        assert_eq!(vector[..2], [AT_PAGESZ, 4096]);
+        assert_eq!(gelf_update_auxv(data, 3, &mut entry), 0);
+        assert_eq!(elf_errno(), ElfError::ELF_E_INVALID_INDEX as i32);
+
+        let mut out = GElf_auxv_t::default();
+        assert!(!gelf_getauxv(data, 0, &mut out).is_null());
+        assert_eq!(out.a_val.get(NativeEndian), 4096);
+
+        // NT_AUXV note with "CORE" owner, holding the vector above
+        let mut note = Vec::new();
+        for word in [5u32, 48, NT_AUXV] {
+            note.extend_from_slice(&word.to_ne_bytes());
+        }
+        note.extend_from_slice(b"CORE\0\0\0\0");
+        for word in vector {
+            note.extend_from_slice(&word.to_ne_bytes());
+        }
+        let parsed: Vec<_> =
+            note::NoteIter::new(&note, 4, true, object::Endianness::default()).collect();
+        assert_eq!(parsed.len(), 1);
+        assert_eq!(parsed[0].name, b"CORE");
+        let auxv = parsed[0].auxv().unwrap();
+        assert_eq!(auxv.len(), 1);
+        assert_eq!(auxv[0].a_type.get(NativeEndian), AT_PAGESZ);
+        elf_end(elf);
+
+        let mut image = std::fs::read("/proc/self/exe").unwrap();
+        let elf = elf_memory(image.as_mut_ptr() as *mut i8, image.len());
+        let notes: Vec<_> = unsafe { &*elf }
+            .file()
+            .and_then(|file| file.notes())
+            .unwrap()
+            .collect();
+        assert!(notes.iter().any(|n| n.name == b"GNU"));
+        elf_end(elf);
+    }
+
+    #[test]
+    fn test_gelf_getnote_alignment() {
+        elf_version(EV_CURRENT.into());
+        use std::os::fd::AsRawFd;
+
+        // namesz 6 puts the descriptor at 20 with 4-byte alignment and at
+        // 24 with 8-byte alignment
+        let mut buf = Vec::new();
+        for word in [6u32, 8, 5] {
+            buf.extend_from_slice(&word.to_be_bytes());
+        }
+        buf.extend_from_slice(b"OWNER\0\0\0\0\0\0\0");
+        buf.extend_from_slice(&[7u8; 8]);
+
+        let be = object::Endianness::Big;
+        let note = note::NoteIter::new(&buf, 8, true, be).next().unwrap();
+        assert_eq!(
+            (note.n_type, note.name, note.desc),
+            (5, &b"OWNER"[..], &[7u8; 8][..])
+        );
+        let note = note::NoteIter::new(&buf, 4, true, be).next().unwrap();
+        assert_eq!(note.desc, &[0u8, 0, 0, 0, 7, 7, 7, 7]);
+
+        let devnull = std::fs::File::create("/dev/null").unwrap();
+        let elf = elf_begin(devnull.as_raw_fd(), ELF_C_WRITE, std::ptr::null_mut());
+        assert!(!elf64_newehdr(elf).is_null());
+        let data = elf_newdata(elf_newscn(elf));
+        let mut native: Vec<u8> = [6u32, 8, 5].iter().flat_map(|w| w.to_ne_bytes()).collect();
+        native.extend_from_slice(&buf[12..]);
+        unsafe {
+            (*data).d_buf = native.as_mut_ptr() as *mut _;
+            (*data).d_size = native.len();
+            (*data).d_type = ELF_T_NHDR8;
+        }
+
+        let mut nhdr: GElf_Nhdr = unsafe { std::mem::zeroed() };
+        let (mut name, mut desc) = (0, 0);
+        assert_eq!(gelf_getnote(data, 0, &mut nhdr, &mut name, &mut desc), 32);
+        assert_eq!((name, desc), (12, 24));
+        assert_eq!(gelf_getnote(data, 32, &mut nhdr, &mut name, &mut desc), 0);
+
+        // descsz running past the end of the buffer
+        native[4..8].copy_from_slice(&u32::MAX.to_ne_bytes());
+        assert_eq!(gelf_getnote(data, 0, &mut nhdr, &mut name, &mut desc), 0);
+        assert_eq!(elf_errno(), ElfError::ELF_E_INVALID_DATA as i32);
+        elf_end(elf);
+    }
+
+    #[test]
+    fn test_gelf_syminfo_move_lib() {
+        elf_version(EV_CURRENT.into());
+        use std::os::fd::AsRawFd;
+
+        let devnull = std::fs::File::create("/dev/null").unwrap();
+        let elf = elf_begin(devnull.as_raw_fd(), ELF_C_WRITE, std::ptr::null_mut());
+        assert!(!elf64_newehdr(elf).is_null());
+        let data = elf_newdata(elf_newscn(elf));
+        let mut buf = [0u64; 8];
+        unsafe {
+            (*data).d_buf = buf.as_mut_ptr() as *mut _;
+            (*data).d_size = std::mem::size_of_val(&buf);
+        }
+
+        let mut mv = GElf_Move::default();
+        mv.m_value.set(NativeEndian, 0x1122);
+        mv.m_info.set(NativeEndian, 0x305);
+        mv.m_poffset.set(NativeEndian, 16);
+        mv.m_repeat.set(NativeEndian, 3);
+        mv.m_stride.set(NativeEndian, 1);
+        assert_eq!(gelf_update_move(data, 1, &mut mv), 1);
+        assert_eq!(gelf_update_move(data, 2, &mut mv), 0);
+        assert_eq!(elf_errno(), ElfError::ELF_E_INVALID_INDEX as i32);
+        let mut out = GElf_Move::default();
+        assert!(!gelf_getmove(data, 1, &mut out).is_null());
+        assert_eq!(out.m_info.get(NativeEndian), 0x305);
+        assert_eq!(out.m_stride.get(NativeEndian), 1);
+
+        let mut si = GElf_Syminfo::default();
+        si.si_boundto.set(NativeEndian, 0xffff);
+        si.si_flags.set(NativeEndian, 2);
+        assert_eq!(gelf_update_syminfo(data, 15, &mut si), 1);
+        let mut out = GElf_Syminfo::default();
+        assert!(!gelf_getsyminfo(data, 15, &mut out).is_null());
+        assert_eq!(out.si_boundto.get(NativeEndian), 0xffff);
+        assert!(gelf_getsyminfo(data, 16, &mut out).is_null());
+
+        let mut lib = GElf_Lib::default();
+        lib.l_name.set(NativeEndian, 7);
+        lib.l_flags.set(NativeEndian, 1);
+        assert_eq!(gelf_update_lib(data, 2, &mut lib), 1);
+        let mut out = GElf_Lib::default();
+        assert!(!gelf_getlib(data, 2, &mut out).is_null());
+        assert_eq!(
+            (out.l_name.get(NativeEndian), out.l_flags.get(NativeEndian)),
+            (7, 1)
+        );
+        elf_end(elf);
+    }
+
+    #[test]
+    fn test_gelf_relr() {
+        elf_version(EV_CURRENT.into());
+        use std::os::fd::AsRawFd;
+
+        let devnull = std::fs::File::create("/dev/null").unwrap();
+        let elf = elf_begin(devnull.as_raw_fd(), ELF_C_WRITE, std::ptr::null_mut());
+        assert!(!elf64_newehdr(elf).is_null());
+        let data = elf_newdata(elf_newscn(elf));
+        let mut table = [0u64; 3];
+        unsafe {
+            (*data).d_buf = table.as_mut_ptr() as *mut _;
+            (*data).d_size = std::mem::size_of_val(&table);
+            (*data).d_type = ELF_T_RELR;
+        }
+
+        for (i, entry) in [0x1000u64, 0b1011, 0b11].into_iter().enumerate() {
+            let mut relr: GElf_Relr = unsafe { std::mem::zeroed() };
+            relr.0.set(NativeEndian, entry);
+            assert_eq!(gelf_update_relr(data, i as i32, &mut relr), 1);
+        }
+        let mut relr: GElf_Relr = unsafe { std::mem::zeroed() };
+        assert!(!gelf_getrelr(data, 1, &mut relr).is_null());
+        assert_eq!(relr.0.get(NativeEndian), 0b1011);
+        assert!(gelf_getrelr(data, 3, &mut relr).is_null());
+
+        assert_eq!(gelf_relr_addrs(data, std::ptr::null_mut(), 0), 4);
+        let mut addrs = [0u64; 4];
+        assert_eq!(gelf_relr_addrs(data, addrs.as_mut_ptr(), addrs.len()), 4);
+        assert_eq!(addrs, [0x1000, 0x1008, 0x1018, 0x1200]);
+        elf_end(elf);
+    }
+
+    #[test]
+    fn test_elf_strptr_data_chain() {
+        elf_version(EV_CURRENT.into());
+        let mut image = std::fs::read("/proc/self/exe").unwrap();
+        let elf = elf_memory(image.as_mut_ptr() as *mut i8, image.len());
+        let mut shstrndx = 0;
+        assert_eq!(elf_getshdrstrndx(elf, &mut shstrndx), 0);
+        let scn = elf_getscn(elf, shstrndx);
+        assert_eq!(elf_getscn(elf, shstrndx), scn);
+
+        let first = elf_getdata(scn, std::ptr::null_mut());
+        assert!(!first.is_null());
+        assert_eq!(unsafe { (*first).d_version }, u32::from(EV_CURRENT));
+        assert_eq!(elf_getdata(scn, std::ptr::null_mut()), first);
+        assert!(elf_getdata(scn, first).is_null());
+        let size = unsafe { (*first).d_size };
+
+        let name = unsafe { std::ffi::CStr::from_ptr(elf_strptr(elf, shstrndx, 1)) };
+        assert!(!name.is_empty());
+        assert!(elf_strptr(elf, shstrndx, size).is_null());
+        assert_eq!(elf_errno(), ElfError::ELF_E_OFFSET_RANGE as i32);
+        assert!(elf_strptr(elf, 1, 0).is_null());
+        assert_eq!(elf_errno(), ElfError::ELF_E_INVALID_SECTION as i32);
+
+        let added = b".added\0unterminated";
+        let data = elf_newdata(scn);
+        unsafe {
+            (*data).d_buf = added.as_ptr() as *mut _;
+            (*data).d_size = added.len();
+        }
+        assert_eq!(elf_getdata(scn, first), data);
+        let name = unsafe { std::ffi::CStr::from_ptr(elf_strptr(elf, shstrndx, size)) };
+        assert_eq!(name, c".added");
+        assert!(elf_strptr(elf, shstrndx, size + 7).is_null());
+        assert_eq!(elf_errno(), ElfError::ELF_E_INVALID_INDEX as i32);
+        elf_end(elf);
+    }
+
+    #[test]
+    fn test_nobits_and_null_section() {
+        elf_version(EV_CURRENT.into());
+        let mut image = std::fs::read("/proc/self/exe").unwrap();
+        let elf = elf_memory(image.as_mut_ptr() as *mut i8, image.len());
+
+        let null = elf_getscn(elf, 0);
+        assert!(!null.is_null());
+        let mut shdr: GElf_Shdr = unsafe { std::mem::zeroed() };
+        assert!(!gelf_getshdr(null, &mut shdr).is_null());
+        assert_eq!(shdr.sh_type.get(NativeEndian), SHT_NULL);
+        let mut ehdr: GElf_Ehdr = unsafe { std::mem::zeroed() };
+        gelf_getehdr(elf, &mut ehdr);
+        let mut shnum = 0;
+        assert_eq!(elf_getshdrnum(elf, &mut shnum), 0);
+        match ehdr.e_shnum.get(NativeEndian) {
+            0 => assert_eq!(shdr.sh_size.get(NativeEndian) as usize, shnum),
+            n => assert_eq!(n as usize, shnum),
+        }
+
+        let mut nobits = 0;
+        let mut scn = elf_nextscn(elf, std::ptr::null_mut());
+        while !scn.is_null() {
+            gelf_getshdr(scn, &mut shdr);
+            if shdr.sh_type.get(NativeEndian) == SHT_NOBITS {
+                let data = unsafe { &*elf_getdata(scn, std::ptr::null_mut()) };
+                assert!(data.d_buf.is_null());
+                assert_eq!(data.d_size as u64, shdr.sh_size.get(NativeEndian));
+                nobits += 1;
+            }
+            scn = elf_nextscn(elf, scn);
+        }
+        // .bss at least
+        assert!(nobits > 0);
+        elf_end(elf);
+    }
+
+    #[test]
+    fn test_permissive_parsing() {
+        elf_version(EV_CURRENT.into());
+        let image = std::fs::read("/proc/self/exe").unwrap();
+        let shoff = u64::from_le_bytes(image[0x28..0x30].try_into().unwrap()) as usize;
+        let shnum = u16::from_le_bytes([image[0x3c], image[0x3d]]) as usize;
+        let shdr_at = |i: usize| shoff + 64 * i;
+
+        let open = |image: &mut Vec<u8>, permissive: bool| {
+            let elf = elf_memory(image.as_mut_ptr() as *mut i8, image.len());
+            if permissive {
+                elf_flagelf(elf, ELF_C_SET, ELF_F_PERMISSIVE);
+            }
+            elf
+        };
+        let mut count = 0;
+        let mut ehdr: GElf_Ehdr = unsafe { std::mem::zeroed() };
+        let mut shdr: GElf_Shdr = unsafe { std::mem::zeroed() };
+        let mut phdr: GElf_Phdr = unsafe { std::mem::zeroed() };
+
+        // e_shstrndx past the end of the section table
+        let mut bad_strndx = image.clone();
+        bad_strndx[0x3e..0x40].copy_from_slice(&(shnum as u16 + 5).to_le_bytes());
+        let elf = open(&mut bad_strndx, false);
+        assert_eq!(elf_getshdrnum(elf, &mut count), -1);
+        elf_end(elf);
+        let elf = open(&mut bad_strndx, true);
+        assert!(!gelf_getehdr(elf, &mut ehdr).is_null());
+        assert_eq!(elf_getshdrnum(elf, &mut count), 0);
+        assert_eq!(count, shnum);
+        assert!(!elf_getdata(elf_getscn(elf, 1), std::ptr::null_mut()).is_null());
+        assert!(elf_strptr(elf, shnum + 5, 0).is_null());
+        assert_eq!(elf_errno(), ElfError::ELF_E_INVALID_INDEX as i32);
+        elf_end(elf);
+
+        // Section table cut off halfway through an entry
+        let mut truncated = image[..shdr_at(shnum / 2) + 10].to_vec();
+        let elf = open(&mut truncated, false);
+        // The header alone is still sound; only the table is refused
+        assert!(!gelf_getehdr(elf, &mut ehdr).is_null());
+        assert_eq!(elf_getshdrnum(elf, &mut count), -1);
+        assert_eq!(elf_errno(), ElfError::ELF_E_INVALID_SECTION_HEADER as i32);
+        elf_end(elf);
+        let elf = open(&mut truncated, true);
+        assert_eq!(elf_getshdrnum(elf, &mut count), 0);
+        assert_eq!(count, shnum / 2);
+        assert!(!gelf_getshdr(elf_getscn(elf, shnum / 2 - 1), &mut shdr).is_null());
+        assert!(elf_getscn(elf, shnum / 2).is_null());
+        assert!(!gelf_getphdr(elf, 0, &mut phdr).is_null());
+        elf_end(elf);
+        // The mode can still change after the header has been read, but
+        // not once the tables have
+        let elf = open(&mut truncated, false);
+        assert!(!gelf_getehdr(elf, &mut ehdr).is_null());
+        let flags = elf_flagelf(elf, ELF_C_SET, ELF_F_PERMISSIVE);
+        assert_eq!(flags, ELF_F_PERMISSIVE);
+        assert_eq!(elf_getshdrnum(elf, &mut count), 0);
+        assert_eq!(count, shnum / 2);
+        assert_eq!(elf_flagelf(elf, ELF_C_CLR, ELF_F_PERMISSIVE), 0);
+        assert_eq!(elf_errno(), ElfError::ELF_E_INVALID_OP as i32);
+        assert_eq!(elf_flagelf(elf, ELF_C_SET, 0), ELF_F_PERMISSIVE);
+        elf_end(elf);
+
+        // The symbol table running past the end of the file
+        let symtab = (1..shnum)
+            .find(|&i| image[shdr_at(i) + 4..shdr_at(i) + 8] == SHT_SYMTAB.to_le_bytes())
+            .unwrap();
+        let mut past_eof = image.clone();
+        let offset = shdr_at(symtab) + 24;
+        past_eof[offset..offset + 8].copy_from_slice(&(image.len() as u64).to_le_bytes());
+        // Only the section that is needed gets looked at
+        let elf = open(&mut past_eof, false);
+        assert_eq!(elf_getshdrnum(elf, &mut count), 0);
+        assert!(elf_getdata(elf_getscn(elf, symtab), std::ptr::null_mut()).is_null());
+        elf_end(elf);
+        let elf = open(&mut past_eof, true);
+        assert!(elf_getdata(elf_getscn(elf, symtab), std::ptr::null_mut()).is_null());
+        assert_eq!(elf_errno(), ElfError::ELF_E_INVALID_SECTION as i32);
+        assert!(!gelf_getshdr(elf_getscn(elf, symtab), &mut shdr).is_null());
+        assert!(!elf_getdata(elf_getscn(elf, 1), std::ptr::null_mut()).is_null());
+        elf_end(elf);
+    }
+
+    #[test]
+    fn test_headerless_dynamic_tables() {
+        elf_version(EV_CURRENT.into());
+        let mut image = std::fs::read("/proc/self/exe").unwrap();
+
+        // What the section headers say, to compare against
+        let elf = elf_memory(image.as_mut_ptr() as *mut i8, image.len());
+        let mut shdr: GElf_Shdr = unsafe { std::mem::zeroed() };
+        let (mut names, mut dynsym_size, mut versym_size) = (Vec::new(), 0, None);
+        let mut scn = elf_nextscn(elf, std::ptr::null_mut());
+        while !scn.is_null() {
+            gelf_getshdr(scn, &mut shdr);
+            let size = shdr.sh_size.get(NativeEndian) as usize;
+            match shdr.sh_type.get(NativeEndian) {
+                SHT_DYNSYM => {
+                    dynsym_size = size;
+                    let data = elf_getdata(scn, std::ptr::null_mut());
+                    let mut sym: GElf_Sym = unsafe { std::mem::zeroed() };
+                    let link = shdr.sh_link.get(NativeEndian) as usize;
+                    for ndx in 0..(size / 24) as i32 {
+                        gelf_getsym(data, ndx, &mut sym);
+                        let name = elf_strptr(elf, link, sym.st_name.get(NativeEndian) as usize);
+                        names.push(
+                            unsafe { std::ffi::CStr::from_ptr(name) }
+                                .to_bytes()
+                                .to_vec(),
+                        );
+                    }
+                }
+                SHT_GNU_VERSYM => versym_size = Some(size),
+                _ => {}
+            }
+            scn = elf_nextscn(elf, scn);
+        }
+        elf_end(elf);
+        assert!(names.len() > 1);
+
+        // Drop the section table
+        image[0x28..0x30].fill(0);
+        image[0x3c..0x40].fill(0);
+        let elf = elf_memory(image.as_mut_ptr() as *mut i8, image.len());
+        assert!(elf_nextscn(elf, std::ptr::null_mut()).is_null());
+
+        let dynamic = unsafe { &*elf }.dynamic().unwrap();
+        let found: Vec<_> = dynamic.symbols().map(|s| s.name.to_vec()).collect();
+        assert_eq!(found, names);
+        assert_eq!(dynamic.versym.map(|(_, size)| size), versym_size);
+        assert!(dynamic.gnu_hash.is_some() || dynamic.hash.is_some());
+
+        let symbols = elf_getdata_dynamic(elf, DT_SYMTAB.into());
+        assert!(!symbols.is_null());
+        assert_eq!(
+            unsafe { ((*symbols).d_type, (*symbols).d_size) },
+            (ELF_T_SYM, dynsym_size)
+        );
+        let strings = elf_getdata_dynamic(elf, DT_STRTAB.into());
+        assert!(!strings.is_null());
+        let mut sym: GElf_Sym = unsafe { std::mem::zeroed() };
+        assert!(!gelf_getsym(symbols, 1, &mut sym).is_null());
+        let name = unsafe {
+            let base = (*strings).d_buf as *const i8;
+            std::ffi::CStr::from_ptr(base.add(sym.st_name.get(NativeEndian) as usize))
+        };
+        assert_eq!(name.to_bytes(), names[1]);
+        if let Some(size) = versym_size {
+            let versym = elf_getdata_dynamic(elf, DT_VERSYM.into());
+            assert_eq!(
+                unsafe { ((*versym).d_type, (*versym).d_size) },
+                (ELF_T_HALF, size)
+            );
+        }
+
+        assert!(elf_getdata_dynamic(elf, DT_NULL.into()).is_null());
+        assert_eq!(elf_errno(), ElfError::ELF_E_INVALID_OPERAND as i32);
+        let notes: Vec<_> = unsafe { &*elf }
+            .file()
+            .and_then(|file| file.notes())
+            .unwrap()
+            .collect();
+        assert!(notes.iter().any(|n| n.name == b"GNU"));
+        elf_end(elf);
+    }
+
+    #[test]
+    fn test_elf_memory_borrows_image() {
+        elf_version(EV_CURRENT.into());
+        let mut image = std::fs::read("/proc/self/exe").unwrap();
+        let len = image.len();
+        let elf = elf_memory(image.as_mut_ptr() as *mut i8, len);
+        let mut shstrndx = 0;
+        elf_getshdrstrndx(elf, &mut shstrndx);
+        let scn = elf_getscn(elf, shstrndx);
+        let mut shdr: GElf_Shdr = unsafe { std::mem::zeroed() };
+        gelf_getshdr(scn, &mut shdr);
+        let offset = shdr.sh_offset.get(NativeEndian) as usize;
+
+        let data = elf_getdata(scn, std::ptr::null_mut());
+        assert_eq!(unsafe { (*data).d_buf } as *const u8, unsafe {
+            image.as_ptr().add(offset)
+        });
+
+        // Replacing the buffer is only seen in the image after elf_update
+        let mut replacement = unsafe {
+            std::slice::from_raw_parts((*data).d_buf as *const u8, (*data).d_size).to_vec()
+        };
+        replacement[1] = b'!';
+        unsafe { (*data).d_buf = replacement.as_mut_ptr() as *mut _ };
+        elf_flagdata(data, ELF_C_SET, ELF_F_DIRTY);
+        assert_eq!(elf_update(elf, ELF_C_WRITE), len as i64);
+        elf_end(elf);
+        assert_eq!(image[offset + 1], b'!');
+
+        let mut mem = ElfMemory::borrowed(&mut image).unwrap();
+        assert_eq!(elf_kind(mem.as_ptr()), ELF_K_ELF);
+        let data = elf_getdata(elf_getscn(mem.as_ptr(), shstrndx), std::ptr::null_mut());
+        let extra = elf_newdata(elf_getscn(mem.as_ptr(), shstrndx));
+        let bytes = [0u8; 4096];
+        unsafe {
+            (*extra).d_buf = bytes.as_ptr() as *mut _;
+            (*extra).d_size = bytes.len();
+        }
+        assert_eq!(elf_update(mem.as_ptr(), ELF_C_WRITE), -1);
+        assert_eq!(elf_errno(), ElfError::ELF_E_SECTION_TOO_SMALL as i32);
+        assert!(!data.is_null());
+        drop(mem);
+
+        mem = ElfMemory::owned(image).unwrap();
+        assert_eq!(mem.image().len(), len);
+        assert_eq!(elf_update(mem.as_ptr(), ELF_C_NULL), len as i64);
+    }
+
+    #[test]
+    fn test_shared_handle_concurrent_reads() {
+        #[derive(Clone, Copy)]
+        struct Shared(*mut Elf);
+        unsafe impl Send for Shared {}
+        unsafe impl Sync for Shared {}
+
+        elf_version(EV_CURRENT.into());
+        let file = std::fs::File::open("/proc/self/exe").unwrap();
+        let fd = std::os::fd::AsRawFd::as_raw_fd(&file);
+        for cmd in [ELF_C_READ, ELF_C_READ_MMAP] {
+            let elf = Shared(elf_begin(fd, cmd, std::ptr::null_mut()));
+            assert!(!elf.0.is_null());
+
+            let walk = move || {
+                let elf = elf;
+                let mut shstrndx = 0;
+                assert_eq!(elf_getshdrstrndx(elf.0, &mut shstrndx), 0);
+                let mut seen = Vec::new();
+                let mut scn = elf_nextscn(elf.0, std::ptr::null_mut());
+                while !scn.is_null() {
+                    let mut shdr: GElf_Shdr = unsafe { std::mem::zeroed() };
+                    assert!(!gelf_getshdr(scn, &mut shdr).is_null());
+                    let name = elf_strptr(elf.0, shstrndx, shdr.sh_name.get(NativeEndian) as usize);
+                    assert!(!name.is_null());
+                    let data = elf_getdata(scn, std::ptr::null_mut());
+                    assert!(!data.is_null());
+                    assert_eq!(elf_getscn(elf.0, elf_ndxscn(scn)), scn);
+                    seen.push((scn as usize, data as usize, name as usize));
+                    scn = elf_nextscn(elf.0, scn);
+                }
+                let chunk = elf_getdata_rawchunk(elf.0, 0, 64, ELF_T_EHDR);
+                assert!(!chunk.is_null());
+                (seen, chunk as usize)
+            };
+
+            let results: Vec<_> = std::thread::scope(|s| {
+                let threads: Vec<_> = (0..8)
+                    .map(|_| {
+                        s.spawn(move || {
+                            let elf = elf;
+                            let first = walk();
+                            for _ in 0..20 {
+                                assert_eq!(walk(), first);
+                                // Flags don't affect reading, but are
+                                // updated through the same descriptor
+                                let flags = elf_flagelf(elf.0, ELF_C_SET, ELF_F_DIRTY);
+                                assert_ne!(flags & ELF_F_DIRTY, 0);
+                                elf_flagehdr(elf.0, ELF_C_CLR, ELF_F_DIRTY);
+                            }
+                            first
+                        })
+                    })
+                    .collect();
+                threads.into_iter().map(|t| t.join().unwrap()).collect()
+            });
+            // Every thread was handed the same descriptors
+            assert!(!results[0].0.is_empty());
+            assert!(results.windows(2).all(|w| w[0] == w[1]));
+            assert_eq!(elf_flagelf(elf.0, ELF_C_CLR, ELF_F_DIRTY), 0);
+            assert_eq!(elf_end(elf.0), 0);
+        }
+    }
+
+    #[test]
+    fn test_panics_do_not_escape() {
+        elf_version(EV_CURRENT.into());
+        use std::os::fd::AsRawFd;
+
+        // A data descriptor claiming more bytes than can ever be allocated
+        // is refused before assembling the section tries to
+        let devnull = std::fs::File::create("/dev/null").unwrap();
+        let elf = elf_begin(devnull.as_raw_fd(), ELF_C_WRITE, std::ptr::null_mut());
+        assert!(!elf64_newehdr(elf).is_null());
+        let scn = elf_newscn(elf);
+        let data = elf_newdata(scn);
+        unsafe { (*data).d_size = usize::MAX };
+        assert_eq!(elf_update(elf, ELF_C_NULL), -1);
+        assert_eq!(elf_errno(), ElfError::ELF_E_NOMEM as i32);
+
+        // The descriptor stays usable once the bad size is corrected
+        unsafe { (*data).d_size = 4 };
+        assert!(elf_update(elf, ELF_C_NULL) > 0);
+        assert_eq!(elf_errno(), 0);
+        elf_end(elf);
+
+        // Whatever still panics is reported like any other failure
+        assert_eq!(
+            error::guard(-1, || -> i32 { panic!("malformed input") }),
+            -1
+        );
+        assert_eq!(elf_errno(), ElfError::ELF_E_UNKNOWN_ERROR as i32);
+    }
+
+    #[test]
+    fn test_raw_headers_bounds_checked() {
+        elf_version(EV_CURRENT.into());
+
+        // Just the identification: too short for any header
+        let mut image = std::fs::read("/proc/self/exe").unwrap();
+        let mut tiny = image[..16].to_vec();
+        let elf = elf_memory(tiny.as_mut_ptr() as *mut i8, tiny.len());
+        assert!(elf64_getehdr(elf).is_null());
+        assert_ne!(elf_errno(), 0);
+        elf_end(elf);
+
+        // A section table pointing past the end of the file
+        let elf = elf_memory(image.as_mut_ptr() as *mut i8, image.len());
+        let ehdr = elf64_getehdr(elf);
+        assert!(!ehdr.is_null());
+        let scn = elf_getscn(elf, 1);
+        assert!(!elf64_getshdr(scn).is_null());
+        elf_end(elf);
+
+        let shoff = image.len() as u64 - 8;
+        image[0x28..0x30].copy_from_slice(&shoff.to_le_bytes());
+        let elf = elf_memory(image.as_mut_ptr() as *mut i8, image.len());
+        assert!(elf_getscn(elf, 1).is_null() || elf64_getshdr(elf_getscn(elf, 1)).is_null());
+        assert_ne!(elf_errno(), 0);
+        elf_end(elf);
+
+        // Offsets that would wrap around
+        let mut image = std::fs::read("/proc/self/exe").unwrap();
+        let elf = elf_memory(image.as_mut_ptr() as *mut i8, image.len());
+        let mut shstrndx = 0;
+        assert_eq!(elf_getshdrstrndx(elf, &mut shstrndx), 0);
+        assert!(elf_strptr(elf, shstrndx, usize::MAX).is_null());
+        assert_eq!(elf_errno(), ElfError::ELF_E_OFFSET_RANGE as i32);
+        elf_end(elf);
+
+        // A string table index past the section table
+        image[0x3e..0x40].copy_from_slice(&0xfff0u16.to_le_bytes());
+        let elf = elf_memory(image.as_mut_ptr() as *mut i8, image.len());
+        assert_eq!(elf_getshdrstrndx(elf, &mut shstrndx), -1);
+        assert_eq!(elf_errno(), ElfError::ELF_E_INVALID_SECTION_HEADER as i32);
+        elf_end(elf);
+
+        // Not ELF at all, and ELF magic with a class nothing knows
+        let mut ehdr: GElf_Ehdr = unsafe { std::mem::zeroed() };
+        image[4] = 9;
+        for (mut image, error) in [
+            (b"!<arch>\n".repeat(8), ElfError::ELF_E_INVALID_FILE),
+            (image[..64].to_vec(), ElfError::ELF_E_INVALID_ELF),
+        ] {
+            let elf = elf_memory(image.as_mut_ptr() as *mut i8, image.len());
+            assert!(gelf_getehdr(elf, &mut ehdr).is_null());
+            assert_eq!(elf_errno(), error as i32);
+            elf_end(elf);
+        }
+    }
+
+    #[test]
+    fn test_resource_limits() {
+        elf_version(EV_CURRENT.into());
+        use std::os::fd::AsRawFd;
+
+        // Limits are process-wide: keep them well above what any other
+        // test needs
+        let mut saved = Elf_Limits::default();
+        assert_eq!(elf_getlimits(&mut saved), 0);
+        let limits = Elf_Limits {
+            max_sections: 200,
+            max_decompressed_size: 1 << 30,
+            max_notes: 100,
+        };
+        assert_eq!(elf_setlimits(&limits), 0);
+
+        let devnull = std::fs::File::create("/dev/null").unwrap();
+        let elf = elf_begin(devnull.as_raw_fd(), ELF_C_WRITE, std::ptr::null_mut());
+        assert!(!elf64_newehdr(elf).is_null());
+        let scn = elf_newscn(elf);
+        let created = (0..300).take_while(|_| !elf_newscn(elf).is_null()).count();
+        assert_eq!(created, 198);
+        assert_eq!(elf_errno(), ElfError::ELF_E_NOMEM as i32);
+
+        let data = elf_newdata(scn);
+        unsafe { (*data).d_size = 2 << 30 };
+        assert_eq!(elf_update(elf, ELF_C_NULL), -1);
+        assert_eq!(elf_errno(), ElfError::ELF_E_NOMEM as i32);
+        elf_end(elf);
+
+        let mut notes = Vec::new();
+        for _ in 0..150 {
+            for word in [4u32, 0, 1] {
+                notes.extend_from_slice(&word.to_ne_bytes());
+            }
+            notes.extend_from_slice(b"GNU\0");
+        }
+        let walked = note::NoteIter::new(&notes, 4, true, object::Endianness::default()).count();
+        assert_eq!(walked, 100);
+
+        assert_eq!(elf_setlimits(&saved), 0);
+        assert!(elf_setlimits(std::ptr::null()) == -1);
+        assert_eq!(elf_errno(), ElfError::ELF_E_INVALID_OPERAND as i32);
+    }
+
+    #[test]
+    fn test_elf_file_iterators() {
+        use object::write::{Object, Relocation, StandardSection, Symbol, SymbolSection};
+        use object::{
+            Architecture, BinaryFormat, Endianness, RelocationFlags, SymbolFlags, SymbolKind,
+        };
+
+        // ELF32 big-endian, with implicit addends
+        let mut obj = Object::new(BinaryFormat::Elf, Architecture::Mips, Endianness::Big);
+        let text = obj.section_id(StandardSection::Text);
+        obj.append_section_data(text, &[0; 16], 16);
+        let helper = obj.add_symbol(Symbol {
+            name: b"helper".to_vec(),
+            value: 4,
+            size: 4,
+            kind: SymbolKind::Text,
+            scope: object::SymbolScope::Linkage,
+            weak: false,
+            section: SymbolSection::Section(text),
+            flags: SymbolFlags::None,
+        });
+        obj.add_relocation(
+            text,
+            Relocation {
+                offset: 8,
+                symbol: helper,
+                addend: 0,
+                flags: RelocationFlags::Elf {
+                    r_type: object::elf::R_MIPS_32,
+                },
+            },
+        )
+        .unwrap();
+        let image = obj.write().unwrap();
+
+        let file = ElfFile::from_bytes(&image).unwrap();
+        assert!(file.is_elf32());
+        assert_eq!(file.endianness(), Endianness::Big);
+        let symbol = file
+            .symbols()
+            .unwrap()
+            .find(|s| s.name == b"helper")
+            .unwrap();
+        assert_eq!(symbol.sym.st_value.get(NativeEndian), 4);
+        let rel = file.section_by_name(b".rel.text").unwrap().unwrap();
+        let relocations: Vec<_> = rel.relocations().unwrap().collect();
+        assert_eq!(
+            relocations,
+            [file::Relocation {
+                offset: 8,
+                symbol: symbol.index as u32,
+                r_type: object::elf::R_MIPS_32,
+                addend: None,
+            }]
+        );
+        let text = file.section_by_name(b".text").unwrap().unwrap();
+        assert_eq!(text.data().unwrap(), &[0; 16]);
+        assert_eq!(
+            text.relocations().err(),
+            Some(ElfError::ELF_E_INVALID_SECTION_TYPE)
+        );
+        assert_eq!(
+            file.section(1000).err(),
+            Some(ElfError::ELF_E_INVALID_INDEX)
+        );
+        assert_eq!(
+            ElfFile::from_bytes(b"not an ELF file").err(),
+            Some(ElfError::ELF_E_INVALID_FILE)
+        );
+        assert_eq!(
+            ElfError::ELF_E_INVALID_INDEX.to_string(),
+            "invalid section index"
+        );
+
+        // The C API sees the same file through the same layer
+        elf_version(EV_CURRENT.into());
+        let file = ElfFile::open("/proc/self/exe").unwrap();
+        let mut exe = file.data().to_vec();
+        let elf = elf_memory(exe.as_mut_ptr() as *mut i8, exe.len());
+        let mut shnum = 0;
+        assert_eq!(elf_getshdrnum(elf, &mut shnum), 0);
+        assert_eq!(file.sections().unwrap().count(), shnum);
+        for section in file.sections().unwrap() {
+            let mut shdr: GElf_Shdr = unsafe { std::mem::zeroed() };
+            assert!(!gelf_getshdr(elf_getscn(elf, section.index), &mut shdr).is_null());
+            assert_eq!(shdr.sh_offset, section.header.sh_offset);
+            assert_eq!(shdr.sh_size, section.header.sh_size);
+        }
+        elf_end(elf);
+
+        for section in file.sections().unwrap() {
+            if let Ok(relocations) = section.relocations() {
+                let entsize = section.header.sh_entsize.get(NativeEndian);
+                let count = section.header.sh_size.get(NativeEndian) / entsize;
+                assert_eq!(relocations.count() as u64, count);
+            }
+        }
+        let dynamic = file.dynamic().unwrap();
+        let dynstr = file.section_by_name(b".dynstr").unwrap().unwrap();
+        assert!(dynamic.entries().any(|entry| {
+            entry.d_tag.get(NativeEndian) == u64::from(DT_STRTAB)
+                && entry.d_val.get(NativeEndian) == dynstr.header.sh_addr.get(NativeEndian)
+        }));
+        let names: Vec<_> = file.dynamic_symbols().unwrap().map(|s| s.name).collect();
+        let through_dynamic: Vec<_> = dynamic.symbols().map(|s| s.name).collect();
+        assert_eq!(names, through_dynamic);
+
+        let notes: Vec<_> = file.notes().unwrap().map(|n| (n.n_type, n.desc)).collect();
+        let per_segment: Vec<_> = file
+            .segments()
+            .unwrap()
+            .filter_map(|segment| segment.notes().ok())
+            .flatten()
+            .map(|n| (n.n_type, n.desc))
+            .collect();
+        assert_eq!(notes, per_segment);
+    }
+
+    /// Files small enough to mutate quickly, covering both classes and
+    /// byte orders.
+    fn fuzz_seeds() -> Vec<Vec<u8>> {
+        use object::write::{Object, StandardSection, Symbol, SymbolSection};
+        use object::{Architecture, BinaryFormat, SectionKind, SymbolFlags, SymbolKind};
+
+        let mut seeds = Vec::new();
+        for (arch, endian) in [
+            (Architecture::X86_64, object::Endianness::Little),
+            (Architecture::Mips, object::Endianness::Big),
+        ] {
+            let mut obj = Object::new(BinaryFormat::Elf, arch, endian);
+            let text = obj.section_id(StandardSection::Text);
+            obj.append_section_data(text, &[0x90; 32], 16);
+            let note = obj.add_section(Vec::new(), b".note.test".to_vec(), SectionKind::Note);
+            let mut desc = Vec::new();
+            for word in [4u32, 4, 1] {
+                desc.extend_from_slice(&match endian {
+                    object::Endianness::Little => word.to_le_bytes(),
+                    object::Endianness::Big => word.to_be_bytes(),
+                });
+            }
+            desc.extend_from_slice(b"GNU\0\x01\x02\x03\x04");
+            obj.append_section_data(note, &desc, 4);
+            for name in ["main", "helper", "table"] {
+                obj.add_symbol(Symbol {
+                    name: name.as_bytes().to_vec(),
+                    value: 0,
+                    size: 4,
+                    kind: SymbolKind::Text,
+                    scope: object::SymbolScope::Dynamic,
+                    weak: false,
+                    section: SymbolSection::Section(text),
+                    flags: SymbolFlags::None,
+                });
+            }
+            seeds.push(obj.write().unwrap());
+        }
+        // The program headers of this executable, without its section table
+        let mut exe = std::fs::read("/proc/self/exe").unwrap();
+        exe.truncate(4096);
+        exe[0x28..0x30].fill(0);
+        exe[0x3c..0x40].fill(0);
+        seeds.push(exe);
+        seeds
+    }
+
+    #[test]
+    fn test_fuzz_harness_mutations() {
+        // xorshift, so failures reproduce
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as usize
+        };
+
+        for seed in fuzz_seeds() {
+            fuzz::exercise(&seed);
+            for _ in 0..300 {
+                let mut input = seed.clone();
+                for _ in 0..1 + next() % 8 {
+                    let pos = next() % input.len();
+                    input[pos] = match next() % 4 {
+                        0 => 0,
+                        1 => 0xff,
+                        _ => next() as u8,
+                    };
+                }
+                if next() % 4 == 0 {
+                    input.truncate(next() % input.len());
+                }
+                fuzz::exercise(&input);
+            }
+        }
     }
 }
@@ -0,0 +1,95 @@
+use crate::error::*;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Caps on the resources a descriptor may claim, for processes that open
+/// untrusted files. They apply process-wide, like `elf_fill`, and only to
+/// descriptors parsed or grown after they are set. Anything over a limit is
+/// refused with `ELF_E_NOMEM`. Every limit defaults to `usize::MAX`, so
+/// nothing is refused unless asked for.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Elf_Limits {
+    /// Sections in a file, counting the null section.
+    pub max_sections: usize,
+    /// Bytes the library materialises for one section or chunk instead of
+    /// pointing into the image: decompressed, translated or assembled
+    /// contents.
+    pub max_decompressed_size: usize,
+    /// Notes one walk over a note buffer or the `PT_NOTE` segments yields.
+    pub max_notes: usize,
+}
+
+impl Default for Elf_Limits {
+    fn default() -> Self {
+        Self {
+            max_sections: usize::MAX,
+            max_decompressed_size: usize::MAX,
+            max_notes: usize::MAX,
+        }
+    }
+}
+
+static MAX_SECTIONS: AtomicUsize = AtomicUsize::new(usize::MAX);
+static MAX_DECOMPRESSED_SIZE: AtomicUsize = AtomicUsize::new(usize::MAX);
+static MAX_NOTES: AtomicUsize = AtomicUsize::new(usize::MAX);
+
+/// The limits currently in force.
+pub fn limits() -> Elf_Limits {
+    Elf_Limits {
+        max_sections: MAX_SECTIONS.load(Ordering::Relaxed),
+        max_decompressed_size: MAX_DECOMPRESSED_SIZE.load(Ordering::Relaxed),
+        max_notes: MAX_NOTES.load(Ordering::Relaxed),
+    }
+}
+
+pub fn set_limits(limits: &Elf_Limits) {
+    MAX_SECTIONS.store(limits.max_sections, Ordering::Relaxed);
+    MAX_DECOMPRESSED_SIZE.store(limits.max_decompressed_size, Ordering::Relaxed);
+    MAX_NOTES.store(limits.max_notes, Ordering::Relaxed);
+}
+
+/// Fails with `ELF_E_NOMEM` when `size` bytes would exceed
+/// `max_decompressed_size`.
+pub fn check_data_size(size: usize) -> Result<(), ElfError> {
+    if size > MAX_DECOMPRESSED_SIZE.load(Ordering::Relaxed) {
+        return Err(ELF_E_NOMEM);
+    }
+    Ok(())
+}
+
+/// Fails with `ELF_E_NOMEM` when a file with `count` sections would exceed
+/// `max_sections`.
+pub fn check_section_count(count: usize) -> Result<(), ElfError> {
+    if count > MAX_SECTIONS.load(Ordering::Relaxed) {
+        return Err(ELF_E_NOMEM);
+    }
+    Ok(())
+}
+
+/// Copies the limits in force to `dst`. Not part of libelf.
+#[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn elf_getlimits(dst: *mut Elf_Limits) -> i32 {
+    guard(-1, || {
+        if dst.is_null() {
+            set_error(ELF_E_INVALID_OPERAND);
+            return -1;
+        }
+        unsafe { *dst = limits() };
+        0
+    })
+}
+
+/// Replaces the limits in force with `src`. Not part of libelf.
+#[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn elf_setlimits(src: *const Elf_Limits) -> i32 {
+    guard(-1, || {
+        if src.is_null() {
+            set_error(ELF_E_INVALID_OPERAND);
+            return -1;
+        }
+        set_limits(unsafe { &*src });
+        0
+    })
+}
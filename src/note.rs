@@ -0,0 +1,306 @@
+//! Note entries, as found in `SHT_NOTE` sections and `PT_NOTE` segments.
+//!
+//! `gelf_getnote` and the Rust-level iterators share the offset logic here.
+
+use crate::bytes::{read_u32, read_word};
+use crate::error::*;
+use crate::limits;
+use crate::types::*;
+use object::Endianness;
+
+/// Location of the parts of one note entry within its buffer.
+pub struct NoteEntry {
+    pub n_namesz: u32,
+    pub n_descsz: u32,
+    pub n_type: u32,
+    pub name_offset: usize,
+    pub desc_offset: usize,
+    pub next: usize,
+}
+
+/// Alignment of the descriptor and of each entry: 8 for `ELF_T_NHDR8` data,
+/// and for untyped data from a section or segment aligned to 8, such as
+/// `.note.gnu.property`; 4 otherwise.
+pub fn note_align(d_type: ElfType, d_align: usize) -> usize {
+    match d_type {
+        ELF_T_NHDR8 => 8,
+        ELF_T_NHDR => 4,
+        _ if d_align == 8 => 8,
+        _ => 4,
+    }
+}
+
+/// Decodes the note header at `offset`. The name and descriptor must lie
+/// within `buf`; `next` is clamped to its end, so a final entry without
+/// trailing padding is still accepted.
+pub fn note_at(
+    buf: &[u8],
+    offset: usize,
+    encoding: Endianness,
+    align: usize,
+) -> Result<NoteEntry, ElfError> {
+    if !offset.is_multiple_of(4) {
+        return Err(ELF_E_INVALID_OFFSET);
+    }
+    let header = |pos: usize| read_u32(buf, offset.checked_add(pos)?, encoding);
+    let (Some(n_namesz), Some(n_descsz), Some(n_type)) = (header(0), header(4), header(8)) else {
+        return Err(ELF_E_INVALID_DATA);
+    };
+
+    let name_offset = offset + 12;
+    let desc_offset = name_offset
+        .checked_add(n_namesz as usize)
+        .filter(|&end| end <= buf.len())
+        .map(|end| end.next_multiple_of(align))
+        .ok_or(ELF_E_INVALID_DATA)?;
+    let desc_end = desc_offset
+        .checked_add(n_descsz as usize)
+        .filter(|&end| end <= buf.len())
+        .ok_or(ELF_E_INVALID_DATA)?;
+    let next = desc_end.next_multiple_of(align).min(buf.len());
+
+    Ok(NoteEntry {
+        n_namesz,
+        n_descsz,
+        n_type,
+        name_offset,
+        desc_offset,
+        next,
+    })
+}
+
+/// One note, with the class and byte order of the file it came from so the
+/// descriptor can be decoded.
+#[derive(Debug, Clone, Copy)]
+pub struct Note<'a> {
+    pub n_type: u32,
+    /// Owner name, without the NUL terminator.
+    pub name: &'a [u8],
+    pub desc: &'a [u8],
+    pub elf64: bool,
+    pub encoding: Endianness,
+}
+
+/// Walks the entries of one note buffer, stopping at the first malformed
+/// one or once `max_notes` have been seen.
+pub struct NoteIter<'a> {
+    buf: &'a [u8],
+    offset: usize,
+    remaining: usize,
+    align: usize,
+    elf64: bool,
+    encoding: Endianness,
+}
+
+impl<'a> NoteIter<'a> {
+    /// `align` is 4 or 8, as returned by [`note_align`].
+    pub fn new(buf: &'a [u8], align: usize, elf64: bool, encoding: Endianness) -> Self {
+        Self {
+            buf,
+            offset: 0,
+            remaining: limits::limits().max_notes,
+            align,
+            elf64,
+            encoding,
+        }
+    }
+}
+
+impl<'a> Iterator for NoteIter<'a> {
+    type Item = Note<'a>;
+
+    fn next(&mut self) -> Option<Note<'a>> {
+        if self.offset >= self.buf.len() || self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        let Ok(entry) = note_at(self.buf, self.offset, self.encoding, self.align) else {
+            self.offset = self.buf.len();
+            return None;
+        };
+        self.offset = entry.next;
+
+        let name = &self.buf[entry.name_offset..entry.name_offset + entry.n_namesz as usize];
+        let desc = &self.buf[entry.desc_offset..entry.desc_offset + entry.n_descsz as usize];
+        Some(Note {
+            n_type: entry.n_type,
+            name: name.strip_suffix(b"\0").unwrap_or(name),
+            desc,
+            elf64: self.elf64,
+            encoding: self.encoding,
+        })
+    }
+}
+
+/// Notes from every `PT_NOTE` segment of a file, in program header order,
+/// up to `max_notes` in all.
+pub struct SegmentNotes<'a> {
+    image: &'a [u8],
+    segments: std::vec::IntoIter<(usize, usize, usize)>,
+    current: Option<NoteIter<'a>>,
+    remaining: usize,
+    elf64: bool,
+    encoding: Endianness,
+}
+
+impl<'a> SegmentNotes<'a> {
+    /// `segments` holds the file offset, size and alignment of each
+    /// `PT_NOTE`.
+    pub fn new(
+        image: &'a [u8],
+        segments: Vec<(usize, usize, usize)>,
+        elf64: bool,
+        encoding: Endianness,
+    ) -> Self {
+        Self {
+            image,
+            segments: segments.into_iter(),
+            current: None,
+            remaining: limits::limits().max_notes,
+            elf64,
+            encoding,
+        }
+    }
+}
+
+impl<'a> Iterator for SegmentNotes<'a> {
+    type Item = Note<'a>;
+
+    fn next(&mut self) -> Option<Note<'a>> {
+        if self.remaining == 0 {
+            return None;
+        }
+        loop {
+            if let Some(note) = self.current.as_mut().and_then(Iterator::next) {
+                self.remaining -= 1;
+                return Some(note);
+            }
+            let (offset, size, align) = self.segments.next()?;
+            let buf = offset
+                .checked_add(size)
+                .and_then(|end| self.image.get(offset..end))
+                .unwrap_or(&[]);
+            let align = note_align(ELF_T_BYTE, align);
+            self.current = Some(NoteIter::new(buf, align, self.elf64, self.encoding));
+        }
+    }
+}
+
+/// `siginfo_t` prefix shared by `NT_SIGINFO` and `NT_PRSTATUS`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SigInfo {
+    pub si_signo: i32,
+    pub si_errno: i32,
+    pub si_code: i32,
+}
+
+/// The architecture-independent head of `struct elf_prstatus`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PrStatus {
+    pub info: SigInfo,
+    pub cursig: u16,
+    pub pid: i32,
+    pub ppid: i32,
+    pub pgrp: i32,
+    pub sid: i32,
+}
+
+/// One mapping from an `NT_FILE` note.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MappedFile<'a> {
+    pub start: u64,
+    pub end: u64,
+    /// Offset into the file, in bytes.
+    pub offset: u64,
+    pub path: &'a [u8],
+}
+
+impl<'a> Note<'a> {
+    fn siginfo_at(&self, pos: usize) -> Option<SigInfo> {
+        Some(SigInfo {
+            si_signo: read_u32(self.desc, pos, self.encoding)? as i32,
+            si_errno: read_u32(self.desc, pos + 4, self.encoding)? as i32,
+            si_code: read_u32(self.desc, pos + 8, self.encoding)? as i32,
+        })
+    }
+
+    /// Decodes an `NT_SIGINFO` descriptor.
+    pub fn siginfo(&self) -> Option<SigInfo> {
+        if self.n_type != NT_SIGINFO {
+            return None;
+        }
+        self.siginfo_at(0)
+    }
+
+    /// Decodes the fields of an `NT_PRSTATUS` descriptor that precede the
+    /// architecture-specific register set.
+    pub fn prstatus(&self) -> Option<PrStatus> {
+        if self.n_type != NT_PRSTATUS {
+            return None;
+        }
+        let cursig = match self.encoding {
+            Endianness::Little => u16::from_le_bytes(self.desc.get(12..14)?.try_into().ok()?),
+            Endianness::Big => u16::from_be_bytes(self.desc.get(12..14)?.try_into().ok()?),
+        };
+        // pr_sigpend and pr_sighold are unsigned longs
+        let pids = if self.elf64 { 32 } else { 24 };
+        Some(PrStatus {
+            info: self.siginfo_at(0)?,
+            cursig,
+            pid: read_u32(self.desc, pids, self.encoding)? as i32,
+            ppid: read_u32(self.desc, pids + 4, self.encoding)? as i32,
+            pgrp: read_u32(self.desc, pids + 8, self.encoding)? as i32,
+            sid: read_u32(self.desc, pids + 12, self.encoding)? as i32,
+        })
+    }
+
+    /// Decodes an `NT_AUXV` descriptor, up to and excluding `AT_NULL`.
+    pub fn auxv(&self) -> Option<Vec<GElf_auxv_t>> {
+        if self.n_type != NT_AUXV {
+            return None;
+        }
+        let word = if self.elf64 { 8 } else { 4 };
+        let mut entries = Vec::new();
+        for pos in (0..self.desc.len() / (2 * word)).map(|i| i * 2 * word) {
+            let a_type = read_word(self.desc, pos, self.elf64, self.encoding)?;
+            if a_type == AT_NULL {
+                break;
+            }
+            let mut entry = GElf_auxv_t::default();
+            entry.a_type.set(NativeEndian, a_type);
+            let a_val = read_word(self.desc, pos + word, self.elf64, self.encoding)?;
+            entry.a_val.set(NativeEndian, a_val);
+            entries.push(entry);
+        }
+        Some(entries)
+    }
+
+    /// Decodes an `NT_FILE` descriptor.
+    pub fn mapped_files(&self) -> Option<Vec<MappedFile<'a>>> {
+        if self.n_type != NT_FILE {
+            return None;
+        }
+        let word = if self.elf64 { 8 } else { 4 };
+        let read = |pos| read_word(self.desc, pos, self.elf64, self.encoding);
+        let count = usize::try_from(read(0)?).ok()?;
+        let page_size = read(word)?;
+
+        let table = 2 * word;
+        let mut paths = self
+            .desc
+            .get(count.checked_mul(3 * word)?.checked_add(table)?..)?
+            .split(|&b| b == 0);
+
+        let mut files = Vec::with_capacity(count.min(self.desc.len() / (3 * word)));
+        for i in 0..count {
+            let pos = table + i * 3 * word;
+            files.push(MappedFile {
+                start: read(pos)?,
+                end: read(pos + word)?,
+                offset: read(pos + 2 * word)?.checked_mul(page_size)?,
+                path: paths.next()?,
+            });
+        }
+        Some(files)
+    }
+}
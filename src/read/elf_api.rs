@@ -1,383 +1,1069 @@
-use crate::error::set_error;
-use crate::handle::{Elf, Elf_Scn};
+use crate::error::*;
+use crate::handle::{Elf, Elf_Data_Scn, Elf_Scn, RawChunk, WriteShdr, locked};
+use crate::limits;
 use crate::types::*;
-use object::FileKind;
+use crate::xlate;
+use object::Endianness;
 use object::NativeEndian;
-use std::ffi::c_void;
+use std::ffi::{CStr, c_char, c_ulong, c_void};
+use std::fs::File;
+use std::io::Read;
+use std::mem::ManuallyDrop;
+use std::os::fd::FromRawFd;
+use std::os::unix::fs::FileExt;
 use std::ptr;
+use std::sync::atomic::{AtomicU32, Ordering};
 
+/// Version negotiated through `elf_version`; `EV_NONE` until then.
+static WORKING_VERSION: AtomicU32 = AtomicU32::new(EV_NONE as u32);
+
+/// The working version, stamped into every `Elf_Data` handed out.
+pub(crate) fn working_version() -> u32 {
+    WORKING_VERSION.load(Ordering::Relaxed)
+}
+
+/// Sets the working version and returns the previous one. With `EV_NONE`
+/// the version is only queried; before any negotiation both report
+/// `EV_CURRENT`.
 #[unsafe(no_mangle)]
 pub extern "C" fn elf_version(ver: u32) -> u32 {
-    if ver == EV_NONE.into() {
-        EV_CURRENT.into()
-    } else if ver > EV_CURRENT.into() {
-        EV_NONE.into()
-    } else {
-        EV_CURRENT.into()
+    guard(EV_NONE.into(), || {
+        let old = match working_version() {
+            v if v == u32::from(EV_NONE) => EV_CURRENT.into(),
+            v => v,
+        };
+        if ver == EV_NONE.into() {
+            return old;
+        }
+        if ver > EV_CURRENT.into() {
+            set_error(ELF_E_UNKNOWN_VERSION);
+            return EV_NONE.into();
+        }
+        WORKING_VERSION.store(ver, Ordering::Relaxed);
+        old
+    })
+}
+
+pub(crate) fn version_set() -> bool {
+    if working_version() == u32::from(EV_NONE) {
+        set_error(ELF_E_NO_VERSION);
+        return false;
     }
+    true
 }
 
-#[unsafe(no_mangle)]
-pub extern "C" fn elf_begin(fd: i32, cmd: ElfCmd, _ref_elf: *mut Elf) -> *mut Elf {
-    if fd < 0 {
-        set_error("invalid file descriptor");
+fn read_fd(fd: i32) -> std::io::Result<Vec<u8>> {
+    // The caller keeps ownership of the descriptor
+    let file = ManuallyDrop::new(unsafe { File::from_raw_fd(fd) });
+
+    // Most /proc files are regular but report a size of zero
+    let meta = file.metadata()?;
+    if meta.is_file() && meta.len() > 0 {
+        let mut buf = vec![0u8; meta.len() as usize];
+        file.read_exact_at(&mut buf, 0)?;
+        return Ok(buf);
+    }
+
+    // Pipes, sockets and /proc files can't be sized up front
+    let mut buf = Vec::new();
+    (&*file).read_to_end(&mut buf)?;
+    Ok(buf)
+}
+
+fn map_fd(fd: i32, cmd: ElfCmd) -> std::io::Result<memmap2::MmapRaw> {
+    let opts = memmap2::MmapOptions::new();
+    match cmd {
+        ELF_C_RDWR_MMAP => opts.map_raw(fd),
+        ELF_C_READ_MMAP_PRIVATE => unsafe { opts.map_copy(fd) }.map(Into::into),
+        _ => opts.map_raw_read_only(fd),
+    }
+}
+
+/// Hands out another activation of `ref_elf`, as libelf does for
+/// `elf_begin` calls that pass a reference descriptor.
+fn dup_elf(fd: i32, ref_elf: *mut Elf) -> *mut Elf {
+    let ref_ref = unsafe { &*ref_elf };
+
+    // The descriptor may be detached (elf_cntl) or the caller may pass -1
+    if fd != -1 && ref_ref.fd != -1 && fd != ref_ref.fd {
+        set_error(ELF_E_FD_MISMATCH);
         return ptr::null_mut();
     }
 
-    if cmd == ELF_C_WRITE {
-        let elf = Box::new(Elf {
-            fd,
-            cmd,
-            data: ptr::null(),
-            data_len: 0,
-            owned_data: None,
-            mmap: None,
-            parsed: None,
-            section_handles: Vec::new(),
-            data_handles: Vec::new(),
-            section_data_cache: Vec::new(),
-            writer: None,
-        });
-        return Box::into_raw(elf);
+    if !matches!(
+        ref_ref.cmd,
+        ELF_C_READ
+            | ELF_C_READ_MMAP
+            | ELF_C_READ_MMAP_PRIVATE
+            | ELF_C_RDWR
+            | ELF_C_RDWR_MMAP
+            | ELF_C_WRITE
+            | ELF_C_WRITE_MMAP
+    ) {
+        set_error(ELF_E_INVALID_OP);
+        return ptr::null_mut();
     }
 
-    let mmap = match memmap2::MmapOptions::new().map_raw(fd) {
-        Ok(m) => m,
-        Err(_) => {
-            set_error("mmap failed");
+    // Archive members would get their own descriptor, but archives aren't
+    // supported beyond elf_kind
+    if elf_kind(ref_elf) == ELF_K_AR {
+        set_error(ELF_E_INVALID_ARCHIVE);
+        return ptr::null_mut();
+    }
+
+    ref_ref.ref_count.fetch_add(1, Ordering::AcqRel);
+    ref_elf
+}
+
+#[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn elf_begin(fd: i32, cmd: ElfCmd, ref_elf: *mut Elf) -> *mut Elf {
+    guard(ptr::null_mut(), || {
+        if !version_set() || cmd == ELF_C_NULL {
             return ptr::null_mut();
         }
-    };
 
-    let data_len = mmap.len();
-    let data_ptr = mmap.as_ptr();
-
-    let elf = Box::new(Elf {
-        fd,
-        cmd,
-        data: data_ptr,
-        data_len,
-        owned_data: None,
-        mmap: Some(mmap),
-        parsed: None,
-        section_handles: Vec::new(),
-        data_handles: Vec::new(),
-        section_data_cache: Vec::new(),
-        writer: None,
-    });
+        if !ref_elf.is_null() {
+            match cmd {
+                ELF_C_READ | ELF_C_READ_MMAP | ELF_C_READ_MMAP_PRIVATE => {
+                    return dup_elf(fd, ref_elf);
+                }
+                ELF_C_RDWR | ELF_C_RDWR_MMAP => {
+                    let ref_cmd = unsafe { (*ref_elf).cmd };
+                    if ref_cmd != ELF_C_RDWR && ref_cmd != ELF_C_RDWR_MMAP {
+                        set_error(ELF_E_INVALID_CMD);
+                        return ptr::null_mut();
+                    }
+                    return dup_elf(fd, ref_elf);
+                }
+                _ => {}
+            }
+        }
 
-    Box::into_raw(elf)
+        if fd < 0 {
+            set_error(ELF_E_INVALID_FILE);
+            return ptr::null_mut();
+        }
+
+        match cmd {
+            ELF_C_WRITE | ELF_C_WRITE_MMAP => Box::into_raw(Box::new(Elf::new(fd, cmd))),
+            ELF_C_READ_MMAP | ELF_C_READ_MMAP_PRIVATE | ELF_C_RDWR_MMAP => {
+                let mut elf = Box::new(Elf::new(fd, cmd));
+                match map_fd(fd, cmd) {
+                    Ok(mmap) => {
+                        elf.data = mmap.as_ptr();
+                        elf.data_len = mmap.len();
+                        elf.mmap = Some(mmap);
+                    }
+                    // Not everything can be mapped (pipes, empty files); reading
+                    // is only an option when we don't need to share the mapping
+                    Err(_) if cmd != ELF_C_RDWR_MMAP => match read_fd(fd) {
+                        Ok(buf) => elf.set_owned_data(buf),
+                        Err(_) => {
+                            set_error(ELF_E_READ_ERROR);
+                            return ptr::null_mut();
+                        }
+                    },
+                    Err(_) => {
+                        set_error(ELF_E_READ_ERROR);
+                        return ptr::null_mut();
+                    }
+                }
+                Box::into_raw(elf)
+            }
+            ELF_C_READ | ELF_C_RDWR => match read_fd(fd) {
+                Ok(buf) => {
+                    let mut elf = Box::new(Elf::new(fd, cmd));
+                    elf.set_owned_data(buf);
+                    Box::into_raw(elf)
+                }
+                Err(_) => {
+                    set_error(ELF_E_READ_ERROR);
+                    ptr::null_mut()
+                }
+            },
+            _ => {
+                set_error(ELF_E_INVALID_CMD);
+                ptr::null_mut()
+            }
+        }
+    })
 }
 
 #[unsafe(no_mangle)]
-pub extern "C" fn elf_memory(image: *mut i8, size: usize) -> *mut Elf {
-    if image.is_null() || size == 0 {
-        set_error("invalid memory image");
-        return ptr::null_mut();
-    }
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn elf_cntl(elf: *mut Elf, cmd: ElfCmd) -> i32 {
+    guard(-1, || {
+        if elf.is_null() {
+            return -1;
+        }
 
-    let owned_data = unsafe { std::slice::from_raw_parts(image as *const u8, size).to_vec() };
-
-    let mut elf = Box::new(Elf {
-        fd: -1,
-        cmd: ELF_C_READ,
-        data: ptr::null(),
-        data_len: size,
-        owned_data: Some(owned_data),
-        mmap: None,
-        parsed: None,
-        section_handles: Vec::new(),
-        data_handles: Vec::new(),
-        section_data_cache: Vec::new(),
-        writer: None,
-    });
+        let elf_ref = unsafe { &mut *elf };
+        if elf_ref.fd < 0 {
+            set_error(ELF_E_INVALID_HANDLE);
+            return -1;
+        }
+
+        match cmd {
+            // The whole image is already in memory or mapped, which outlives the
+            // descriptor, so reading it in is a no-op
+            ELF_C_FDREAD | ELF_C_FDDONE => {
+                elf_ref.fd = -1;
+                0
+            }
+            _ => {
+                set_error(ELF_E_INVALID_CMD);
+                -1
+            }
+        }
+    })
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn elf_memory(image: *mut i8, size: usize) -> *mut Elf {
+    guard(ptr::null_mut(), || {
+        if !version_set() {
+            return ptr::null_mut();
+        }
+        if image.is_null() || size == 0 {
+            set_error(ELF_E_INVALID_OPERAND);
+            return ptr::null_mut();
+        }
 
-    elf.data = elf.owned_data.as_ref().unwrap().as_ptr();
-    Box::into_raw(elf)
+        // The caller's buffer stands in for a shared mapping: it is read in
+        // place, and elf_update writes back into it
+        let mut elf = Box::new(Elf::new(-1, ELF_C_RDWR_MMAP));
+        elf.data = image as *const u8;
+        elf.data_len = size;
+        Box::into_raw(elf)
+    })
 }
 
 #[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
 pub extern "C" fn elf_end(elf: *mut Elf) -> i32 {
-    if elf.is_null() {
-        return 0;
-    }
-    unsafe { drop(Box::from_raw(elf)) };
-    0
+    guard(0, || {
+        if elf.is_null() {
+            return 0;
+        }
+
+        let remaining = unsafe { (*elf).ref_count.fetch_sub(1, Ordering::AcqRel) } - 1;
+        if remaining > 0 {
+            return remaining as i32;
+        }
+
+        let source = unsafe { (*elf).source };
+        unsafe { drop(Box::from_raw(elf)) };
+        // A clone borrows its source's image, so it held a reference to it
+        elf_end(source);
+        0
+    })
 }
 
+/// Only `ELF_C_EMPTY` is supported: the clone shares the descriptor,
+/// command, image, kind and class of `elf` but starts out dirty with no
+/// sections handed out. It holds a reference to `elf`, which is released
+/// when the clone is ended.
 #[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn elf_clone(elf: *mut Elf, cmd: ElfCmd) -> *mut Elf {
+    guard(ptr::null_mut(), || {
+        if elf.is_null() {
+            return ptr::null_mut();
+        }
+        if cmd != ELF_C_EMPTY {
+            set_error(ELF_E_INVALID_CMD);
+            return ptr::null_mut();
+        }
+
+        Box::into_raw(Box::new(Elf::clone_of(elf)))
+    })
+}
+
+#[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
 pub extern "C" fn elf_kind(elf: *mut Elf) -> ElfKind {
-    if elf.is_null() {
-        return ELF_K_NONE;
-    }
+    guard(ELF_K_NONE, || {
+        if elf.is_null() {
+            return ELF_K_NONE;
+        }
 
-    let elf = unsafe { &*elf };
-    let data = unsafe { std::slice::from_raw_parts(elf.data, elf.data_len) };
+        unsafe { (*elf).kind() }
+    })
+}
 
-    match FileKind::parse(data) {
-        Ok(FileKind::Elf32) | Ok(FileKind::Elf64) => ELF_K_ELF,
-        Ok(FileKind::Archive) => ELF_K_AR,
-        _ => ELF_K_NONE,
-    }
+#[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn elf_getident(elf: *mut Elf, nbytes: *mut usize) -> *mut c_char {
+    guard(ptr::null_mut(), || {
+        if elf.is_null() {
+            return ptr::null_mut();
+        }
+
+        if elf_kind(elf) != ELF_K_ELF {
+            if !nbytes.is_null() {
+                unsafe { *nbytes = 0 };
+            }
+            return ptr::null_mut();
+        }
+
+        if !nbytes.is_null() {
+            unsafe { *nbytes = EI_NIDENT };
+        }
+        unsafe { (*elf).data as *mut c_char }
+    })
 }
 
+/// Archive members aren't supported, so every image starts at offset 0.
 #[unsafe(no_mangle)]
-pub extern "C" fn elf_nextscn(elf: *mut Elf, scn: *mut Elf_Scn) -> *mut Elf_Scn {
+pub extern "C" fn elf_getbase(elf: *mut Elf) -> i64 {
+    guard(-1, || {
+        if elf.is_null() {
+            return -1;
+        }
+        0
+    })
+}
+
+#[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn elf_rawfile(elf: *mut Elf, nbytes: *mut usize) -> *mut c_char {
+    guard(ptr::null_mut(), || {
+        let image = if elf.is_null() {
+            &[][..]
+        } else {
+            unsafe { (*elf).image() }
+        };
+
+        if image.is_empty() {
+            set_error(ELF_E_INVALID_HANDLE);
+            if !nbytes.is_null() {
+                unsafe { *nbytes = 0 };
+            }
+            return ptr::null_mut();
+        }
+
+        if !nbytes.is_null() {
+            unsafe { *nbytes = image.len() };
+        }
+        image.as_ptr() as *mut c_char
+    })
+}
+
+/// Like libelf, a section starting exactly at `offset` wins, preferring one
+/// that occupies file space over empty ones at the same offset. Otherwise
+/// the section whose file contents span `offset` is returned.
+fn offscn(elf: *mut Elf, offset: u64, want_elf64: bool) -> *mut Elf_Scn {
     if elf.is_null() {
         return ptr::null_mut();
     }
 
-    let elf_ref = unsafe { &mut *elf };
+    let elf_ref = unsafe { &*elf };
     if !elf_ref.ensure_parsed() {
         return ptr::null_mut();
     }
 
-    let next_idx = if scn.is_null() {
-        1 // skip section 0 (null section)
-    } else {
-        unsafe { (*scn).index + 1 }
-    };
+    let found = elf_ref.with_parsed(|p| {
+        if p.is_elf64() != want_elf64 {
+            return Err(ELF_E_INVALID_CLASS);
+        }
 
-    let section_count = elf_ref.with_parsed(|p| p.section_count()).unwrap_or(0);
-    if next_idx >= section_count {
-        return ptr::null_mut();
+        let mut exact_empty = None;
+        let mut containing = None;
+        for index in 0..p.section_count()? {
+            let Some(shdr) = p.section_header(index) else {
+                continue;
+            };
+            let start = shdr.sh_offset.get(NativeEndian);
+            let size = shdr.sh_size.get(NativeEndian);
+            let in_file = size != 0 && shdr.sh_type.get(NativeEndian) != SHT_NOBITS;
+            if start == offset {
+                if in_file {
+                    return Ok(Some(index));
+                }
+                exact_empty.get_or_insert(index);
+            } else if in_file && offset > start && offset - start < size {
+                containing.get_or_insert(index);
+            }
+        }
+        Ok(exact_empty.or(containing))
+    });
+
+    match found {
+        Some(Ok(Some(index))) => elf_getscn(elf, index),
+        Some(Ok(None)) | None => ptr::null_mut(),
+        Some(Err(e)) => {
+            set_error(e);
+            ptr::null_mut()
+        }
     }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn elf32_offscn(elf: *mut Elf, offset: u32) -> *mut Elf_Scn {
+    guard(ptr::null_mut(), || offscn(elf, offset.into(), false))
+}
 
-    let scn_box = Box::new(Elf_Scn::new(elf, next_idx));
-    let scn_ptr = Box::into_raw(scn_box);
-    elf_ref.section_handles.push(scn_ptr);
-    scn_ptr
+#[unsafe(no_mangle)]
+pub extern "C" fn elf64_offscn(elf: *mut Elf, offset: u64) -> *mut Elf_Scn {
+    guard(ptr::null_mut(), || offscn(elf, offset, true))
 }
 
 #[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn elf_nextscn(elf: *mut Elf, scn: *mut Elf_Scn) -> *mut Elf_Scn {
+    guard(ptr::null_mut(), || {
+        if elf.is_null() {
+            return ptr::null_mut();
+        }
+
+        let elf_ref = unsafe { &*elf };
+        if !elf_ref.ensure_parsed() {
+            return ptr::null_mut();
+        }
+
+        let next_idx = if scn.is_null() {
+            1 // skip section 0 (null section)
+        } else {
+            unsafe { (*scn).index + 1 }
+        };
+
+        let section_count = match elf_ref.with_parsed(|p| p.section_count()) {
+            Some(Ok(count)) => count,
+            Some(Err(e)) => {
+                set_error(e);
+                return ptr::null_mut();
+            }
+            None => 0,
+        };
+        if next_idx >= section_count {
+            return ptr::null_mut();
+        }
+
+        elf_ref.scn_handle(next_idx)
+    })
+}
+
+#[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
 pub extern "C" fn elf_getscn(elf: *mut Elf, index: usize) -> *mut Elf_Scn {
-    if elf.is_null() {
-        return ptr::null_mut();
-    }
+    guard(ptr::null_mut(), || {
+        if elf.is_null() {
+            return ptr::null_mut();
+        }
 
-    let elf_ref = unsafe { &mut *elf };
-    if !elf_ref.ensure_parsed() {
-        return ptr::null_mut();
-    }
+        let elf_ref = unsafe { &*elf };
+        if !elf_ref.ensure_parsed() {
+            return ptr::null_mut();
+        }
 
-    let section_count = elf_ref.with_parsed(|p| p.section_count()).unwrap_or(0);
-    if index >= section_count {
-        set_error("invalid section index");
-        return ptr::null_mut();
-    }
+        let section_count = match elf_ref.with_parsed(|p| p.section_count()) {
+            Some(Ok(count)) => count,
+            Some(Err(e)) => {
+                set_error(e);
+                return ptr::null_mut();
+            }
+            None => 0,
+        };
+        if index >= section_count {
+            set_error(ELF_E_INVALID_INDEX);
+            return ptr::null_mut();
+        }
 
-    let scn_box = Box::new(Elf_Scn::new(elf, index));
-    let scn_ptr = Box::into_raw(scn_box);
-    elf_ref.section_handles.push(scn_ptr);
-    scn_ptr
+        elf_ref.scn_handle(index)
+    })
 }
 
 #[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
 pub extern "C" fn elf_ndxscn(scn: *mut Elf_Scn) -> usize {
-    if scn.is_null() {
-        return 0;
-    }
-    unsafe { (*scn).index }
+    guard(0, || {
+        if scn.is_null() {
+            return 0;
+        }
+        unsafe { (*scn).index }
+    })
 }
 
 #[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
 pub extern "C" fn elf_getshdrstrndx(elf: *mut Elf, dst: *mut usize) -> i32 {
-    if elf.is_null() || dst.is_null() {
-        set_error("invalid argument");
-        return -1;
-    }
-
-    let elf_ref = unsafe { &mut *elf };
-    if !elf_ref.ensure_parsed() {
-        return -1;
-    }
+    guard(-1, || {
+        if elf.is_null() || dst.is_null() {
+            set_error(ELF_E_INVALID_OPERAND);
+            return -1;
+        }
 
-    match elf_ref.with_parsed(|p| p.shstrndx()) {
-        Some(idx) => {
-            unsafe { *dst = idx };
-            0
+        let elf_ref = unsafe { &*elf };
+        if !elf_ref.ensure_parsed() {
+            return -1;
         }
-        None => {
-            set_error("failed to get shstrndx");
-            -1
+
+        match elf_ref.with_parsed(|p| p.shstrndx()) {
+            Some(Ok(idx)) => {
+                unsafe { *dst = idx };
+                0
+            }
+            Some(Err(e)) => {
+                set_error(e);
+                -1
+            }
+            None => {
+                set_error(ELF_E_INVALID_HANDLE);
+                -1
+            }
         }
-    }
+    })
 }
 
 #[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
 pub extern "C" fn elf_getphdrnum(elf: *mut Elf, dst: *mut usize) -> i32 {
-    if elf.is_null() || dst.is_null() {
-        set_error("invalid argument");
-        return -1;
-    }
-
-    let elf_ref = unsafe { &mut *elf };
-    if !elf_ref.ensure_parsed() {
-        return -1;
-    }
+    guard(-1, || {
+        if elf.is_null() || dst.is_null() {
+            set_error(ELF_E_INVALID_OPERAND);
+            return -1;
+        }
 
-    match elf_ref.with_parsed(|p| p.program_header_count()) {
-        Some(count) => {
-            unsafe { *dst = count };
-            0
+        let elf_ref = unsafe { &*elf };
+        if !elf_ref.ensure_parsed() {
+            return -1;
         }
-        None => {
-            set_error("failed to get phdrnum");
-            -1
+
+        match elf_ref.with_parsed(|p| p.program_header_count()) {
+            Some(Ok(count)) => {
+                unsafe { *dst = count };
+                0
+            }
+            Some(Err(e)) => {
+                set_error(e);
+                -1
+            }
+            None => {
+                set_error(ELF_E_INVALID_HANDLE);
+                -1
+            }
         }
-    }
+    })
 }
 
+/// Walks the data chain of `scn`: the first descriptor for a null `data`,
+/// else the one after `data`. The file contents are loaded on first use.
 #[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
 pub extern "C" fn elf_getdata(scn: *mut Elf_Scn, data: *mut Elf_Data) -> *mut Elf_Data {
-    if scn.is_null() {
-        return ptr::null_mut();
-    }
+    guard(ptr::null_mut(), || {
+        if scn.is_null() {
+            return ptr::null_mut();
+        }
 
-    if !data.is_null() {
-        return ptr::null_mut();
-    }
+        let elf = unsafe { &*(*scn).elf };
+        if let Err(e) = elf.load_scn_data(scn) {
+            set_error(e);
+            return ptr::null_mut();
+        }
 
-    let scn_ref = unsafe { &*scn };
-    let elf = unsafe { &mut *scn_ref.elf };
+        let scn_ref = unsafe { &*scn };
+        if data.is_null() {
+            return scn_ref.data_list_head as *mut Elf_Data;
+        }
 
-    if !elf.ensure_parsed() {
-        return ptr::null_mut();
-    }
+        let data_scn = unsafe { &*(data as *mut Elf_Data_Scn) };
+        if data_scn.scn != scn {
+            set_error(ELF_E_DATA_MISMATCH);
+            return ptr::null_mut();
+        }
+        data_scn.next as *mut Elf_Data
+    })
+}
 
-    let section_data = elf.with_parsed(|p| p.section_data(scn_ref.index).map(|d| d.to_vec()));
+#[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn elf_getdata_rawchunk(
+    elf: *mut Elf,
+    offset: i64,
+    size: usize,
+    ty: ElfType,
+) -> *mut Elf_Data {
+    guard(ptr::null_mut(), || {
+        if elf.is_null() {
+            return ptr::null_mut();
+        }
 
-    match section_data {
-        Some(Some(data_vec)) => {
-            let idx = elf.section_data_cache.len();
-            elf.section_data_cache.push(data_vec);
+        let elf_ref = unsafe { &*elf };
+        if !elf_ref.ensure_parsed() {
+            return ptr::null_mut();
+        }
 
-            let shdr = elf.with_parsed(|p| p.get_shdr(scn_ref.index)).flatten();
-            let align = shdr
-                .map(|s| s.sh_addralign.get(NativeEndian) as usize)
-                .unwrap_or(1);
+        if offset < 0
+            || offset as u64 > elf_ref.data_len as u64
+            || elf_ref.data_len - (offset as usize) < size
+        {
+            set_error(ELF_E_INVALID_OP);
+            return ptr::null_mut();
+        }
+
+        // Held until the new chunk is recorded, so racing callers share it
+        let mut rawchunks = locked(&elf_ref.rawchunks);
+        if let Some(chunk) = rawchunks
+            .iter()
+            .find(|c| c.offset == offset && c.size == size && c.ty == ty)
+        {
+            return chunk.data;
+        }
 
-            let elf_data = Box::new(Elf_Data {
-                d_buf: elf.section_data_cache[idx].as_mut_ptr() as *mut c_void,
-                d_size: elf.section_data_cache[idx].len(),
-                d_type: ELF_T_BYTE,
-                d_version: EV_CURRENT.into(),
-                d_off: 0,
-                d_align: align.max(1),
-            });
+        let (elf64, endian) = match elf_ref.with_parsed(|p| (p.is_elf64(), p.endianness())) {
+            Some(v) => v,
+            None => return ptr::null_mut(),
+        };
 
-            let data_ptr = Box::into_raw(elf_data);
-            elf.data_handles.push(data_ptr);
-            data_ptr
+        // Use the image directly when it's already in memory representation,
+        // otherwise translate a copy
+        let align = xlate::type_align(ty, elf64);
+        let src = unsafe { elf_ref.data.add(offset as usize) };
+        let d_buf = if endian == Endianness::default() && (src as usize).is_multiple_of(align) {
+            src as *mut c_void
+        } else {
+            if let Err(e) = limits::check_data_size(size) {
+                set_error(e);
+                return ptr::null_mut();
+            }
+            let mut buf = unsafe { std::slice::from_raw_parts(src, size) }.to_vec();
+            xlate::xlatetom(&mut buf, ty, elf64, endian);
+            let mut cache = locked(&elf_ref.section_data_cache);
+            cache.push(buf);
+            cache.last_mut().unwrap().as_mut_ptr() as *mut c_void
+        };
+
+        let elf_data = Elf_Data {
+            d_buf,
+            d_type: ty,
+            d_version: working_version(),
+            d_size: size,
+            d_off: 0,
+            d_align: align,
+        };
+        let data_ptr = elf_ref.alloc_data(elf_data, ptr::null_mut());
+        rawchunks.push(RawChunk {
+            offset,
+            size,
+            ty,
+            data: data_ptr,
+        });
+        data_ptr
+    })
+}
+
+/// Data for the table the dynamic entry `tag` points at: `DT_SYMTAB`,
+/// `DT_STRTAB`, `DT_HASH`, `DT_GNU_HASH`, `DT_VERSYM`, `DT_VERDEF` or
+/// `DT_VERNEED`. The table is found through `PT_DYNAMIC`, so this works on
+/// files without section headers, and is read with `elf_getdata_rawchunk`.
+/// Not part of libelf.
+#[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn elf_getdata_dynamic(elf: *mut Elf, tag: i64) -> *mut Elf_Data {
+    guard(ptr::null_mut(), || {
+        if elf.is_null() {
+            return ptr::null_mut();
         }
-        _ => {
-            set_error("failed to get section data");
-            ptr::null_mut()
+
+        let elf_ref = unsafe { &*elf };
+        let dynamic = match elf_ref.dynamic() {
+            Ok(dynamic) => dynamic,
+            Err(e) => {
+                set_error(e);
+                return ptr::null_mut();
+            }
+        };
+        let table = u32::try_from(tag).ok().and_then(|tag| dynamic.table(tag));
+        match table {
+            Some(((offset, size), ty)) => elf_getdata_rawchunk(elf, offset as i64, size, ty),
+            None => {
+                set_error(ELF_E_INVALID_OPERAND);
+                ptr::null_mut()
+            }
         }
-    }
+    })
 }
 
 #[unsafe(no_mangle)]
 pub extern "C" fn elf_rawdata(scn: *mut Elf_Scn, data: *mut Elf_Data) -> *mut Elf_Data {
-    elf_getdata(scn, data)
+    guard(ptr::null_mut(), || elf_getdata(scn, data))
 }
 
+/// Returns the string at `offset` in string table `section`, looked up in
+/// the section's current data chain so strings added with `elf_newdata`
+/// resolve before `elf_update`.
 #[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
 pub extern "C" fn elf_strptr(elf: *mut Elf, section: usize, offset: usize) -> *const i8 {
-    if elf.is_null() {
-        return ptr::null();
-    }
+    guard(ptr::null(), || {
+        if elf.is_null() {
+            return ptr::null();
+        }
 
-    let elf_ref = unsafe { &mut *elf };
-    if !elf_ref.ensure_parsed() {
-        return ptr::null();
-    }
+        let elf_ref = unsafe { &*elf };
+        let scn = if elf_ref.data.is_null() {
+            // Being created: only sections from elf_newscn exist, without headers
+            match elf_ref.find_scn(section) {
+                Some(scn) => scn,
+                None => {
+                    set_error(ELF_E_INVALID_INDEX);
+                    return ptr::null();
+                }
+            }
+        } else {
+            if !elf_ref.ensure_parsed() {
+                return ptr::null();
+            }
+            let shdr = match elf_ref.with_parsed(|p| p.section_header(section)).flatten() {
+                Some(s) => s,
+                None => {
+                    set_error(ELF_E_INVALID_INDEX);
+                    return ptr::null();
+                }
+            };
+            if shdr.sh_type.get(NativeEndian) != SHT_STRTAB {
+                set_error(ELF_E_INVALID_SECTION);
+                return ptr::null();
+            }
+            if shdr.sh_flags.get(NativeEndian) & u64::from(SHF_COMPRESSED) != 0 {
+                set_error(ELF_E_ALREADY_COMPRESSED);
+                return ptr::null();
+            }
+            elf_ref.scn_handle(section)
+        };
 
-    let shdr = match elf_ref.with_parsed(|p| p.get_shdr(section)).flatten() {
-        Some(s) => s,
-        None => return ptr::null(),
-    };
+        if let Err(e) = elf_ref.load_scn_data(scn) {
+            set_error(e);
+            return ptr::null();
+        }
 
-    if shdr.sh_type.get(NativeEndian) != SHT_STRTAB {
-        set_error("not a string table section");
-        return ptr::null();
-    }
+        let layout = elf_ref.flag(ELF_F_LAYOUT);
+        let scn_ref = unsafe { &*scn };
+        for (start, data) in scn_ref.placed_data(layout) {
+            let d = &data.d;
+            if offset < start || offset - start >= d.d_size {
+                continue;
+            }
+            if d.d_buf.is_null() {
+                set_error(ELF_E_INVALID_SECTION);
+                return ptr::null();
+            }
+            let tail = match d.bytes() {
+                Ok(buf) => &buf[offset - start..],
+                Err(e) => {
+                    set_error(e);
+                    return ptr::null();
+                }
+            };
+            if !tail.contains(&0) {
+                set_error(ELF_E_INVALID_INDEX);
+                return ptr::null();
+            }
+            return tail.as_ptr() as *const i8;
+        }
 
-    let section_offset = shdr.sh_offset.get(NativeEndian) as usize;
-    let section_size = shdr.sh_size.get(NativeEndian) as usize;
+        set_error(ELF_E_OFFSET_RANGE);
+        ptr::null()
+    })
+}
 
-    if offset >= section_size {
-        set_error("offset out of bounds");
-        return ptr::null();
+/// Sets or clears `flags` in `target` and returns the new flags. The flags
+/// of a shared descriptor may be updated from several threads at once.
+fn update_flags(target: &AtomicU32, cmd: ElfCmd, flags: u32, allowed: u32) -> u32 {
+    let flags = flags & allowed;
+    match cmd {
+        ELF_C_SET => target.fetch_or(flags, Ordering::AcqRel) | flags,
+        ELF_C_CLR => target.fetch_and(!flags, Ordering::AcqRel) & !flags,
+        _ => {
+            set_error(ELF_E_INVALID_COMMAND);
+            0
+        }
     }
+}
 
-    unsafe { elf_ref.data.add(section_offset + offset) as *const i8 }
+/// `ELF_F_PERMISSIVE` selects how the program and section header tables
+/// are decoded. It takes effect as long as neither table has been read
+/// yet, whatever else has been queried; changing it later fails with
+/// `ELF_E_INVALID_OP` and leaves the flags as they were.
+#[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn elf_flagelf(elf: *mut Elf, cmd: ElfCmd, flags: u32) -> u32 {
+    guard(0, || {
+        if elf.is_null() {
+            return 0;
+        }
+        let elf_ref = unsafe { &*elf };
+        if flags & ELF_F_PERMISSIVE != 0
+            && matches!(cmd, ELF_C_SET | ELF_C_CLR)
+            && let Some(file) = elf_ref.parsed.get()
+            && let Err(e) = file.set_permissive(cmd == ELF_C_SET)
+        {
+            set_error(e);
+            return 0;
+        }
+        let allowed = ELF_F_DIRTY | ELF_F_LAYOUT | ELF_F_PERMISSIVE;
+        update_flags(unsafe { &(*elf).flags }, cmd, flags, allowed)
+    })
 }
 
 #[unsafe(no_mangle)]
-pub extern "C" fn elf_flagdata(_data: *mut Elf_Data, _cmd: ElfCmd, flags: u32) -> u32 {
-    flags
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn elf_flagehdr(elf: *mut Elf, cmd: ElfCmd, flags: u32) -> u32 {
+    guard(0, || {
+        if elf.is_null() {
+            return 0;
+        }
+        update_flags(unsafe { &(*elf).ehdr_flags }, cmd, flags, ELF_F_DIRTY)
+    })
 }
 
 #[unsafe(no_mangle)]
-pub extern "C" fn elf_flagshdr(scn: *mut Elf_Scn, _cmd: ElfCmd, flags: u32) -> u32 {
-    if scn.is_null() {
-        return 0;
-    }
-    unsafe { (*scn).flags |= flags };
-    flags
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn elf_flagphdr(elf: *mut Elf, cmd: ElfCmd, flags: u32) -> u32 {
+    guard(0, || {
+        if elf.is_null() {
+            return 0;
+        }
+        update_flags(unsafe { &(*elf).phdr_flags }, cmd, flags, ELF_F_DIRTY)
+    })
 }
 
 #[unsafe(no_mangle)]
-pub extern "C" fn elf_getshdrnum(elf: *mut Elf, dst: *mut usize) -> i32 {
-    if elf.is_null() || dst.is_null() {
-        set_error("invalid argument");
-        return -1;
-    }
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn elf_flagscn(scn: *mut Elf_Scn, cmd: ElfCmd, flags: u32) -> u32 {
+    guard(0, || {
+        if scn.is_null() {
+            return 0;
+        }
+        update_flags(unsafe { &(*scn).flags }, cmd, flags, ELF_F_DIRTY)
+    })
+}
 
-    let elf_ref = unsafe { &mut *elf };
-    if !elf_ref.ensure_parsed() {
-        return -1;
-    }
+#[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn elf_flagshdr(scn: *mut Elf_Scn, cmd: ElfCmd, flags: u32) -> u32 {
+    guard(0, || {
+        if scn.is_null() {
+            return 0;
+        }
+        update_flags(unsafe { &(*scn).shdr_flags }, cmd, flags, ELF_F_DIRTY)
+    })
+}
 
-    match elf_ref.with_parsed(|p| p.section_count()) {
-        Some(count) => {
-            unsafe { *dst = count };
-            0
+#[unsafe(no_mangle)]
+pub extern "C" fn elf_flagdata(data: *mut Elf_Data, cmd: ElfCmd, flags: u32) -> u32 {
+    guard(0, || {
+        if data.is_null() {
+            return 0;
         }
-        None => {
-            set_error("failed to get shdrnum");
-            -1
+        let data_scn = data as *mut Elf_Data_Scn;
+        update_flags(unsafe { &(*data_scn).flags }, cmd, flags, ELF_F_DIRTY)
+    })
+}
+
+#[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn elf_hash(string: *const c_char) -> c_ulong {
+    guard(0, || {
+        if string.is_null() {
+            return 0;
         }
-    }
+        let name = unsafe { CStr::from_ptr(string) };
+        object::elf::hash(name.to_bytes()).into()
+    })
+}
+
+#[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn elf_gnu_hash(string: *const c_char) -> c_ulong {
+    guard(0, || {
+        if string.is_null() {
+            return 0;
+        }
+        let name = unsafe { CStr::from_ptr(string) };
+        object::elf::gnu_hash(name.to_bytes()).into()
+    })
 }
 
+#[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn elf_getshdrnum(elf: *mut Elf, dst: *mut usize) -> i32 {
+    guard(-1, || {
+        if elf.is_null() || dst.is_null() {
+            set_error(ELF_E_INVALID_OPERAND);
+            return -1;
+        }
+
+        let elf_ref = unsafe { &*elf };
+        if !elf_ref.ensure_parsed() {
+            return -1;
+        }
+
+        match elf_ref.with_parsed(|p| p.section_count()) {
+            Some(Ok(count)) => {
+                unsafe { *dst = count };
+                0
+            }
+            Some(Err(e)) => {
+                set_error(e);
+                -1
+            }
+            None => {
+                set_error(ELF_E_INVALID_HANDLE);
+                -1
+            }
+        }
+    })
+}
+
+pub type Elf32_Ehdr = object::elf::FileHeader32<object::Endianness>;
 pub type Elf64_Ehdr = object::elf::FileHeader64<object::Endianness>;
+pub type Elf32_Shdr = object::elf::SectionHeader32<object::Endianness>;
 pub type Elf64_Shdr = object::elf::SectionHeader64<object::Endianness>;
 
-#[unsafe(no_mangle)]
-pub extern "C" fn elf64_getehdr(elf: *mut Elf) -> *mut Elf64_Ehdr {
+/// The header of a file opened for reading, pointing into the image, or the
+/// one `elf32_newehdr`/`elf64_newehdr` created. Fails with
+/// `ELF_E_INVALID_CLASS` unless the file is of `class`.
+fn getehdr(elf: *mut Elf, class: u8) -> *mut c_void {
     if elf.is_null() {
         return ptr::null_mut();
     }
 
-    let elf_ref = unsafe { &*elf };
-    elf_ref.data as *mut Elf64_Ehdr
+    let elf_ref = unsafe { &mut *elf };
+    if elf_ref.data.is_null() {
+        return match elf_ref.writer.as_mut().and_then(|w| w.ehdr.as_mut()) {
+            Some(ehdr) if ehdr.class() == class => ehdr.as_ptr(),
+            Some(_) => {
+                set_error(ELF_E_INVALID_CLASS);
+                ptr::null_mut()
+            }
+            None => {
+                set_error(ELF_E_WRONG_ORDER_EHDR);
+                ptr::null_mut()
+            }
+        };
+    }
+    if !elf_ref.ensure_parsed() {
+        return ptr::null_mut();
+    }
+    let (elf32, size) = match class {
+        ELFCLASS32 => (true, size_of::<Elf32_Ehdr>()),
+        _ => (false, size_of::<Elf64_Ehdr>()),
+    };
+    if elf_ref
+        .with_parsed(|p| p.is_elf32() != elf32)
+        .unwrap_or(true)
+        || elf_ref.data_len < size
+    {
+        set_error(ELF_E_INVALID_CLASS);
+        return ptr::null_mut();
+    }
+    elf_ref.data as *mut c_void
 }
 
+/// The ELF32 header of a file opened for reading, pointing into the image,
+/// or the one `elf32_newehdr` created.
 #[unsafe(no_mangle)]
-pub extern "C" fn elf64_getshdr(scn: *mut Elf_Scn) -> *mut Elf64_Shdr {
+pub extern "C" fn elf32_getehdr(elf: *mut Elf) -> *mut Elf32_Ehdr {
+    guard(ptr::null_mut(), || {
+        getehdr(elf, ELFCLASS32) as *mut Elf32_Ehdr
+    })
+}
+
+/// The ELF64 header of a file opened for reading, pointing into the image,
+/// or the one `elf64_newehdr` created.
+#[unsafe(no_mangle)]
+pub extern "C" fn elf64_getehdr(elf: *mut Elf) -> *mut Elf64_Ehdr {
+    guard(ptr::null_mut(), || {
+        getehdr(elf, ELFCLASS64) as *mut Elf64_Ehdr
+    })
+}
+
+fn getshdr(scn: *mut Elf_Scn, class: u8) -> *mut c_void {
     if scn.is_null() {
         return ptr::null_mut();
     }
 
-    let scn_ref = unsafe { &*scn };
+    let scn_ref = unsafe { &mut *scn };
     let elf = unsafe { &*scn_ref.elf };
+    if elf.data.is_null() {
+        let Some(ehdr) = elf.writer.as_ref().and_then(|w| w.ehdr.as_ref()) else {
+            set_error(ELF_E_WRONG_ORDER_EHDR);
+            return ptr::null_mut();
+        };
+        if ehdr.class() != class {
+            set_error(ELF_E_INVALID_CLASS);
+            return ptr::null_mut();
+        }
+        return scn_ref
+            .shdr
+            .get_or_insert_with(|| WriteShdr::new(ehdr.elf64()))
+            .as_ptr();
+    }
+    if !elf.ensure_parsed() {
+        return ptr::null_mut();
+    }
 
-    let ehdr = elf.data as *const Elf64_Ehdr;
-    let shoff = unsafe { (*ehdr).e_shoff.get(object::Endianness::Little) } as usize;
-    let shentsize = unsafe { (*ehdr).e_shentsize.get(object::Endianness::Little) } as usize;
+    let size = match class {
+        ELFCLASS32 => size_of::<Elf32_Shdr>() as u64,
+        _ => size_of::<Elf64_Shdr>() as u64,
+    };
+    let index = scn_ref.index;
+    let located = elf.with_parsed(|p| {
+        if p.is_elf64() != (class == ELFCLASS64) {
+            return Err(ELF_E_INVALID_CLASS);
+        }
+        if index >= p.section_count()? {
+            return Err(ELF_E_INVALID_INDEX);
+        }
+        let header = p.header();
+        let shentsize = header.e_shentsize.get(NativeEndian);
+        Ok((header.e_shoff.get(NativeEndian), u64::from(shentsize)))
+    });
+    let (shoff, shentsize) = match located {
+        Some(Ok(v)) => v,
+        Some(Err(e)) => {
+            set_error(e);
+            return ptr::null_mut();
+        }
+        None => return ptr::null_mut(),
+    };
 
-    let shdr_offset = shoff + scn_ref.index * shentsize;
-    unsafe { elf.data.add(shdr_offset) as *mut Elf64_Shdr }
+    let offset = (index as u64)
+        .checked_mul(shentsize)
+        .and_then(|o| o.checked_add(shoff))
+        .filter(|&o| {
+            shentsize >= size
+                && o.checked_add(size)
+                    .is_some_and(|end| end <= elf.data_len as u64)
+        });
+    match offset {
+        Some(offset) => unsafe { elf.data.add(offset as usize) as *mut c_void },
+        None => {
+            set_error(ELF_E_INVALID_SECTION_HEADER);
+            ptr::null_mut()
+        }
+    }
+}
+
+/// The ELF32 header of a section, pointing into the image, whose whole entry
+/// must lie inside the file; or, for a section added with `elf_newscn`, the
+/// header `elf_update` writes for it.
+#[unsafe(no_mangle)]
+pub extern "C" fn elf32_getshdr(scn: *mut Elf_Scn) -> *mut Elf32_Shdr {
+    guard(ptr::null_mut(), || {
+        getshdr(scn, ELFCLASS32) as *mut Elf32_Shdr
+    })
+}
+
+/// The ELF64 header of a section, pointing into the image, whose whole entry
+/// must lie inside the file; or, for a section added with `elf_newscn`, the
+/// header `elf_update` writes for it.
+#[unsafe(no_mangle)]
+pub extern "C" fn elf64_getshdr(scn: *mut Elf_Scn) -> *mut Elf64_Shdr {
+    guard(ptr::null_mut(), || {
+        getshdr(scn, ELFCLASS64) as *mut Elf64_Shdr
+    })
 }
@@ -1,247 +1,350 @@
-use crate::error::set_error;
-use crate::handle::{Elf, Elf_Scn};
+use crate::bytes::{
+    read_u16, read_u32, read_u64, read_word, write_u16, write_u32, write_u64, write_word,
+};
+use crate::error::*;
+use crate::file::Symbols;
+use crate::handle::{Elf, Elf_Data_Scn, Elf_Scn, WriteShdr};
+use crate::note::{note_align, note_at};
+use crate::relr::RelrIter;
 use crate::types::*;
 use object::Endianness;
 use object::NativeEndian;
+use std::ffi::{CStr, c_char};
+use std::ops::Range;
 use std::ptr;
+use std::sync::atomic::Ordering;
 
 #[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
 pub extern "C" fn gelf_getclass(elf: *mut Elf) -> i32 {
-    if elf.is_null() {
-        return ELFCLASSNONE as i32;
-    }
+    guard(ELFCLASSNONE as i32, || {
+        if elf.is_null() {
+            return ELFCLASSNONE as i32;
+        }
 
-    let elf_ref = unsafe { &mut *elf };
-    if !elf_ref.ensure_parsed() {
-        return ELFCLASSNONE as i32;
-    }
+        let elf_ref = unsafe { &*elf };
+        if let Some(ehdr) = elf_ref.writer.as_ref().and_then(|w| w.ehdr.as_ref()) {
+            return ehdr.class() as i32;
+        }
+        if !elf_ref.ensure_parsed() {
+            return ELFCLASSNONE as i32;
+        }
 
-    elf_ref
-        .with_parsed(|p| {
-            if p.is_elf32() {
-                ELFCLASS32 as i32
-            } else {
-                ELFCLASS64 as i32
-            }
-        })
-        .unwrap_or(ELFCLASSNONE as i32)
+        elf_ref
+            .with_parsed(|p| {
+                if p.is_elf32() {
+                    ELFCLASS32 as i32
+                } else {
+                    ELFCLASS64 as i32
+                }
+            })
+            .unwrap_or(ELFCLASSNONE as i32)
+    })
 }
 
 #[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
 pub extern "C" fn gelf_getehdr(elf: *mut Elf, dst: *mut GElf_Ehdr) -> *mut GElf_Ehdr {
-    if elf.is_null() || dst.is_null() {
-        set_error("invalid argument");
-        return ptr::null_mut();
-    }
+    guard(ptr::null_mut(), || {
+        if elf.is_null() || dst.is_null() {
+            set_error(ELF_E_INVALID_OPERAND);
+            return ptr::null_mut();
+        }
 
-    let elf_ref = unsafe { &mut *elf };
-    if !elf_ref.ensure_parsed() {
-        return ptr::null_mut();
-    }
+        let elf_ref = unsafe { &*elf };
+        if let Some(ehdr) = elf_ref.writer.as_ref().and_then(|w| w.ehdr.as_ref()) {
+            unsafe { *dst = ehdr.get() };
+            return dst;
+        }
+        if !elf_ref.ensure_parsed() {
+            return ptr::null_mut();
+        }
 
-    match elf_ref.with_parsed(|p| p.get_ehdr()) {
-        Some(ehdr) => {
-            unsafe { *dst = ehdr };
-            dst
+        match elf_ref.with_parsed(|p| p.header()) {
+            Some(ehdr) => {
+                unsafe { *dst = ehdr };
+                dst
+            }
+            None => ptr::null_mut(),
         }
-        None => ptr::null_mut(),
-    }
+    })
 }
 
 #[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
 pub extern "C" fn gelf_getshdr(scn: *mut Elf_Scn, dst: *mut GElf_Shdr) -> *mut GElf_Shdr {
-    if scn.is_null() || dst.is_null() {
-        set_error("invalid argument");
-        return ptr::null_mut();
-    }
+    guard(ptr::null_mut(), || {
+        if scn.is_null() || dst.is_null() {
+            set_error(ELF_E_INVALID_OPERAND);
+            return ptr::null_mut();
+        }
 
-    let scn_ref = unsafe { &*scn };
-    let elf = unsafe { &mut *scn_ref.elf };
+        let scn_ref = unsafe { &*scn };
+        let elf = unsafe { &*scn_ref.elf };
+
+        // A section added with `elf_newscn`: zero until it is set
+        if elf.data.is_null() {
+            unsafe {
+                *dst = match &scn_ref.shdr {
+                    Some(shdr) => shdr.get(),
+                    None => std::mem::zeroed(),
+                }
+            };
+            return dst;
+        }
+        if !elf.ensure_parsed() {
+            return ptr::null_mut();
+        }
 
-    if !elf.ensure_parsed() {
-        return ptr::null_mut();
-    }
+        match elf
+            .with_parsed(|p| p.section_header(scn_ref.index))
+            .flatten()
+        {
+            Some(shdr) => {
+                unsafe { *dst = shdr };
+                dst
+            }
+            None => {
+                set_error(ELF_E_INVALID_SECTION_HEADER);
+                ptr::null_mut()
+            }
+        }
+    })
+}
 
-    match elf.with_parsed(|p| p.get_shdr(scn_ref.index)).flatten() {
-        Some(shdr) => {
-            unsafe { *dst = shdr };
-            dst
+/// Sets the header of a section added with `elf_newscn`, in the class of
+/// the file's header. The headers of a file that was read are the image's
+/// and can't be replaced.
+#[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn gelf_update_shdr(scn: *mut Elf_Scn, src: *mut GElf_Shdr) -> i32 {
+    guard(0, || {
+        if scn.is_null() || src.is_null() {
+            set_error(ELF_E_INVALID_OPERAND);
+            return 0;
         }
-        None => {
-            set_error("failed to get section header");
-            ptr::null_mut()
+
+        let scn_ref = unsafe { &mut *scn };
+        let elf = unsafe { &*scn_ref.elf };
+        if !elf.data.is_null() {
+            set_error(ELF_E_INVALID_OPERAND);
+            return 0;
         }
-    }
+        let Some(ehdr) = elf.writer.as_ref().and_then(|w| w.ehdr.as_ref()) else {
+            set_error(ELF_E_WRONG_ORDER_EHDR);
+            return 0;
+        };
+
+        let shdr = scn_ref
+            .shdr
+            .get_or_insert_with(|| WriteShdr::new(ehdr.elf64()));
+        match shdr.set(unsafe { &*src }) {
+            Ok(()) => {
+                scn_ref.shdr_flags.fetch_or(ELF_F_DIRTY, Ordering::Relaxed);
+                1
+            }
+            Err(e) => {
+                set_error(e);
+                0
+            }
+        }
+    })
 }
 
 #[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
 pub extern "C" fn gelf_getphdr(elf: *mut Elf, index: i32, dst: *mut GElf_Phdr) -> *mut GElf_Phdr {
-    if elf.is_null() || dst.is_null() || index < 0 {
-        set_error("invalid argument");
-        return ptr::null_mut();
-    }
-
-    let elf_ref = unsafe { &mut *elf };
-    if !elf_ref.ensure_parsed() {
-        return ptr::null_mut();
-    }
+    guard(ptr::null_mut(), || {
+        if elf.is_null() || dst.is_null() || index < 0 {
+            set_error(ELF_E_INVALID_OPERAND);
+            return ptr::null_mut();
+        }
 
-    match elf_ref
-        .with_parsed(|p| p.get_phdr(index as usize))
-        .flatten()
-    {
-        Some(phdr) => {
-            unsafe { *dst = phdr };
-            dst
+        let elf_ref = unsafe { &*elf };
+        if !elf_ref.ensure_parsed() {
+            return ptr::null_mut();
         }
-        None => {
-            set_error("failed to get program header");
-            ptr::null_mut()
+
+        match elf_ref
+            .with_parsed(|p| p.program_header(index as usize))
+            .flatten()
+        {
+            Some(phdr) => {
+                unsafe { *dst = phdr };
+                dst
+            }
+            None => {
+                set_error(ELF_E_INVALID_INDEX);
+                ptr::null_mut()
+            }
         }
-    }
+    })
 }
 
+/// Symbol `ndx` of `data`, decoded as `ElfFile::symbols` does.
 #[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
 pub extern "C" fn gelf_getsym(data: *mut Elf_Data, ndx: i32, dst: *mut GElf_Sym) -> *mut GElf_Sym {
-    if data.is_null() || dst.is_null() || ndx < 0 {
-        set_error("invalid argument");
-        return ptr::null_mut();
-    }
-
-    let data_ref = unsafe { &*data };
-    let buf = unsafe { std::slice::from_raw_parts(data_ref.d_buf as *const u8, data_ref.d_size) };
-
-    let sym_size = std::mem::size_of::<object::elf::Sym64<Endianness>>();
-    let offset = (ndx as usize) * sym_size;
-
-    if offset + sym_size > buf.len() {
-        set_error("symbol index out of range");
-        return ptr::null_mut();
-    }
+    guard(ptr::null_mut(), || {
+        if dst.is_null() {
+            set_error(ELF_E_INVALID_OPERAND);
+            return ptr::null_mut();
+        }
+        let sym = record_at(data, ndx, 16, 24).and_then(|(rec, elf64, encoding)| {
+            Symbols::decode(rec, elf64, encoding).ok_or(ELF_E_INVALID_DATA)
+        });
+        match sym {
+            Ok(sym) => {
+                unsafe { *dst = sym };
+                dst
+            }
+            Err(e) => {
+                set_error(e);
+                ptr::null_mut()
+            }
+        }
+    })
+}
 
-    let sym: &object::elf::Sym64<Endianness> =
-        match object::pod::from_bytes(&buf[offset..offset + sym_size]) {
-            Ok((s, _)) => s,
-            Err(_) => return ptr::null_mut(),
-        };
+/// Looks up a dynamic symbol by name through the ELF hash tables; `version`
+/// may be null to get the default version. Not part of libelf.
+#[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn gelf_lookup_dynsym(
+    elf: *mut Elf,
+    name: *const c_char,
+    version: *const c_char,
+    dst: *mut GElf_Sym,
+    ndx: *mut usize,
+) -> *mut GElf_Sym {
+    guard(ptr::null_mut(), || {
+        if elf.is_null() || name.is_null() || dst.is_null() {
+            set_error(ELF_E_INVALID_OPERAND);
+            return ptr::null_mut();
+        }
 
-    let endian = Endianness::Little;
+        let elf_ref = unsafe { &*elf };
+        if !elf_ref.ensure_parsed() {
+            return ptr::null_mut();
+        }
 
-    unsafe {
-        (*dst).st_name.set(NativeEndian, sym.st_name.get(endian));
-        (*dst).st_info = sym.st_info;
-        (*dst).st_other = sym.st_other;
-        (*dst).st_shndx.set(NativeEndian, sym.st_shndx.get(endian));
-        (*dst).st_value.set(NativeEndian, sym.st_value.get(endian));
-        (*dst).st_size.set(NativeEndian, sym.st_size.get(endian));
-    }
+        let name = unsafe { CStr::from_ptr(name) }.to_bytes();
+        let version = (!version.is_null()).then(|| unsafe { CStr::from_ptr(version) }.to_bytes());
 
-    dst
+        match elf_ref.with_parsed(|p| p.lookup_dynsym(name, version)) {
+            Some(Ok(Some((index, sym)))) => {
+                unsafe { *dst = sym };
+                if !ndx.is_null() {
+                    unsafe { *ndx = index };
+                }
+                dst
+            }
+            Some(Ok(None)) | None => ptr::null_mut(),
+            Some(Err(e)) => {
+                set_error(e);
+                ptr::null_mut()
+            }
+        }
+    })
 }
 
 #[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
 pub extern "C" fn gelf_getversym(
     data: *mut Elf_Data,
     ndx: i32,
     dst: *mut GElf_Versym,
 ) -> *mut GElf_Versym {
-    if data.is_null() || dst.is_null() || ndx < 0 {
-        return ptr::null_mut();
-    }
-
-    let data_ref = unsafe { &*data };
-    let buf = unsafe { std::slice::from_raw_parts(data_ref.d_buf as *const u8, data_ref.d_size) };
-
-    let versym_size = std::mem::size_of::<GElf_Versym>();
-    let offset = (ndx as usize) * versym_size;
-
-    if offset + versym_size > buf.len() {
-        return ptr::null_mut();
-    }
+    guard(ptr::null_mut(), || {
+        if dst.is_null() {
+            set_error(ELF_E_INVALID_OPERAND);
+            return ptr::null_mut();
+        }
+        let (rec, _, encoding) = match record_at(data, ndx, 2, 2) {
+            Ok(r) => r,
+            Err(e) => {
+                set_error(e);
+                return ptr::null_mut();
+            }
+        };
 
-    let versym = u16::from_le_bytes([buf[offset], buf[offset + 1]]);
-    unsafe { (*dst).0.set(NativeEndian, versym) };
-    dst
+        let versym = read_u16(rec, 0, encoding).unwrap_or_default();
+        unsafe { (*dst).0.set(NativeEndian, versym) };
+        dst
+    })
 }
 
+/// The version definition at byte `offset` of `data`; the layout is the
+/// same in both classes.
 #[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
 pub extern "C" fn gelf_getverdef(
     data: *mut Elf_Data,
     offset: i32,
     dst: *mut GElf_Verdef,
 ) -> *mut GElf_Verdef {
-    if data.is_null() || dst.is_null() || offset < 0 {
-        return ptr::null_mut();
-    }
-
-    let data_ref = unsafe { &*data };
-    let buf = unsafe { std::slice::from_raw_parts(data_ref.d_buf as *const u8, data_ref.d_size) };
-
-    let off = offset as usize;
-    if off + 20 > buf.len() {
-        return ptr::null_mut();
-    }
-
-    let endian = Endianness::Little;
-    let verdef: &object::elf::Verdef<Endianness> = match object::pod::from_bytes(&buf[off..]) {
-        Ok((v, _)) => v,
-        Err(_) => return ptr::null_mut(),
-    };
-
-    unsafe {
-        (*dst)
-            .vd_version
-            .set(NativeEndian, verdef.vd_version.get(endian));
-        (*dst)
-            .vd_flags
-            .set(NativeEndian, verdef.vd_flags.get(endian));
-        (*dst).vd_ndx.set(NativeEndian, verdef.vd_ndx.get(endian));
-        (*dst).vd_cnt.set(NativeEndian, verdef.vd_cnt.get(endian));
-        (*dst).vd_hash.set(NativeEndian, verdef.vd_hash.get(endian));
-        (*dst).vd_aux.set(NativeEndian, verdef.vd_aux.get(endian));
-        (*dst).vd_next.set(NativeEndian, verdef.vd_next.get(endian));
-    }
+    guard(ptr::null_mut(), || {
+        if dst.is_null() {
+            set_error(ELF_E_INVALID_OPERAND);
+            return ptr::null_mut();
+        }
+        let (rec, encoding) = match entry_at(data, offset, 20) {
+            Ok(r) => r,
+            Err(e) => {
+                set_error(e);
+                return ptr::null_mut();
+            }
+        };
 
-    dst
+        let half = |pos| read_u16(rec, pos, encoding).unwrap_or_default();
+        let word = |pos| read_u32(rec, pos, encoding).unwrap_or_default();
+        unsafe {
+            (*dst).vd_version.set(NativeEndian, half(0));
+            (*dst).vd_flags.set(NativeEndian, half(2));
+            (*dst).vd_ndx.set(NativeEndian, half(4));
+            (*dst).vd_cnt.set(NativeEndian, half(6));
+            (*dst).vd_hash.set(NativeEndian, word(8));
+            (*dst).vd_aux.set(NativeEndian, word(12));
+            (*dst).vd_next.set(NativeEndian, word(16));
+        }
+        dst
+    })
 }
 
 #[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
 pub extern "C" fn gelf_getverdaux(
     data: *mut Elf_Data,
     offset: i32,
     dst: *mut GElf_Verdaux,
 ) -> *mut GElf_Verdaux {
-    if data.is_null() || dst.is_null() || offset < 0 {
-        return ptr::null_mut();
-    }
-
-    let data_ref = unsafe { &*data };
-    let buf = unsafe { std::slice::from_raw_parts(data_ref.d_buf as *const u8, data_ref.d_size) };
-
-    let off = offset as usize;
-    if off + 8 > buf.len() {
-        return ptr::null_mut();
-    }
-
-    let endian = Endianness::Little;
-    let verdaux: &object::elf::Verdaux<Endianness> = match object::pod::from_bytes(&buf[off..]) {
-        Ok((v, _)) => v,
-        Err(_) => return ptr::null_mut(),
-    };
-
-    unsafe {
-        (*dst)
-            .vda_name
-            .set(NativeEndian, verdaux.vda_name.get(endian));
-        (*dst)
-            .vda_next
-            .set(NativeEndian, verdaux.vda_next.get(endian));
-    }
+    guard(ptr::null_mut(), || {
+        if dst.is_null() {
+            set_error(ELF_E_INVALID_OPERAND);
+            return ptr::null_mut();
+        }
+        let (rec, encoding) = match entry_at(data, offset, 8) {
+            Ok(r) => r,
+            Err(e) => {
+                set_error(e);
+                return ptr::null_mut();
+            }
+        };
 
-    dst
+        unsafe {
+            (*dst)
+                .vda_name
+                .set(NativeEndian, read_u32(rec, 0, encoding).unwrap_or_default());
+            (*dst)
+                .vda_next
+                .set(NativeEndian, read_u32(rec, 4, encoding).unwrap_or_default());
+        }
+        dst
+    })
 }
 
 #[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
 pub extern "C" fn gelf_getnote(
     data: *mut Elf_Data,
     offset: usize,
@@ -249,49 +352,459 @@ pub extern "C" fn gelf_getnote(
     name_offset: *mut usize,
     desc_offset: *mut usize,
 ) -> usize {
-    if data.is_null() || nhdr.is_null() {
-        return 0;
-    }
+    guard(0, || {
+        if data.is_null() || nhdr.is_null() {
+            return 0;
+        }
+
+        let data_ref = unsafe { &*data };
+        let buf = match data_ref.bytes() {
+            Ok(buf) => buf,
+            Err(e) => {
+                set_error(e);
+                return 0;
+            }
+        };
 
-    let data_ref = unsafe { &*data };
-    let buf = unsafe { std::slice::from_raw_parts(data_ref.d_buf as *const u8, data_ref.d_size) };
+        if offset >= buf.len() {
+            return 0;
+        }
 
-    if offset >= buf.len() {
-        return 0;
-    }
+        let (_, encoding) = unsafe { (*(data as *mut Elf_Data_Scn)).layout() };
+        let align = note_align(data_ref.d_type, data_ref.d_align);
+        let entry = match note_at(buf, offset, encoding, align) {
+            Ok(e) => e,
+            Err(e) => {
+                set_error(e);
+                return 0;
+            }
+        };
 
-    let remaining = &buf[offset..];
-    if remaining.len() < 12 {
-        return 0;
-    }
+        unsafe {
+            (*nhdr).n_namesz.set(NativeEndian, entry.n_namesz);
+            (*nhdr).n_descsz.set(NativeEndian, entry.n_descsz);
+            (*nhdr).n_type.set(NativeEndian, entry.n_type);
+        }
+
+        if !name_offset.is_null() {
+            unsafe { *name_offset = entry.name_offset };
+        }
+        if !desc_offset.is_null() {
+            unsafe { *desc_offset = entry.desc_offset };
+        }
+
+        entry.next
+    })
+}
 
-    let n_namesz = u32::from_le_bytes([remaining[0], remaining[1], remaining[2], remaining[3]]);
-    let n_descsz = u32::from_le_bytes([remaining[4], remaining[5], remaining[6], remaining[7]]);
-    let n_type = u32::from_le_bytes([remaining[8], remaining[9], remaining[10], remaining[11]]);
+/// Where the `ndx`th fixed-size record of `data` lies, with the class and
+/// byte order it is stored in.
+fn record_span(
+    data: *mut Elf_Data,
+    ndx: i32,
+    size32: usize,
+    size64: usize,
+) -> Result<(Range<usize>, bool, Endianness), ElfError> {
+    if data.is_null() || ndx < 0 {
+        return Err(ELF_E_INVALID_OPERAND);
+    }
+    let (elf64, encoding) = unsafe { (*(data as *mut Elf_Data_Scn)).layout() };
+    let size = if elf64 { size64 } else { size32 };
+    let offset = (ndx as usize).saturating_mul(size);
+    Ok((offset..offset.saturating_add(size), elf64, encoding))
+}
 
-    unsafe {
-        (*nhdr).n_namesz.set(NativeEndian, n_namesz);
-        (*nhdr).n_descsz.set(NativeEndian, n_descsz);
-        (*nhdr).n_type.set(NativeEndian, n_type);
+/// The `ndx`th fixed-size record of `data`. Getters may run concurrently
+/// on one buffer, so this only reads it.
+fn record_at<'a>(
+    data: *mut Elf_Data,
+    ndx: i32,
+    size32: usize,
+    size64: usize,
+) -> Result<(&'a [u8], bool, Endianness), ElfError> {
+    let (span, elf64, encoding) = record_span(data, ndx, size32, size64)?;
+    let size = span.len();
+    match unsafe { &*data }.bytes()?.get(span) {
+        Some(record) if record.len() == size => Ok((record, elf64, encoding)),
+        _ => Err(ELF_E_INVALID_INDEX),
     }
+}
 
-    let align = 4usize;
-    let name_off = offset + 12;
-    let name_aligned_size = (n_namesz as usize + align - 1) & !(align - 1);
-    let desc_off = name_off + name_aligned_size;
-    let desc_aligned_size = (n_descsz as usize + align - 1) & !(align - 1);
-    let next_offset = desc_off + desc_aligned_size;
+/// Like `record_at`, for the `gelf_update_*` functions that write the
+/// record.
+fn record_at_mut<'a>(
+    data: *mut Elf_Data,
+    ndx: i32,
+    size32: usize,
+    size64: usize,
+) -> Result<(&'a mut [u8], bool, Endianness), ElfError> {
+    let (span, elf64, encoding) = record_span(data, ndx, size32, size64)?;
+    let size = span.len();
+    match unsafe { &mut *data }.bytes_mut()?.get_mut(span) {
+        Some(record) if record.len() == size => Ok((record, elf64, encoding)),
+        _ => Err(ELF_E_INVALID_INDEX),
+    }
+}
 
-    if !name_offset.is_null() {
-        unsafe { *name_offset = name_off };
+/// The `size` bytes at byte `offset` of `data`, for the chained records
+/// (version definitions and needs) that are found by offset rather than
+/// by index, and the byte order they are in.
+fn entry_at<'a>(
+    data: *mut Elf_Data,
+    offset: i32,
+    size: usize,
+) -> Result<(&'a [u8], Endianness), ElfError> {
+    if data.is_null() || offset < 0 {
+        return Err(ELF_E_INVALID_OPERAND);
     }
-    if !desc_offset.is_null() {
-        unsafe { *desc_offset = desc_off };
+    let (_, encoding) = unsafe { (*(data as *mut Elf_Data_Scn)).layout() };
+    let buf = unsafe { &*data }.bytes()?;
+    let offset = offset as usize;
+    match buf.get(offset..offset.saturating_add(size)) {
+        Some(entry) if entry.len() == size => Ok((entry, encoding)),
+        _ => Err(ELF_E_INVALID_INDEX),
     }
+}
+
+fn mark_dirty(data: *mut Elf_Data) {
+    let data = unsafe { &*(data as *const Elf_Data_Scn) };
+    data.flags.fetch_or(ELF_F_DIRTY, Ordering::Relaxed);
+}
+
+#[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn gelf_getauxv(
+    data: *mut Elf_Data,
+    ndx: i32,
+    dst: *mut GElf_auxv_t,
+) -> *mut GElf_auxv_t {
+    guard(ptr::null_mut(), || {
+        if dst.is_null() {
+            set_error(ELF_E_INVALID_OPERAND);
+            return ptr::null_mut();
+        }
+        let (rec, elf64, encoding) = match record_at(data, ndx, 8, 16) {
+            Ok(r) => r,
+            Err(e) => {
+                set_error(e);
+                return ptr::null_mut();
+            }
+        };
+
+        let word = if elf64 { 8 } else { 4 };
+        let a_type = read_word(rec, 0, elf64, encoding).unwrap_or_default();
+        let a_val = read_word(rec, word, elf64, encoding).unwrap_or_default();
+        unsafe {
+            (*dst).a_type.set(NativeEndian, a_type);
+            (*dst).a_val.set(NativeEndian, a_val);
+        }
+        dst
+    })
+}
 
-    if next_offset > buf.len() {
-        0
-    } else {
-        next_offset
+#[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn gelf_update_auxv(data: *mut Elf_Data, ndx: i32, src: *mut GElf_auxv_t) -> i32 {
+    guard(0, || {
+        if src.is_null() {
+            set_error(ELF_E_INVALID_OPERAND);
+            return 0;
+        }
+        let src = unsafe { &*src };
+        let result = record_at_mut(data, ndx, 8, 16).and_then(|(rec, elf64, encoding)| {
+            let word = if elf64 { 8 } else { 4 };
+            // Check both words before writing either
+            let mut tmp = [0u8; 16];
+            write_word(&mut tmp, src.a_type.get(NativeEndian), elf64, encoding)?;
+            write_word(
+                &mut tmp[word..],
+                src.a_val.get(NativeEndian),
+                elf64,
+                encoding,
+            )?;
+            rec.copy_from_slice(&tmp[..2 * word]);
+            Ok(())
+        });
+        update_result(data, result)
+    })
+}
+
+fn update_result(data: *mut Elf_Data, result: Result<(), ElfError>) -> i32 {
+    match result {
+        Ok(()) => {
+            mark_dirty(data);
+            1
+        }
+        Err(e) => {
+            set_error(e);
+            0
+        }
     }
 }
+
+#[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn gelf_getsyminfo(
+    data: *mut Elf_Data,
+    ndx: i32,
+    dst: *mut GElf_Syminfo,
+) -> *mut GElf_Syminfo {
+    guard(ptr::null_mut(), || {
+        if dst.is_null() {
+            set_error(ELF_E_INVALID_OPERAND);
+            return ptr::null_mut();
+        }
+        let (rec, _, encoding) = match record_at(data, ndx, 4, 4) {
+            Ok(r) => r,
+            Err(e) => {
+                set_error(e);
+                return ptr::null_mut();
+            }
+        };
+
+        unsafe {
+            (*dst)
+                .si_boundto
+                .set(NativeEndian, read_u16(rec, 0, encoding).unwrap_or_default());
+            (*dst)
+                .si_flags
+                .set(NativeEndian, read_u16(rec, 2, encoding).unwrap_or_default());
+        }
+        dst
+    })
+}
+
+#[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn gelf_update_syminfo(
+    data: *mut Elf_Data,
+    ndx: i32,
+    src: *mut GElf_Syminfo,
+) -> i32 {
+    guard(0, || {
+        if src.is_null() {
+            set_error(ELF_E_INVALID_OPERAND);
+            return 0;
+        }
+        let src = unsafe { &*src };
+        let result = record_at_mut(data, ndx, 4, 4).map(|(rec, _, encoding)| {
+            write_u16(rec, src.si_boundto.get(NativeEndian), encoding);
+            write_u16(&mut rec[2..], src.si_flags.get(NativeEndian), encoding);
+        });
+        update_result(data, result)
+    })
+}
+
+// Elf32_Move keeps a 64-bit m_value, so both classes are padded to a
+// multiple of 8 like the C structures.
+const MOVE32_SIZE: usize = 24;
+const MOVE64_SIZE: usize = 32;
+
+#[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn gelf_getmove(
+    data: *mut Elf_Data,
+    ndx: i32,
+    dst: *mut GElf_Move,
+) -> *mut GElf_Move {
+    guard(ptr::null_mut(), || {
+        if dst.is_null() {
+            set_error(ELF_E_INVALID_OPERAND);
+            return ptr::null_mut();
+        }
+        let (rec, elf64, encoding) = match record_at(data, ndx, MOVE32_SIZE, MOVE64_SIZE) {
+            Ok(r) => r,
+            Err(e) => {
+                set_error(e);
+                return ptr::null_mut();
+            }
+        };
+
+        let word = if elf64 { 8 } else { 4 };
+        let half = 8 + 2 * word;
+        unsafe {
+            let dst = &mut *dst;
+            dst.m_value
+                .set(NativeEndian, read_u64(rec, 0, encoding).unwrap_or_default());
+            dst.m_info.set(
+                NativeEndian,
+                read_word(rec, 8, elf64, encoding).unwrap_or_default(),
+            );
+            dst.m_poffset.set(
+                NativeEndian,
+                read_word(rec, 8 + word, elf64, encoding).unwrap_or_default(),
+            );
+            dst.m_repeat.set(
+                NativeEndian,
+                read_u16(rec, half, encoding).unwrap_or_default(),
+            );
+            dst.m_stride.set(
+                NativeEndian,
+                read_u16(rec, half + 2, encoding).unwrap_or_default(),
+            );
+        }
+        dst
+    })
+}
+
+#[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn gelf_update_move(data: *mut Elf_Data, ndx: i32, src: *mut GElf_Move) -> i32 {
+    guard(0, || {
+        if src.is_null() {
+            set_error(ELF_E_INVALID_OPERAND);
+            return 0;
+        }
+        let src = unsafe { &*src };
+        let result = record_at_mut(data, ndx, MOVE32_SIZE, MOVE64_SIZE).and_then(
+            |(rec, elf64, encoding)| {
+                let word = if elf64 { 8 } else { 4 };
+                let half = 8 + 2 * word;
+                let mut tmp = [0u8; MOVE64_SIZE];
+                write_u64(&mut tmp, src.m_value.get(NativeEndian), encoding);
+                write_word(&mut tmp[8..], src.m_info.get(NativeEndian), elf64, encoding)?;
+                write_word(
+                    &mut tmp[8 + word..],
+                    src.m_poffset.get(NativeEndian),
+                    elf64,
+                    encoding,
+                )?;
+                write_u16(&mut tmp[half..], src.m_repeat.get(NativeEndian), encoding);
+                write_u16(
+                    &mut tmp[half + 2..],
+                    src.m_stride.get(NativeEndian),
+                    encoding,
+                );
+                rec[..half + 4].copy_from_slice(&tmp[..half + 4]);
+                Ok(())
+            },
+        );
+        update_result(data, result)
+    })
+}
+
+#[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn gelf_getlib(data: *mut Elf_Data, ndx: i32, dst: *mut GElf_Lib) -> *mut GElf_Lib {
+    guard(ptr::null_mut(), || {
+        if dst.is_null() {
+            set_error(ELF_E_INVALID_OPERAND);
+            return ptr::null_mut();
+        }
+        let (rec, _, encoding) = match record_at(data, ndx, 20, 20) {
+            Ok(r) => r,
+            Err(e) => {
+                set_error(e);
+                return ptr::null_mut();
+            }
+        };
+
+        let word = |pos| read_u32(rec, pos, encoding).unwrap_or_default();
+        unsafe {
+            let dst = &mut *dst;
+            dst.l_name.set(NativeEndian, word(0));
+            dst.l_time_stamp.set(NativeEndian, word(4));
+            dst.l_checksum.set(NativeEndian, word(8));
+            dst.l_version.set(NativeEndian, word(12));
+            dst.l_flags.set(NativeEndian, word(16));
+        }
+        dst
+    })
+}
+
+#[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn gelf_update_lib(data: *mut Elf_Data, ndx: i32, src: *mut GElf_Lib) -> i32 {
+    guard(0, || {
+        if src.is_null() {
+            set_error(ELF_E_INVALID_OPERAND);
+            return 0;
+        }
+        let src = unsafe { &*src };
+        let result = record_at_mut(data, ndx, 20, 20).map(|(rec, _, encoding)| {
+            let fields = [
+                src.l_name,
+                src.l_time_stamp,
+                src.l_checksum,
+                src.l_version,
+                src.l_flags,
+            ];
+            for (i, field) in fields.iter().enumerate() {
+                write_u32(&mut rec[i * 4..], field.get(NativeEndian), encoding);
+            }
+        });
+        update_result(data, result)
+    })
+}
+
+#[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn gelf_getrelr(
+    data: *mut Elf_Data,
+    ndx: i32,
+    dst: *mut GElf_Relr,
+) -> *mut GElf_Relr {
+    guard(ptr::null_mut(), || {
+        if dst.is_null() {
+            set_error(ELF_E_INVALID_OPERAND);
+            return ptr::null_mut();
+        }
+        let (rec, elf64, encoding) = match record_at(data, ndx, 4, 8) {
+            Ok(r) => r,
+            Err(e) => {
+                set_error(e);
+                return ptr::null_mut();
+            }
+        };
+
+        let entry = read_word(rec, 0, elf64, encoding).unwrap_or_default();
+        unsafe { (*dst).0.set(NativeEndian, entry) };
+        dst
+    })
+}
+
+#[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn gelf_update_relr(data: *mut Elf_Data, ndx: i32, src: *mut GElf_Relr) -> i32 {
+    guard(0, || {
+        if src.is_null() {
+            set_error(ELF_E_INVALID_OPERAND);
+            return 0;
+        }
+        let entry = unsafe { (*src).0.get(NativeEndian) };
+        let result = record_at_mut(data, ndx, 4, 8)
+            .and_then(|(rec, elf64, encoding)| write_word(rec, entry, elf64, encoding));
+        update_result(data, result)
+    })
+}
+
+/// Expands the RELR table in `data` into the addresses it relocates. Up to
+/// `n` addresses are stored in `dst`, which may be NULL when `n` is 0; the
+/// return value is the total number, so callers can size `dst` first.
+#[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn gelf_relr_addrs(data: *mut Elf_Data, dst: *mut u64, n: usize) -> usize {
+    guard(0, || {
+        if data.is_null() || (dst.is_null() && n > 0) {
+            set_error(ELF_E_INVALID_OPERAND);
+            return 0;
+        }
+        let (elf64, encoding) = unsafe { (*(data as *mut Elf_Data_Scn)).layout() };
+        let buf = match unsafe { &*data }.bytes() {
+            Ok(buf) => buf,
+            Err(e) => {
+                set_error(e);
+                return 0;
+            }
+        };
+
+        let mut count = 0;
+        for addr in RelrIter::new(buf, elf64, encoding) {
+            if count < n {
+                unsafe { *dst.add(count) = addr };
+            }
+            count += 1;
+        }
+        count
+    })
+}
@@ -2,3 +2,4 @@ mod elf_api;
 mod gelf_api;
 
 pub use elf_api::*;
+pub use gelf_api::*;
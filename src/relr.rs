@@ -0,0 +1,72 @@
+//! Decoding of `SHT_RELR`/`DT_RELR` relative relocation tables.
+//!
+//! An entry with the lowest bit clear is an address to relocate. An entry
+//! with the lowest bit set is a bitmap: bit `n` (for `n >= 1`) marks the
+//! word `n - 1` words past the last address. Each bitmap advances the base
+//! by 31 or 63 words, so bitmaps can be chained.
+
+use crate::bytes::read_word;
+use object::Endianness;
+
+/// Iterates the relocated addresses of a RELR table.
+pub struct RelrIter<'a> {
+    buf: &'a [u8],
+    pos: usize,
+    elf64: bool,
+    encoding: Endianness,
+    base: u64,
+    bitmap: u64,
+    bit: u32,
+}
+
+impl<'a> RelrIter<'a> {
+    pub fn new(buf: &'a [u8], elf64: bool, encoding: Endianness) -> Self {
+        Self {
+            buf,
+            pos: 0,
+            elf64,
+            encoding,
+            base: 0,
+            bitmap: 0,
+            bit: 0,
+        }
+    }
+
+    fn word_size(&self) -> u64 {
+        if self.elf64 { 8 } else { 4 }
+    }
+
+    fn bits(&self) -> u32 {
+        self.word_size() as u32 * 8
+    }
+}
+
+impl Iterator for RelrIter<'_> {
+    type Item = u64;
+
+    fn next(&mut self) -> Option<u64> {
+        let word = self.word_size();
+        loop {
+            while self.bit != 0 && self.bit < self.bits() {
+                let bit = self.bit;
+                self.bit += 1;
+                if self.bitmap >> bit & 1 != 0 {
+                    return Some(self.base.wrapping_add(u64::from(bit - 1) * word));
+                }
+            }
+            if self.bit != 0 {
+                self.base = self.base.wrapping_add(u64::from(self.bits() - 1) * word);
+                self.bit = 0;
+            }
+
+            let entry = read_word(self.buf, self.pos, self.elf64, self.encoding)?;
+            self.pos += word as usize;
+            if entry & 1 == 0 {
+                self.base = entry.wrapping_add(word);
+                return Some(entry);
+            }
+            self.bitmap = entry;
+            self.bit = 1;
+        }
+    }
+}
@@ -1,16 +1,26 @@
+use crate::error::*;
 use std::ffi::c_void;
 
 pub use object::NativeEndian;
 pub use object::elf::{
-    ELFCLASS32, ELFCLASS64, ELFCLASSNONE, ELFDATA2LSB, ELFDATA2MSB, ELFDATANONE, ET_DYN, ET_EXEC,
-    ET_REL, EV_CURRENT, EV_NONE, PF_R, PF_W, PF_X, PT_DYNAMIC, PT_INTERP, PT_LOAD, PT_NOTE,
-    PT_NULL, PT_PHDR, SHF_ALLOC, SHF_EXECINSTR, SHF_WRITE, SHN_ABS, SHN_COMMON, SHN_UNDEF,
-    SHT_DYNAMIC, SHT_DYNSYM, SHT_GNU_VERDEF, SHT_GNU_VERNEED, SHT_GNU_VERSYM, SHT_HASH, SHT_NOBITS,
-    SHT_NOTE, SHT_NULL, SHT_PROGBITS, SHT_REL, SHT_RELA, SHT_SHLIB, SHT_STRTAB, SHT_SYMTAB,
-    STB_GLOBAL, STB_LOCAL, STB_WEAK, STT_FILE, STT_FUNC, STT_NOTYPE, STT_OBJECT, STT_SECTION,
-    VER_DEF_CURRENT, VER_NEED_CURRENT,
+    DT_GNU_HASH, DT_HASH, DT_NULL, DT_STRSZ, DT_STRTAB, DT_SYMENT, DT_SYMTAB, DT_VERDEF,
+    DT_VERDEFNUM, DT_VERNEED, DT_VERNEEDNUM, DT_VERSYM, ELFCLASS32, ELFCLASS64, ELFCLASSNONE,
+    ELFDATA2LSB, ELFDATA2MSB, ELFDATANONE, ET_CORE, ET_DYN, ET_EXEC, ET_REL, EV_CURRENT, EV_NONE,
+    NT_AUXV, NT_FILE, NT_PRPSINFO, NT_PRSTATUS, NT_SIGINFO, PF_R, PF_W, PF_X, PT_DYNAMIC,
+    PT_INTERP, PT_LOAD, PT_NOTE, PT_NULL, PT_PHDR, SHF_ALLOC, SHF_COMPRESSED, SHF_EXECINSTR,
+    SHF_WRITE, SHN_ABS, SHN_COMMON, SHN_UNDEF, SHT_DYNAMIC, SHT_DYNSYM, SHT_GNU_VERDEF,
+    SHT_GNU_VERNEED, SHT_GNU_VERSYM, SHT_HASH, SHT_NOBITS, SHT_NOTE, SHT_NULL, SHT_PROGBITS,
+    SHT_REL, SHT_RELA, SHT_RELR, SHT_SHLIB, SHT_STRTAB, SHT_SYMTAB, STB_GLOBAL, STB_LOCAL,
+    STB_WEAK, STT_FILE, STT_FUNC, STT_NOTYPE, STT_OBJECT, STT_SECTION, VER_DEF_CURRENT,
+    VER_NEED_CURRENT,
 };
 
+pub const EI_NIDENT: usize = 16;
+
+pub const DT_RELRSZ: u32 = 35;
+pub const DT_RELR: u32 = 36;
+pub const DT_RELRENT: u32 = 37;
+
 #[repr(C)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ElfKind {
@@ -48,7 +58,15 @@ pub const ELF_C_NULL: ElfCmd = ElfCmd::ELF_C_NULL;
 pub const ELF_C_READ: ElfCmd = ElfCmd::ELF_C_READ;
 pub const ELF_C_RDWR: ElfCmd = ElfCmd::ELF_C_RDWR;
 pub const ELF_C_WRITE: ElfCmd = ElfCmd::ELF_C_WRITE;
+pub const ELF_C_CLR: ElfCmd = ElfCmd::ELF_C_CLR;
+pub const ELF_C_SET: ElfCmd = ElfCmd::ELF_C_SET;
+pub const ELF_C_FDDONE: ElfCmd = ElfCmd::ELF_C_FDDONE;
+pub const ELF_C_FDREAD: ElfCmd = ElfCmd::ELF_C_FDREAD;
 pub const ELF_C_READ_MMAP: ElfCmd = ElfCmd::ELF_C_READ_MMAP;
+pub const ELF_C_RDWR_MMAP: ElfCmd = ElfCmd::ELF_C_RDWR_MMAP;
+pub const ELF_C_WRITE_MMAP: ElfCmd = ElfCmd::ELF_C_WRITE_MMAP;
+pub const ELF_C_READ_MMAP_PRIVATE: ElfCmd = ElfCmd::ELF_C_READ_MMAP_PRIVATE;
+pub const ELF_C_EMPTY: ElfCmd = ElfCmd::ELF_C_EMPTY;
 
 #[repr(C)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -80,11 +98,38 @@ pub enum ElfType {
     ELF_T_AUXV = 24,
     ELF_T_CHDR = 25,
     ELF_T_NHDR8 = 26,
-    ELF_T_NUM = 27,
+    ELF_T_RELR = 27,
+    ELF_T_NUM = 28,
 }
 
 pub const ELF_T_BYTE: ElfType = ElfType::ELF_T_BYTE;
+pub const ELF_T_ADDR: ElfType = ElfType::ELF_T_ADDR;
+pub const ELF_T_DYN: ElfType = ElfType::ELF_T_DYN;
+pub const ELF_T_EHDR: ElfType = ElfType::ELF_T_EHDR;
+pub const ELF_T_HALF: ElfType = ElfType::ELF_T_HALF;
+pub const ELF_T_OFF: ElfType = ElfType::ELF_T_OFF;
+pub const ELF_T_PHDR: ElfType = ElfType::ELF_T_PHDR;
+pub const ELF_T_RELA: ElfType = ElfType::ELF_T_RELA;
+pub const ELF_T_REL: ElfType = ElfType::ELF_T_REL;
+pub const ELF_T_SHDR: ElfType = ElfType::ELF_T_SHDR;
+pub const ELF_T_SWORD: ElfType = ElfType::ELF_T_SWORD;
 pub const ELF_T_SYM: ElfType = ElfType::ELF_T_SYM;
+pub const ELF_T_WORD: ElfType = ElfType::ELF_T_WORD;
+pub const ELF_T_XWORD: ElfType = ElfType::ELF_T_XWORD;
+pub const ELF_T_SXWORD: ElfType = ElfType::ELF_T_SXWORD;
+pub const ELF_T_VDEF: ElfType = ElfType::ELF_T_VDEF;
+pub const ELF_T_VDAUX: ElfType = ElfType::ELF_T_VDAUX;
+pub const ELF_T_VNEED: ElfType = ElfType::ELF_T_VNEED;
+pub const ELF_T_VNAUX: ElfType = ElfType::ELF_T_VNAUX;
+pub const ELF_T_NHDR: ElfType = ElfType::ELF_T_NHDR;
+pub const ELF_T_SYMINFO: ElfType = ElfType::ELF_T_SYMINFO;
+pub const ELF_T_MOVE: ElfType = ElfType::ELF_T_MOVE;
+pub const ELF_T_LIB: ElfType = ElfType::ELF_T_LIB;
+pub const ELF_T_GNUHASH: ElfType = ElfType::ELF_T_GNUHASH;
+pub const ELF_T_AUXV: ElfType = ElfType::ELF_T_AUXV;
+pub const ELF_T_CHDR: ElfType = ElfType::ELF_T_CHDR;
+pub const ELF_T_NHDR8: ElfType = ElfType::ELF_T_NHDR8;
+pub const ELF_T_RELR: ElfType = ElfType::ELF_T_RELR;
 
 pub const ELF_F_DIRTY: u32 = 0x1;
 pub const ELF_F_LAYOUT: u32 = 0x4;
@@ -106,7 +151,7 @@ impl Default for Elf_Data {
         Self {
             d_buf: std::ptr::null_mut(),
             d_type: ELF_T_BYTE,
-            d_version: EV_CURRENT.into(),
+            d_version: crate::read::working_version(),
             d_size: 0,
             d_off: 0,
             d_align: 1,
@@ -114,6 +159,30 @@ impl Default for Elf_Data {
     }
 }
 
+impl Elf_Data {
+    /// The buffer the descriptor points to, empty when it has none (as for
+    /// `SHT_NOBITS`). A size no allocation can have is `ELF_E_INVALID_DATA`.
+    pub(crate) fn bytes(&self) -> Result<&[u8], ElfError> {
+        if self.d_buf.is_null() {
+            return Ok(&[]);
+        }
+        if self.d_size > isize::MAX as usize {
+            return Err(ELF_E_INVALID_DATA);
+        }
+        Ok(unsafe { std::slice::from_raw_parts(self.d_buf as *const u8, self.d_size) })
+    }
+
+    pub(crate) fn bytes_mut(&mut self) -> Result<&mut [u8], ElfError> {
+        if self.d_buf.is_null() {
+            return Ok(&mut []);
+        }
+        if self.d_size > isize::MAX as usize {
+            return Err(ELF_E_INVALID_DATA);
+        }
+        Ok(unsafe { std::slice::from_raw_parts_mut(self.d_buf as *mut u8, self.d_size) })
+    }
+}
+
 pub type GElf_Ehdr = object::elf::FileHeader64<NativeEndian>;
 pub type GElf_Shdr = object::elf::SectionHeader64<NativeEndian>;
 pub type GElf_Phdr = object::elf::ProgramHeader64<NativeEndian>;
@@ -124,6 +193,66 @@ pub type GElf_Nhdr = object::elf::NoteHeader64<NativeEndian>;
 pub type GElf_Verdef = object::elf::Verdef<NativeEndian>;
 pub type GElf_Verdaux = object::elf::Verdaux<NativeEndian>;
 pub type GElf_Versym = object::elf::Versym<NativeEndian>;
+pub type GElf_Relr = object::elf::Relr64<NativeEndian>;
+pub type GElf_Dyn = object::elf::Dyn64<NativeEndian>;
+pub type GElf_auxv_t = Auxv64<NativeEndian>;
+pub type GElf_Syminfo = Syminfo<NativeEndian>;
+pub type GElf_Move = Move64<NativeEndian>;
+pub type GElf_Lib = Lib<NativeEndian>;
+
+/// Auxiliary vector entry. `a_val` is the `a_un.a_val` member of the C union.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Auxv64<E: object::Endian> {
+    pub a_type: object::U64<E>,
+    pub a_val: object::U64<E>,
+}
+
+/// `.SUNW_syminfo` entry, the same in both classes.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Syminfo<E: object::Endian> {
+    pub si_boundto: object::U16<E>,
+    pub si_flags: object::U16<E>,
+}
+
+/// `.SUNW_move` entry.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Move64<E: object::Endian> {
+    pub m_value: object::U64<E>,
+    pub m_info: object::U64<E>,
+    pub m_poffset: object::U64<E>,
+    pub m_repeat: object::U16<E>,
+    pub m_stride: object::U16<E>,
+}
+
+/// `.liblist` entry, the same in both classes.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Lib<E: object::Endian> {
+    pub l_name: object::U32<E>,
+    pub l_time_stamp: object::U32<E>,
+    pub l_checksum: object::U32<E>,
+    pub l_version: object::U32<E>,
+    pub l_flags: object::U32<E>,
+}
+
+pub const AT_NULL: u64 = 0;
+pub const AT_IGNORE: u64 = 1;
+pub const AT_EXECFD: u64 = 2;
+pub const AT_PHDR: u64 = 3;
+pub const AT_PHENT: u64 = 4;
+pub const AT_PHNUM: u64 = 5;
+pub const AT_PAGESZ: u64 = 6;
+pub const AT_BASE: u64 = 7;
+pub const AT_FLAGS: u64 = 8;
+pub const AT_ENTRY: u64 = 9;
+pub const AT_UID: u64 = 11;
+pub const AT_HWCAP: u64 = 16;
+pub const AT_RANDOM: u64 = 25;
+pub const AT_EXECFN: u64 = 31;
+pub const AT_SYSINFO_EHDR: u64 = 33;
 
 #[inline]
 pub fn gelf_st_bind(info: u8) -> u8 {
@@ -0,0 +1,152 @@
+use object::write::WritableBuffer;
+use std::fs::File;
+use std::io;
+use std::os::unix::fs::FileExt;
+
+/// Bytes gathered before a write to the descriptor.
+pub const STAGE_SIZE: usize = 64 * 1024;
+
+/// Where `elf_update` sends the file it lays out.
+pub enum Target<'a> {
+    /// Positional writes to the descriptor, like libelf's `pwrite`s.
+    File(&'a File),
+    /// A shared mapping of the descriptor, made once the size is known.
+    Map(&'a File, Option<memmap2::MmapMut>),
+}
+
+/// Output buffer that passes everything on to a `Target` as it comes, so
+/// the file is never held in memory as a whole. Gaps are padded with a
+/// caller-chosen byte, so they are deterministic and configurable.
+///
+/// `WritableBuffer` can't fail: the first error is kept, later writes are
+/// dropped, and `finish` reports it.
+pub struct StreamBuffer<'a> {
+    target: Target<'a>,
+    fill: u8,
+    len: usize,
+    stage: Vec<u8>,
+    error: Option<io::Error>,
+}
+
+impl<'a> StreamBuffer<'a> {
+    pub fn new(target: Target<'a>, fill: u8) -> Self {
+        Self {
+            target,
+            fill,
+            len: 0,
+            stage: Vec::new(),
+            error: None,
+        }
+    }
+
+    fn fail(&mut self, error: io::Error) {
+        self.error.get_or_insert(error);
+    }
+
+    fn flush_stage(&mut self) {
+        if let Target::File(file) = self.target {
+            let offset = (self.len - self.stage.len()) as u64;
+            if let Err(e) = file.write_all_at(&self.stage, offset) {
+                self.fail(e);
+            }
+        }
+        self.stage.clear();
+    }
+
+    /// Writes out what is still staged and returns the length written.
+    pub fn finish(mut self) -> io::Result<usize> {
+        self.flush_stage();
+        if let (None, Target::Map(_, Some(map))) = (&self.error, &self.target)
+            && let Err(e) = map.flush()
+        {
+            self.fail(e);
+        }
+        match self.error {
+            Some(e) => Err(e),
+            None => Ok(self.len),
+        }
+    }
+}
+
+impl WritableBuffer for StreamBuffer<'_> {
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Called once with the size of the whole file, before anything is
+    /// written: regular files are cut or grown to it, and mapped.
+    fn reserve(&mut self, size: usize) -> Result<(), ()> {
+        let file = match self.target {
+            Target::File(file) | Target::Map(file, _) => file,
+        };
+        let regular = file.metadata().map(|m| m.is_file());
+        if let Err(e) = regular.and_then(|regular| match regular {
+            true => file.set_len(size as u64),
+            false => Ok(()),
+        }) {
+            self.fail(e);
+            return Err(());
+        }
+
+        match &mut self.target {
+            Target::File(_) => self.stage.try_reserve(STAGE_SIZE).map_err(|_| ()),
+            Target::Map(file, map) => match unsafe { memmap2::MmapMut::map_mut(*file) } {
+                Ok(m) if m.len() >= size => {
+                    *map = Some(m);
+                    Ok(())
+                }
+                Ok(_) => {
+                    self.fail(io::ErrorKind::UnexpectedEof.into());
+                    Err(())
+                }
+                Err(e) => {
+                    self.fail(e);
+                    Err(())
+                }
+            },
+        }
+    }
+
+    fn resize(&mut self, new_len: usize) {
+        let pad = [self.fill; 256];
+        while self.len < new_len {
+            let n = (new_len - self.len).min(pad.len());
+            self.write_bytes(&pad[..n]);
+        }
+    }
+
+    fn write_bytes(&mut self, val: &[u8]) {
+        if self.error.is_some() {
+            self.len += val.len();
+            return;
+        }
+        match &mut self.target {
+            Target::Map(_, Some(map)) => {
+                match map.get_mut(self.len..self.len + val.len()) {
+                    Some(dst) => dst.copy_from_slice(val),
+                    None => self.fail(io::ErrorKind::UnexpectedEof.into()),
+                }
+                self.len += val.len();
+            }
+            Target::Map(_, None) => {
+                self.fail(io::ErrorKind::NotConnected.into());
+                self.len += val.len();
+            }
+            Target::File(file) => {
+                let file: &File = file;
+                if self.stage.len() + val.len() > STAGE_SIZE {
+                    self.flush_stage();
+                }
+                // Large writes skip the staging buffer
+                if val.len() >= STAGE_SIZE {
+                    if let Err(e) = file.write_all_at(val, self.len as u64) {
+                        self.fail(e);
+                    }
+                } else {
+                    self.stage.extend_from_slice(val);
+                }
+                self.len += val.len();
+            }
+        }
+    }
+}
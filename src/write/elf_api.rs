@@ -1,39 +1,58 @@
-use crate::error::set_error;
-use crate::handle::{Elf, Elf_Scn, WriteState};
+use super::buffer::{STAGE_SIZE, StreamBuffer, Target};
+use crate::error::*;
+use crate::handle::{Elf, Elf_Data_Scn, Elf_Scn, WriteEhdr, WriteSection, WriteState, locked};
+use crate::limits;
 use crate::types::*;
-use object::write::{Object as WriteObject, SectionKind};
-use object::{Architecture, BinaryFormat, Endianness};
-use std::io::Write;
+use crate::xlate;
+use object::Endianness;
+use object::NativeEndian;
+use object::elf::{SHN_LORESERVE, SHN_XINDEX};
+use object::endian::{U32, U64};
+use object::write::WritableBuffer;
+use object::write::elf::{FileHeader, Writer};
+use std::ffi::c_void;
+use std::mem::ManuallyDrop;
 use std::os::fd::FromRawFd;
+use std::os::unix::fs::FileExt;
 use std::ptr;
+use std::sync::atomic::{AtomicU8, AtomicU32, Ordering};
 
+type Elf32_Ehdr = object::elf::FileHeader32<Endianness>;
 type Elf64_Ehdr = object::elf::FileHeader64<Endianness>;
+type Elf32_Shdr = object::elf::SectionHeader32<Endianness>;
+type Elf64_Shdr = object::elf::SectionHeader64<Endianness>;
+
+static FILL_BYTE: AtomicU8 = AtomicU8::new(0);
 
 fn ensure_writer(elf: &mut Elf) -> bool {
     if elf.writer.is_some() {
         return true;
     }
 
-    let arch = Architecture::X86_64;
-    let endian = Endianness::Little;
-
-    let obj = WriteObject::new(BinaryFormat::Elf, arch, endian);
-
     elf.writer = Some(WriteState {
-        obj,
         sections: Vec::new(),
-        section_data: Vec::new(),
         shstrtab_idx: None,
-        ehdr64: None,
+        ehdr: None,
     });
 
     true
 }
 
+/// Sets the byte `elf_update` uses for every gap in the output, for all
+/// descriptors, as libelf does.
 #[unsafe(no_mangle)]
-pub extern "C" fn elf64_newehdr(elf: *mut Elf) -> *mut Elf64_Ehdr {
+pub extern "C" fn elf_fill(fill: i32) {
+    guard((), || {
+        FILL_BYTE.store(fill as u8, Ordering::Relaxed);
+    })
+}
+
+/// Creates the header of a new file, which fixes its class. Asking again
+/// returns the same header; asking for the other class fails with
+/// `ELF_E_INVALID_CLASS`.
+fn newehdr(elf: *mut Elf, class: u8) -> *mut c_void {
     if elf.is_null() {
-        set_error("invalid argument");
+        set_error(ELF_E_INVALID_OPERAND);
         return ptr::null_mut();
     }
 
@@ -45,146 +64,780 @@ pub extern "C" fn elf64_newehdr(elf: *mut Elf) -> *mut Elf64_Ehdr {
 
     let writer = elf_ref.writer.as_mut().unwrap();
 
-    if writer.ehdr64.is_none() {
-        writer.ehdr64 = Some(Box::new(unsafe { std::mem::zeroed() }));
+    if writer.ehdr.is_none() {
+        writer.ehdr = Some(match class {
+            ELFCLASS32 => WriteEhdr::Elf32(Box::new(unsafe { std::mem::zeroed() })),
+            ELFCLASS64 => WriteEhdr::Elf64(Box::new(unsafe { std::mem::zeroed() })),
+            _ => {
+                set_error(ELF_E_INVALID_CLASS);
+                return ptr::null_mut();
+            }
+        });
+        elf_ref.ehdr_flags.fetch_or(ELF_F_DIRTY, Ordering::Relaxed);
+    }
+
+    let ehdr = writer.ehdr.as_mut().unwrap();
+    if ehdr.class() != class {
+        set_error(ELF_E_INVALID_CLASS);
+        return ptr::null_mut();
     }
+    ehdr.as_ptr()
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn elf32_newehdr(elf: *mut Elf) -> *mut Elf32_Ehdr {
+    guard(ptr::null_mut(), || {
+        newehdr(elf, ELFCLASS32) as *mut Elf32_Ehdr
+    })
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn elf64_newehdr(elf: *mut Elf) -> *mut Elf64_Ehdr {
+    guard(ptr::null_mut(), || {
+        newehdr(elf, ELFCLASS64) as *mut Elf64_Ehdr
+    })
+}
 
-    writer.ehdr64.as_mut().unwrap().as_mut() as *mut Elf64_Ehdr
+/// `elf32_newehdr` or `elf64_newehdr`, as `class` says.
+#[unsafe(no_mangle)]
+pub extern "C" fn gelf_newehdr(elf: *mut Elf, class: i32) -> *mut c_void {
+    guard(ptr::null_mut(), || match u8::try_from(class) {
+        Ok(class) => newehdr(elf, class),
+        Err(_) => {
+            set_error(ELF_E_INVALID_CLASS);
+            ptr::null_mut()
+        }
+    })
 }
 
 #[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
 pub extern "C" fn elf_newscn(elf: *mut Elf) -> *mut Elf_Scn {
-    if elf.is_null() {
-        set_error("invalid argument");
-        return ptr::null_mut();
-    }
+    guard(ptr::null_mut(), || {
+        if elf.is_null() {
+            set_error(ELF_E_INVALID_OPERAND);
+            return ptr::null_mut();
+        }
 
-    let elf_ref = unsafe { &mut *elf };
+        let elf_ref = unsafe { &mut *elf };
 
-    if !ensure_writer(elf_ref) {
-        return ptr::null_mut();
-    }
+        if !ensure_writer(elf_ref) {
+            return ptr::null_mut();
+        }
 
-    let writer = elf_ref.writer.as_mut().unwrap();
-    let section_idx = writer.sections.len();
+        let writer = elf_ref.writer.as_mut().unwrap();
+        // Section 0 is the null section, as in the file
+        let section_idx = writer.sections.len() + 1;
+        if let Err(e) = limits::check_section_count(section_idx + 1) {
+            set_error(e);
+            return ptr::null_mut();
+        }
 
-    let section_id = writer
-        .obj
-        .add_section(Vec::new(), Vec::new(), SectionKind::Data);
-    writer.sections.push(section_id);
-    writer.section_data.push(Vec::new());
+        writer.sections.push(WriteSection::default());
 
-    let scn = Box::new(Elf_Scn::new(elf, section_idx));
-    let scn_ptr = Box::into_raw(scn);
-    elf_ref.section_handles.push(scn_ptr);
-    scn_ptr
+        let mut scn = Box::new(Elf_Scn::new(elf, section_idx));
+        scn.flags = AtomicU32::new(ELF_F_DIRTY);
+        scn.shdr_flags = AtomicU32::new(ELF_F_DIRTY);
+        let scn_ptr = Box::into_raw(scn);
+        locked(&elf_ref.section_handles).push(scn_ptr);
+        scn_ptr
+    })
 }
 
 #[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
 pub extern "C" fn elf_newdata(scn: *mut Elf_Scn) -> *mut Elf_Data {
-    if scn.is_null() {
-        set_error("invalid argument");
-        return ptr::null_mut();
-    }
+    guard(ptr::null_mut(), || {
+        if scn.is_null() {
+            set_error(ELF_E_INVALID_OPERAND);
+            return ptr::null_mut();
+        }
 
-    let scn_ref = unsafe { &mut *scn };
-    let elf = unsafe { &mut *scn_ref.elf };
+        let scn_ref = unsafe { &mut *scn };
+        let elf = unsafe { &mut *scn_ref.elf };
+
+        // New data goes after the section's existing contents
+        if let Err(e) = elf.load_scn_data(scn) {
+            set_error(e);
+            return ptr::null_mut();
+        }
 
-    let data = Box::new(Elf_Data::default());
-    let data_ptr = Box::into_raw(data);
+        let data_ptr = elf.alloc_data(Elf_Data::default(), scn);
+        let data_scn = data_ptr as *mut Elf_Data_Scn;
+        unsafe { (*data_scn).flags.store(ELF_F_DIRTY, Ordering::Relaxed) };
 
-    scn_ref.data_list_head = data_ptr;
-    elf.data_handles.push(data_ptr);
+        match scn_ref.data_chain_mut().last() {
+            Some(tail) => tail.next = data_scn,
+            None => scn_ref.data_list_head = data_scn,
+        }
+        scn_ref.flags.fetch_or(ELF_F_DIRTY, Ordering::Relaxed);
 
-    data_ptr
+        data_ptr
+    })
 }
 
 #[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
 pub extern "C" fn elf_setshstrndx(elf: *mut Elf, idx: usize) -> i32 {
-    if elf.is_null() {
-        return -1;
+    guard(-1, || {
+        if elf.is_null() {
+            return -1;
+        }
+
+        let elf_ref = unsafe { &mut *elf };
+
+        if !ensure_writer(elf_ref) {
+            return -1;
+        }
+
+        if let Some(writer) = elf_ref.writer.as_mut() {
+            writer.shstrtab_idx = Some(idx);
+        }
+
+        0
+    })
+}
+
+/// Where `place_section` put a section's buffers, and the size and
+/// alignment that gives the section.
+struct Placement {
+    offsets: Vec<usize>,
+    size: usize,
+    align: usize,
+}
+
+/// Places the data chain of `scn` without looking at its contents. Unless
+/// the caller owns the layout (`ELF_F_LAYOUT`), each buffer is placed at
+/// its alignment, for `record_offsets` to store in its `d_off`; otherwise
+/// `d_off` is taken as given.
+fn place_section(scn: &Elf_Scn, layout: bool, permissive: bool) -> Result<Placement, ElfError> {
+    let mut offsets = Vec::new();
+    let mut size = 0usize;
+    let mut section_align = 1usize;
+
+    for data in scn.data_chain() {
+        let d = &data.d;
+        let align = if d.d_align.is_power_of_two() {
+            d.d_align
+        } else if permissive {
+            1
+        } else {
+            return Err(ELF_E_INVALID_ALIGN);
+        };
+        section_align = section_align.max(align);
+
+        let offset = if layout {
+            if !permissive && (d.d_off < 0 || (d.d_off as usize) < size) {
+                return Err(ELF_E_INVALID_DATA);
+            }
+            d.d_off.max(0) as usize
+        } else {
+            size.checked_next_multiple_of(align).ok_or(ELF_E_NOMEM)?
+        };
+
+        // Sizes come from the caller: refuse what can't be addressed rather
+        // than aborting
+        let end = offset
+            .checked_add(d.d_size)
+            .filter(|&end| end <= isize::MAX as usize)
+            .ok_or(ELF_E_NOMEM)?;
+        limits::check_data_size(end)?;
+        size = size.max(end);
+        offsets.push(offset);
     }
 
-    let elf_ref = unsafe { &mut *elf };
+    Ok(Placement {
+        offsets,
+        size,
+        align: section_align,
+    })
+}
 
-    if !ensure_writer(elf_ref) {
-        return -1;
+/// Stores the offsets `place_section` chose in the `d_off` of each buffer,
+/// as libelf does.
+fn record_offsets(scn: &mut Elf_Scn, placement: &Placement) {
+    for (data, &offset) in scn.data_chain_mut().zip(&placement.offsets) {
+        data.d.d_off = offset as i64;
     }
+}
 
-    if let Some(writer) = elf_ref.writer.as_mut() {
-        writer.shstrtab_idx = Some(idx);
+/// Concatenates the data chain of `scn` into its file representation for
+/// the class and byte order in `file`, placed as `place_section` does.
+fn assemble_section(
+    scn: &Elf_Scn,
+    (elf64, encoding): (bool, Endianness),
+    layout: bool,
+    permissive: bool,
+    fill: u8,
+) -> Result<(Vec<u8>, Placement), ElfError> {
+    let placement = place_section(scn, layout, permissive)?;
+    let mut bytes = Vec::new();
+    bytes
+        .try_reserve_exact(placement.size)
+        .map_err(|_| ELF_E_NOMEM)?;
+    bytes.resize(placement.size, fill);
+
+    for (data, &offset) in scn.data_chain().zip(&placement.offsets) {
+        let d = &data.d;
+        let dst = &mut bytes[offset..offset + d.d_size];
+        let buf = d.bytes()?;
+        if buf.is_empty() {
+            dst.fill(0);
+        } else {
+            dst.copy_from_slice(buf);
+            xlate::xlatetof(dst, d.d_type, elf64, encoding);
+        }
     }
 
-    0
+    Ok((bytes, placement))
 }
 
-#[unsafe(no_mangle)]
-pub extern "C" fn elf_update(elf: *mut Elf, cmd: ElfCmd) -> i64 {
-    if elf.is_null() {
-        set_error("invalid argument");
-        return -1;
+/// Writes `buf` in the file representation of `file`. Arrays of records
+/// are translated through `scratch` a stage at a time; the chained types
+/// (notes, version definitions and needs, GNU hash tables) as a whole.
+fn write_translated(
+    writer: &mut Writer,
+    buf: &[u8],
+    ty: ElfType,
+    (elf64, encoding): (bool, Endianness),
+    scratch: &mut Vec<u8>,
+) {
+    if ty == ELF_T_BYTE || encoding == Endianness::default() {
+        writer.write(buf);
+        return;
     }
 
-    let elf_ref = unsafe { &mut *elf };
+    let step = match xlate::record_size(ty, elf64) {
+        Some(size) => (STAGE_SIZE / size).max(1) * size,
+        None => buf.len(),
+    };
+    for chunk in buf.chunks(step.max(1)) {
+        scratch.clear();
+        scratch.extend_from_slice(chunk);
+        xlate::xlatetof(scratch, ty, elf64, encoding);
+        writer.write(scratch);
+    }
+}
 
-    let writer = match elf_ref.writer.as_mut() {
-        Some(w) => w,
-        None => {
-            set_error("no write state");
-            return -1;
-        }
+/// Writes a section laid out as `section` straight from the data chain of
+/// `scn`. Buffers that overlap or go back, which only permissive layouts
+/// allow, can't be streamed: such a section is assembled first.
+fn stream_section(
+    writer: &mut Writer,
+    scn: Option<&Elf_Scn>,
+    section: &WriteSection,
+    file: (bool, Endianness),
+    fill: u8,
+) -> Result<(), ElfError> {
+    const ZEROS: [u8; 4096] = [0; 4096];
+    let start = writer.len();
+    let end = start + section.size;
+    let Some(scn) = scn else {
+        writer.pad_until(end);
+        return Ok(());
     };
 
-    for scn_ptr in elf_ref.section_handles.iter() {
-        let scn = unsafe { &**scn_ptr };
-        if !scn.data_list_head.is_null() {
-            let data = unsafe { &*scn.data_list_head };
-            if !data.d_buf.is_null() && data.d_size > 0 {
-                let buf =
-                    unsafe { std::slice::from_raw_parts(data.d_buf as *const u8, data.d_size) };
-                if scn.index < writer.sections.len() {
-                    writer.obj.set_section_data(
-                        writer.sections[scn.index],
-                        buf.to_vec(),
-                        data.d_align as u64,
-                    );
-                }
+    let mut cursor = 0usize;
+    let in_order = scn.data_chain().all(|data| {
+        let d = &data.d;
+        let offset = d.d_off as usize;
+        let fits = d.d_off >= 0
+            && offset >= cursor
+            && offset
+                .checked_add(d.d_size)
+                .is_some_and(|end| end <= section.size);
+        cursor = offset.wrapping_add(d.d_size);
+        fits
+    });
+    if !in_order {
+        let (bytes, _) = assemble_section(scn, file, true, true, fill)?;
+        writer.write(&bytes[..bytes.len().min(section.size)]);
+        writer.pad_until(end);
+        return Ok(());
+    }
+
+    let mut scratch = Vec::new();
+    for data in scn.data_chain() {
+        let d = &data.d;
+        writer.pad_until(start + d.d_off as usize);
+        let buf = d.bytes()?;
+        if buf.is_empty() {
+            let mut left = d.d_size;
+            while left > 0 {
+                let n = left.min(ZEROS.len());
+                writer.write(&ZEROS[..n]);
+                left -= n;
             }
+        } else {
+            write_translated(writer, buf, d.d_type, file, &mut scratch);
         }
     }
+    writer.pad_until(end);
+    Ok(())
+}
 
+/// Fills in the identification bytes `elf_update` owns, with the byte order
+/// defaulting to the host's, and returns the class and byte order of the
+/// output.
+fn complete_ident(ehdr: &mut WriteEhdr) -> Result<(bool, Endianness), ElfError> {
+    let class = ehdr.class();
+    let ident = ehdr.e_ident();
+    ident.magic = object::elf::ELFMAG;
+    ident.class = class;
+    ident.version = EV_CURRENT;
+    if ident.data == ELFDATANONE {
+        ident.data = match Endianness::default() {
+            Endianness::Little => ELFDATA2LSB,
+            Endianness::Big => ELFDATA2MSB,
+        };
+    }
+    let encoding = ehdr.encoding().ok_or(ELF_E_DATA_ENCODING)?;
+    Ok((ehdr.elf64(), encoding))
+}
+
+/// Where `emit` put the parts of a file the header points at, and the
+/// offset of each new section.
+struct FileLayout {
+    len: usize,
+    shoff: usize,
+    shnum: usize,
+    shstrndx: usize,
+    offsets: Vec<usize>,
+}
+
+/// Writes the header of a new section: its place from the layout, the rest
+/// from `shdr`.
+fn write_section_header(
+    writer: &mut Writer,
+    shdr: &GElf_Shdr,
+    section: &WriteSection,
+    offset: usize,
+    (elf64, encoding): (bool, Endianness),
+) {
+    let e = encoding;
+    let n = NativeEndian;
+    if elf64 {
+        writer.write(object::bytes_of(&Elf64_Shdr {
+            sh_name: U32::new(e, shdr.sh_name.get(n)),
+            sh_type: U32::new(e, shdr.sh_type.get(n)),
+            sh_flags: U64::new(e, shdr.sh_flags.get(n)),
+            sh_addr: U64::new(e, shdr.sh_addr.get(n)),
+            sh_offset: U64::new(e, offset as u64),
+            sh_size: U64::new(e, section.size as u64),
+            sh_link: U32::new(e, shdr.sh_link.get(n)),
+            sh_info: U32::new(e, shdr.sh_info.get(n)),
+            sh_addralign: U64::new(e, section.align as u64),
+            sh_entsize: U64::new(e, shdr.sh_entsize.get(n)),
+        }));
+    } else {
+        // The stored header is an ELF32 one: its fields fit
+        writer.write(object::bytes_of(&Elf32_Shdr {
+            sh_name: U32::new(e, shdr.sh_name.get(n)),
+            sh_type: U32::new(e, shdr.sh_type.get(n)),
+            sh_flags: U32::new(e, shdr.sh_flags.get(n) as u32),
+            sh_addr: U32::new(e, shdr.sh_addr.get(n) as u32),
+            sh_offset: U32::new(e, offset as u32),
+            sh_size: U32::new(e, section.size as u32),
+            sh_link: U32::new(e, shdr.sh_link.get(n)),
+            sh_info: U32::new(e, shdr.sh_info.get(n)),
+            sh_addralign: U32::new(e, section.align as u32),
+            sh_entsize: U32::new(e, shdr.sh_entsize.get(n) as u32),
+        }));
+    }
+}
+
+/// Lays out a new file: the header, the sections in order, the section name
+/// table and the section header table. `shdrs` are the headers of the
+/// sections, by index less one. With `scns` (the section handles, indexed
+/// the same way) the file is then written to `buffer` front to back, in the
+/// class and byte order of `file`; without, only its layout is computed.
+///
+/// If the application named one of its sections with `elf_setshstrndx`,
+/// `e_shstrndx` points at it and `sh_name` is an offset into it; otherwise
+/// a `.shstrtab` is added after the sections.
+fn emit(
+    buffer: &mut dyn WritableBuffer,
+    state: &WriteState,
+    shdrs: &[GElf_Shdr],
+    scns: Option<&[Option<&Elf_Scn>]>,
+    ehdr: &GElf_Ehdr,
+    file: (bool, Endianness),
+    fill: u8,
+) -> Result<FileLayout, ElfError> {
+    let (elf64, encoding) = file;
+    let sections = &state.sections;
+    let mut writer = Writer::new(encoding, elf64, buffer);
+    let nobits = |index: usize| shdrs[index].sh_type.get(NativeEndian) == SHT_NOBITS;
+    let names = state
+        .shstrtab_idx
+        .filter(|idx| (1..=sections.len()).contains(idx));
+
+    writer.reserve_file_header();
+    writer.reserve_null_section_index();
+    for index in 1..=sections.len() {
+        if names == Some(index) {
+            // Only to point `e_shstrndx` at it: the writer's own table is
+            // never reserved, so nothing of it is written
+            writer.reserve_shstrtab_section_index_with_name(b"");
+        } else {
+            writer.reserve_section_index();
+        }
+    }
+    let shstrndx = match names {
+        Some(idx) => idx,
+        None => writer.reserve_shstrtab_section_index().0 as usize,
+    };
+    // `SHT_NOBITS` sections take no room in the file
+    let offsets: Vec<_> = sections
+        .iter()
+        .enumerate()
+        .map(|(index, s)| writer.reserve(if nobits(index) { 0 } else { s.size }, s.align))
+        .collect();
+    if names.is_none() {
+        writer.reserve_shstrtab();
+    }
+    writer.reserve_section_headers();
+
+    let shnum = sections.len() + 1 + usize::from(names.is_none());
+    let shentsize = if elf64 {
+        size_of::<Elf64_Shdr>()
+    } else {
+        size_of::<Elf32_Shdr>()
+    };
+    let len = writer.reserved_len();
+    let layout = FileLayout {
+        len,
+        shoff: len - shnum * shentsize,
+        shnum,
+        shstrndx,
+        offsets,
+    };
+    let Some(scns) = scns else {
+        return Ok(layout);
+    };
+
+    writer
+        .write_file_header(&FileHeader {
+            os_abi: ehdr.e_ident.os_abi,
+            abi_version: ehdr.e_ident.abi_version,
+            e_type: ehdr.e_type.get(NativeEndian),
+            e_machine: ehdr.e_machine.get(NativeEndian),
+            e_entry: ehdr.e_entry.get(NativeEndian),
+            e_flags: ehdr.e_flags.get(NativeEndian),
+        })
+        .map_err(|_| ELF_E_WRITE_ERROR)?;
+    for (index, section) in sections.iter().enumerate() {
+        writer.write_align(section.align);
+        if nobits(index) {
+            continue;
+        }
+        let scn = scns.get(index).copied().flatten();
+        stream_section(&mut writer, scn, section, file, fill)?;
+    }
+    writer.write_shstrtab();
+
+    writer.write_null_section_header();
+    for (index, section) in sections.iter().enumerate() {
+        write_section_header(
+            &mut writer,
+            &shdrs[index],
+            section,
+            layout.offsets[index],
+            file,
+        );
+    }
+    if names.is_none() {
+        writer.write_shstrtab_section_header();
+    }
+
+    Ok(layout)
+}
+
+/// Brings the caller's header in line with the layout `elf_update` chose,
+/// as libelf does: offsets, counts and sizes are the ones written.
+fn refresh_ehdr(ehdr: &mut WriteEhdr, layout: &FileLayout) {
+    let e = Endianness::default();
+    // Counts that don't fit are in the null section header instead
+    let shnum = if layout.shnum >= SHN_LORESERVE as usize {
+        0
+    } else {
+        layout.shnum as u16
+    };
+    let shstrndx = if layout.shstrndx >= SHN_LORESERVE as usize {
+        SHN_XINDEX
+    } else {
+        layout.shstrndx as u16
+    };
+    match ehdr {
+        WriteEhdr::Elf32(h) => {
+            h.e_version.set(e, EV_CURRENT.into());
+            h.e_phoff.set(e, 0);
+            h.e_shoff.set(e, layout.shoff as u32);
+            h.e_ehsize.set(e, size_of::<Elf32_Ehdr>() as u16);
+            h.e_phentsize.set(e, 0);
+            h.e_phnum.set(e, 0);
+            h.e_shentsize.set(e, size_of::<Elf32_Shdr>() as u16);
+            h.e_shnum.set(e, shnum);
+            h.e_shstrndx.set(e, shstrndx);
+        }
+        WriteEhdr::Elf64(h) => {
+            h.e_version.set(e, EV_CURRENT.into());
+            h.e_phoff.set(e, 0);
+            h.e_shoff.set(e, layout.shoff as u64);
+            h.e_ehsize.set(e, size_of::<Elf64_Ehdr>() as u16);
+            h.e_phentsize.set(e, 0);
+            h.e_phnum.set(e, 0);
+            h.e_shentsize.set(e, size_of::<Elf64_Shdr>() as u16);
+            h.e_shnum.set(e, shnum);
+            h.e_shstrndx.set(e, shstrndx);
+        }
+    }
+}
+
+fn clear_dirty(elf: &mut Elf) {
+    elf.flags.fetch_and(!ELF_F_DIRTY, Ordering::Relaxed);
+    elf.ehdr_flags.fetch_and(!ELF_F_DIRTY, Ordering::Relaxed);
+    elf.phdr_flags.fetch_and(!ELF_F_DIRTY, Ordering::Relaxed);
+    for scn_ptr in locked(&elf.section_handles).iter() {
+        let scn = unsafe { &mut **scn_ptr };
+        scn.flags.fetch_and(!ELF_F_DIRTY, Ordering::Relaxed);
+        scn.shdr_flags.fetch_and(!ELF_F_DIRTY, Ordering::Relaxed);
+        for data in scn.data_chain() {
+            data.flags.fetch_and(!ELF_F_DIRTY, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Updates a descriptor that was read from a file or memory in place: the
+/// data of each dirty section is written over its contents in the image,
+/// and from there to the file for `ELF_C_RDWR`. Sections cannot grow.
+fn update_image(elf: &mut Elf, cmd: ElfCmd) -> Result<usize, ElfError> {
     match cmd {
-        ELF_C_NULL => match writer.obj.write() {
-            Ok(bytes) => bytes.len() as i64,
-            Err(_) => {
-                set_error("failed to compute layout");
-                -1
+        ELF_C_NULL => return Ok(elf.data_len),
+        ELF_C_WRITE => {}
+        _ => return Err(ELF_E_INVALID_CMD),
+    }
+    if !matches!(elf.cmd, ELF_C_RDWR | ELF_C_RDWR_MMAP) {
+        return Err(ELF_E_UPDATE_RO);
+    }
+    if !elf.ensure_parsed() {
+        return Err(get_error());
+    }
+
+    let file = elf
+        .with_parsed(|p| (p.is_elf64(), p.endianness()))
+        .ok_or(ELF_E_INVALID_ELF)?;
+    let rewrite_all = elf.flag(ELF_F_DIRTY);
+    let permissive = elf.flag(ELF_F_PERMISSIVE);
+    let layout = elf.flag(ELF_F_LAYOUT);
+    let fill = FILL_BYTE.load(Ordering::Relaxed);
+
+    let mut updates = Vec::new();
+    for &scn_ptr in locked(&elf.section_handles).iter() {
+        // The descriptor is ours alone for the update
+        let scn = unsafe { &mut *scn_ptr };
+        let dirty = rewrite_all
+            || scn.flags.load(Ordering::Relaxed) & ELF_F_DIRTY != 0
+            || scn
+                .data_chain()
+                .any(|d| d.flags.load(Ordering::Relaxed) & ELF_F_DIRTY != 0);
+        if !dirty || !scn.data_read {
+            continue;
+        }
+        let shdr = elf
+            .with_parsed(|p| p.section_header(scn.index))
+            .flatten()
+            .ok_or(ELF_E_INVALID_SECTION)?;
+        if shdr.sh_type.get(NativeEndian) == SHT_NOBITS {
+            continue;
+        }
+
+        let (bytes, placement) = assemble_section(scn, file, layout, permissive, fill)?;
+        if bytes.len() as u64 > shdr.sh_size.get(NativeEndian) {
+            return Err(ELF_E_SECTION_TOO_SMALL);
+        }
+        if !layout {
+            record_offsets(scn, &placement);
+        }
+        updates.push((shdr.sh_offset.get(NativeEndian) as usize, bytes));
+    }
+
+    let image = elf.image_mut().ok_or(ELF_E_UPDATE_RO)?;
+    for (offset, bytes) in &updates {
+        let dst = image
+            .get_mut(*offset..offset.saturating_add(bytes.len()))
+            .ok_or(ELF_E_INVALID_OFFSET)?;
+        dst.copy_from_slice(bytes);
+    }
+
+    if let Some(mmap) = elf.mmap.as_ref() {
+        mmap.flush().map_err(|_| ELF_E_WRITE_ERROR)?;
+    } else if elf.fd >= 0 {
+        // The caller keeps ownership of the descriptor
+        let file = ManuallyDrop::new(unsafe { std::fs::File::from_raw_fd(elf.fd) });
+        for (offset, bytes) in &updates {
+            file.write_all_at(bytes, *offset as u64)
+                .map_err(|_| ELF_E_WRITE_ERROR)?;
+        }
+    }
+
+    clear_dirty(elf);
+    Ok(elf.data_len)
+}
+
+#[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn elf_update(elf: *mut Elf, cmd: ElfCmd) -> i64 {
+    guard(-1, || {
+        if elf.is_null() {
+            set_error(ELF_E_INVALID_OPERAND);
+            return -1;
+        }
+
+        let elf_ref = unsafe { &mut *elf };
+
+        let writer = match elf_ref.writer.as_mut() {
+            Some(w) => w,
+            None if !elf_ref.data.is_null() => {
+                return match update_image(elf_ref, cmd) {
+                    Ok(len) => len as i64,
+                    Err(e) => {
+                        set_error(e);
+                        -1
+                    }
+                };
             }
-        },
-        ELF_C_WRITE => {
-            if elf_ref.fd < 0 {
-                set_error("no file descriptor for write");
+            None => {
+                set_error(ELF_E_WRONG_ORDER_EHDR);
                 return -1;
             }
+        };
 
-            match writer.obj.write() {
-                Ok(bytes) => {
-                    let mut f = unsafe { std::fs::File::from_raw_fd(elf_ref.fd) };
-                    if let Err(e) = f.write_all(&bytes) {
-                        set_error(&format!("write failed: {e:?}"));
+        let Some(ehdr) = writer.ehdr.as_mut() else {
+            set_error(ELF_E_WRONG_ORDER_EHDR);
+            return -1;
+        };
+        let file = match complete_ident(ehdr) {
+            Ok(file) => file,
+            Err(e) => {
+                set_error(e);
+                return -1;
+            }
+        };
+        let header = ehdr.get();
+
+        let flags = elf_ref.flags.load(Ordering::Relaxed);
+        let rewrite_all = flags & ELF_F_DIRTY != 0;
+        let permissive = flags & ELF_F_PERMISSIVE != 0;
+        let layout = flags & ELF_F_LAYOUT != 0;
+        let fill = FILL_BYTE.load(Ordering::Relaxed);
+
+        let handles = locked(&elf_ref.section_handles);
+        let mut scns = vec![None; writer.sections.len()];
+        for &scn_ptr in handles.iter() {
+            // The descriptor is ours alone for the update
+            let scn = unsafe { &mut *scn_ptr };
+            let Some(slot) = scn.index.checked_sub(1).filter(|&i| i < scns.len()) else {
+                continue;
+            };
+
+            let dirty = rewrite_all
+                || scn.flags.load(Ordering::Relaxed) & ELF_F_DIRTY != 0
+                || scn
+                    .data_chain()
+                    .any(|d| d.flags.load(Ordering::Relaxed) & ELF_F_DIRTY != 0);
+            if dirty {
+                match place_section(scn, layout, permissive) {
+                    Ok(placement) => {
+                        if !layout {
+                            record_offsets(scn, &placement);
+                        }
+                        writer.sections[slot] = WriteSection {
+                            size: placement.size,
+                            align: placement.align,
+                        };
+                    }
+                    Err(e) => {
+                        set_error(e);
                         return -1;
-                    };
-                    bytes.len() as i64
+                    }
                 }
-                Err(_) => {
-                    set_error("failed to write ELF");
-                    -1
+            }
+            scns[slot] = Some(&*scn);
+        }
+
+        // Every section is addressable on its own; together they must be too
+        let total = writer.sections.iter().try_fold(0usize, |total, s| {
+            total
+                .checked_add(s.size)?
+                .checked_add(s.align)
+                .filter(|&total| total <= isize::MAX as usize)
+        });
+        if total.is_none() {
+            set_error(ELF_E_NOMEM);
+            return -1;
+        }
+
+        let shdrs: Vec<GElf_Shdr> = scns
+            .iter()
+            .map(|scn| match scn.and_then(|s| s.shdr.as_ref()) {
+                Some(shdr) => shdr.get(),
+                None => unsafe { std::mem::zeroed() },
+            })
+            .collect();
+        let emitted = match cmd {
+            ELF_C_NULL => emit(&mut Vec::new(), writer, &shdrs, None, &header, file, fill),
+            ELF_C_WRITE if elf_ref.fd < 0 => Err(ELF_E_FD_DISABLED),
+            ELF_C_WRITE => {
+                // The caller keeps ownership of the descriptor
+                let f = ManuallyDrop::new(unsafe { std::fs::File::from_raw_fd(elf_ref.fd) });
+                let target = match elf_ref.cmd {
+                    ELF_C_WRITE_MMAP => Target::Map(&f, None),
+                    _ => Target::File(&f),
+                };
+                let mut buffer = StreamBuffer::new(target, fill);
+                let emitted = emit(
+                    &mut buffer,
+                    writer,
+                    &shdrs,
+                    Some(&scns),
+                    &header,
+                    file,
+                    fill,
+                );
+                let finished = buffer.finish();
+                match emitted {
+                    Ok(layout) if finished.is_ok() => Ok(layout),
+                    Ok(_) => Err(ELF_E_WRITE_ERROR),
+                    Err(e) => Err(e),
                 }
             }
+            _ => Err(ELF_E_INVALID_CMD),
+        };
+        drop(handles);
+
+        let layout = match emitted {
+            Ok(layout) => layout,
+            Err(e) => {
+                set_error(e);
+                return -1;
+            }
+        };
+        if let Some(ehdr) = writer.ehdr.as_mut() {
+            refresh_ehdr(ehdr, &layout);
+        }
+        for &scn_ptr in locked(&elf_ref.section_handles).iter() {
+            let scn = unsafe { &mut *scn_ptr };
+            let Some(slot) = scn.index.checked_sub(1) else {
+                continue;
+            };
+            if let (Some(shdr), Some(section)) = (scn.shdr.as_mut(), writer.sections.get(slot)) {
+                shdr.place(layout.offsets[slot], section.size, section.align);
+            }
         }
-        _ => {
-            set_error("unsupported command");
-            -1
+        if cmd == ELF_C_WRITE {
+            clear_dirty(elf_ref);
         }
-    }
+        layout.len as i64
+    })
 }
@@ -1,3 +1,4 @@
+mod buffer;
 mod elf_api;
 
 pub use elf_api::*;
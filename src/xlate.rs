@@ -0,0 +1,228 @@
+use crate::types::*;
+use object::Endianness;
+
+// Field widths of one record, in declaration order. The memory and file
+// representations only differ in byte order, so translating is swapping
+// each field in place.
+const EHDR32: &[usize] = &[
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 2, 2, 4, 4, 4, 4, 4, 2, 2, 2, 2, 2, 2,
+];
+const EHDR64: &[usize] = &[
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 2, 2, 4, 8, 8, 8, 4, 2, 2, 2, 2, 2, 2,
+];
+const SHDR32: &[usize] = &[4, 4, 4, 4, 4, 4, 4, 4, 4, 4];
+const SHDR64: &[usize] = &[4, 4, 8, 8, 8, 8, 4, 4, 8, 8];
+const PHDR32: &[usize] = &[4, 4, 4, 4, 4, 4, 4, 4];
+const PHDR64: &[usize] = &[4, 4, 8, 8, 8, 8, 8, 8];
+const SYM32: &[usize] = &[4, 4, 4, 1, 1, 2];
+const SYM64: &[usize] = &[4, 1, 1, 2, 8, 8];
+// The last field of the move records is trailing padding
+const MOVE32: &[usize] = &[8, 4, 4, 2, 2, 4];
+const MOVE64: &[usize] = &[8, 8, 8, 2, 2, 4];
+const CHDR32: &[usize] = &[4, 4, 4];
+const CHDR64: &[usize] = &[4, 4, 8, 8];
+const VDEF: &[usize] = &[2, 2, 2, 2, 4, 4, 4];
+const VDAUX: &[usize] = &[4, 4];
+const VNEED: &[usize] = &[2, 2, 4, 4, 4];
+const VNAUX: &[usize] = &[4, 2, 2, 4, 4];
+const NHDR: &[usize] = &[4, 4, 4];
+const SYMINFO: &[usize] = &[2, 2];
+const LIB: &[usize] = &[4, 4, 4, 4, 4];
+
+fn layout(ty: ElfType, elf64: bool) -> &'static [usize] {
+    let addr: &[usize] = if elf64 { &[8] } else { &[4] };
+    let pair: &[usize] = if elf64 { &[8, 8] } else { &[4, 4] };
+    let triple: &[usize] = if elf64 { &[8, 8, 8] } else { &[4, 4, 4] };
+    match ty {
+        ELF_T_ADDR | ELF_T_OFF | ELF_T_RELR => addr,
+        ELF_T_HALF => &[2],
+        ELF_T_WORD | ELF_T_SWORD => &[4],
+        ELF_T_XWORD | ELF_T_SXWORD => &[8],
+        ELF_T_DYN | ELF_T_REL | ELF_T_AUXV => pair,
+        ELF_T_RELA => triple,
+        ELF_T_EHDR if elf64 => EHDR64,
+        ELF_T_EHDR => EHDR32,
+        ELF_T_SHDR if elf64 => SHDR64,
+        ELF_T_SHDR => SHDR32,
+        ELF_T_PHDR if elf64 => PHDR64,
+        ELF_T_PHDR => PHDR32,
+        ELF_T_SYM if elf64 => SYM64,
+        ELF_T_SYM => SYM32,
+        ELF_T_MOVE if elf64 => MOVE64,
+        ELF_T_MOVE => MOVE32,
+        ELF_T_CHDR if elf64 => CHDR64,
+        ELF_T_CHDR => CHDR32,
+        ELF_T_VDEF => VDEF,
+        ELF_T_VDAUX => VDAUX,
+        ELF_T_VNEED => VNEED,
+        ELF_T_VNAUX => VNAUX,
+        ELF_T_NHDR | ELF_T_NHDR8 => NHDR,
+        ELF_T_SYMINFO => SYMINFO,
+        ELF_T_LIB => LIB,
+        ELF_T_GNUHASH => &[4],
+        _ => &[1],
+    }
+}
+
+/// Alignment the memory representation of `ty` needs.
+pub fn type_align(ty: ElfType, elf64: bool) -> usize {
+    match ty {
+        ELF_T_NHDR8 => 8,
+        ELF_T_GNUHASH => {
+            if elf64 {
+                8
+            } else {
+                4
+            }
+        }
+        _ => layout(ty, elf64).iter().copied().max().unwrap_or(1),
+    }
+}
+
+/// Size of one record of `ty` if data of that type is an array of records
+/// of the same size, which can be translated a piece at a time.
+pub fn record_size(ty: ElfType, elf64: bool) -> Option<usize> {
+    match ty {
+        ELF_T_VDEF | ELF_T_VNEED | ELF_T_NHDR | ELF_T_NHDR8 | ELF_T_GNUHASH => None,
+        _ => Some(layout(ty, elf64).iter().sum()),
+    }
+}
+
+fn swap_record(buf: &mut [u8], fields: &[usize]) {
+    let mut pos = 0;
+    for &width in fields {
+        if pos + width > buf.len() {
+            break;
+        }
+        buf[pos..pos + width].reverse();
+        pos += width;
+    }
+}
+
+fn swap_records(buf: &mut [u8], fields: &[usize]) {
+    let size: usize = fields.iter().sum();
+    for record in buf.chunks_exact_mut(size) {
+        swap_record(record, fields);
+    }
+}
+
+fn read_u32(buf: &[u8], pos: usize, native: bool) -> Option<usize> {
+    let bytes: [u8; 4] = buf.get(pos..pos + 4)?.try_into().ok()?;
+    let value = if native {
+        u32::from_ne_bytes(bytes)
+    } else {
+        u32::from_ne_bytes(bytes).swap_bytes()
+    };
+    Some(value as usize)
+}
+
+/// Walks a chain of `Verdef`/`Verneed` entries, each followed by its own
+/// chain of aux entries. `aux_at`/`next_at` locate the offset fields.
+fn swap_version_chain(
+    buf: &mut [u8],
+    head: &[usize],
+    aux: &[usize],
+    cnt_at: usize,
+    aux_at: usize,
+    next_at: usize,
+    to_memory: bool,
+) {
+    let head_size: usize = head.iter().sum();
+    let aux_size: usize = aux.iter().sum();
+    let aux_next_at = aux_size - 4;
+    let mut pos = 0usize;
+    loop {
+        if pos + head_size > buf.len() {
+            return;
+        }
+        // Offsets must be read in memory order: after swapping when
+        // decoding, before swapping when encoding
+        if to_memory {
+            swap_record(&mut buf[pos..pos + head_size], head);
+        }
+        let cnt = u16::from_ne_bytes([buf[pos + cnt_at], buf[pos + cnt_at + 1]]);
+        let aux_off = read_u32(buf, pos + aux_at, true).unwrap_or(0);
+        let next = read_u32(buf, pos + next_at, true).unwrap_or(0);
+        if !to_memory {
+            swap_record(&mut buf[pos..pos + head_size], head);
+        }
+
+        let mut apos = pos.saturating_add(aux_off);
+        for _ in 0..cnt {
+            if aux_off == 0 || apos + aux_size > buf.len() {
+                break;
+            }
+            if to_memory {
+                swap_record(&mut buf[apos..apos + aux_size], aux);
+            }
+            let anext = read_u32(buf, apos + aux_next_at, true).unwrap_or(0);
+            if !to_memory {
+                swap_record(&mut buf[apos..apos + aux_size], aux);
+            }
+            if anext == 0 {
+                break;
+            }
+            apos = apos.saturating_add(anext);
+        }
+
+        if next == 0 {
+            return;
+        }
+        pos = pos.saturating_add(next);
+    }
+}
+
+fn swap_notes(buf: &mut [u8], align: usize, to_memory: bool) {
+    let mut pos = 0usize;
+    while pos + 12 <= buf.len() {
+        let namesz = read_u32(buf, pos, !to_memory).unwrap_or(0);
+        let descsz = read_u32(buf, pos + 4, !to_memory).unwrap_or(0);
+        swap_record(&mut buf[pos..pos + 12], NHDR);
+
+        let desc = (pos + 12).saturating_add(namesz).next_multiple_of(align);
+        pos = desc.saturating_add(descsz).next_multiple_of(align);
+    }
+}
+
+fn swap_gnu_hash(buf: &mut [u8], elf64: bool, to_memory: bool) {
+    if buf.len() < 16 {
+        return;
+    }
+    let bloom_size = read_u32(buf, 8, !to_memory).unwrap_or(0);
+    swap_records(&mut buf[..16], &[4, 4, 4, 4]);
+    let word = if elf64 { 8 } else { 4 };
+    let bloom_end = bloom_size
+        .saturating_mul(word)
+        .saturating_add(16)
+        .min(buf.len());
+    swap_records(&mut buf[16..bloom_end], &[word]);
+    swap_records(&mut buf[bloom_end..], &[4]);
+}
+
+fn swap(buf: &mut [u8], ty: ElfType, elf64: bool, to_memory: bool) {
+    match ty {
+        ELF_T_BYTE => {}
+        ELF_T_VDEF => swap_version_chain(buf, VDEF, VDAUX, 6, 12, 16, to_memory),
+        ELF_T_VNEED => swap_version_chain(buf, VNEED, VNAUX, 2, 8, 12, to_memory),
+        ELF_T_NHDR => swap_notes(buf, 4, to_memory),
+        ELF_T_NHDR8 => swap_notes(buf, 8, to_memory),
+        ELF_T_GNUHASH => swap_gnu_hash(buf, elf64, to_memory),
+        _ => swap_records(buf, layout(ty, elf64)),
+    }
+}
+
+/// Converts `buf` from the file representation in `encoding` to the memory
+/// representation, like `elf_xlatetom`.
+pub fn xlatetom(buf: &mut [u8], ty: ElfType, elf64: bool, encoding: Endianness) {
+    if encoding != Endianness::default() {
+        swap(buf, ty, elf64, true);
+    }
+}
+
+/// Converts `buf` from the memory representation to the file representation
+/// in `encoding`, like `elf_xlatetof`.
+pub fn xlatetof(buf: &mut [u8], ty: ElfType, elf64: bool, encoding: Endianness) {
+    if encoding != Endianness::default() {
+        swap(buf, ty, elf64, false);
+    }
+}